@@ -2,8 +2,13 @@ use aes_gcm::{
     Aes256Gcm, Nonce as AesNonce,
     aead::{Aead, KeyInit, Payload},
 };
+use argon2::{Algorithm, Argon2, Params, Version};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
 use sha2::{Digest, Sha256};
 use std::fmt;
+use std::time::Duration;
+use x25519_dalek::{PublicKey as ExchangeKey, StaticSecret};
 
 pub const FRAME_MAGIC: [u8; 4] = *b"SCB1";
 pub const FRAME_HEADER_BYTES: usize = 8;
@@ -18,55 +23,333 @@ pub const MAX_ACK_BYTES: usize = 4096;
 pub const MAX_DATA_ACK_BYTES: usize = MAX_FRAME_BYTES;
 pub const NONCE_BYTES: usize = 12;
 pub const CHALLENGE_BYTES: usize = 32;
+/// The protocol revision this build speaks.  Revision 1 is the daemon whose
+/// hello is nothing but the challenge; it is never sent, only inferred.
+pub const PROTOCOL_VERSION: u16 = 2;
+/// How long a daemon keeps a session open between requests.  A client stops
+/// reusing a session well before this, so a request never races the close.
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// The most text one chunk of a streamed transfer carries.  Chunks end on a
+/// character boundary, so a chunk may carry up to three bytes less.
+pub const CHUNK_BYTES: usize = 1024 * 1024;
+/// How much text a daemon assembles from one streamed Set unless it is
+/// configured otherwise.
+pub const DEFAULT_MAX_STREAM_BYTES: u64 = 256 * 1024 * 1024;
+/// The largest chunk frame payload, sealed or not.
+pub const MAX_CHUNK_PAYLOAD_BYTES: usize =
+    CHUNK_HEADER_BYTES + NONCE_BYTES + LENGTH_BYTES + CHUNK_BYTES + AEAD_TAG_BYTES;
+/// How long a subscription goes quiet before the daemon sends a heartbeat, so
+/// a client that hears nothing for twice as long knows the subscription is gone.
+pub const EVENT_HEARTBEAT: Duration = Duration::from_secs(15);
+/// The most text one change event carries.  A larger change is reported by
+/// size and hash alone, and a Get fetches the text.
+pub const MAX_EVENT_TEXT_BYTES: usize = CHUNK_BYTES;
+/// The largest event frame payload.
+pub const MAX_EVENT_PAYLOAD_BYTES: usize =
+    EVENT_HEADER_BYTES + NONCE_BYTES + LENGTH_BYTES + MAX_EVENT_BODY_BYTES + AEAD_TAG_BYTES;
+pub const HASH_BYTES: usize = 32;
+/// The most entries a daemon keeps in its history, and so the most one list or
+/// search reply carries.  That many still fit a status-sized ack.
+pub const MAX_HISTORY_ENTRIES: usize = 64;
+/// How many named slots a daemon holds at once, which is also how many one
+/// slot list can carry.
+pub const MAX_SLOTS: usize = 64;
+/// The longest slot name, in bytes.
+pub const MAX_SLOT_NAME_BYTES: usize = 32;
+/// The most detail codes one Status report counts, and the longest any string
+/// in it may be.  A daemon has fewer details than this, and a report of that
+/// many still fits a status-sized ack.
+pub const MAX_STATUS_DETAILS: usize = 40;
+pub const MAX_STATUS_NAME_BYTES: usize = 64;
+/// The most pending expiries one Status report lists: one per selection.
+pub const MAX_STATUS_EXPIRIES: usize = 2;
+/// The longest an expiring Set may leave its text on the clipboard, in
+/// seconds.
+pub const MAX_EXPIRY_SECONDS: u32 = 24 * 60 * 60;
+/// The most text one slot holds.  A slot is a snippet passed between editors,
+/// not a second clipboard, and it always fits one Set and one data ack.
+pub const MAX_SLOT_TEXT_BYTES: usize = CHUNK_BYTES;
 
 pub type Nonce = [u8; NONCE_BYTES];
 pub type Challenge = [u8; CHALLENGE_BYTES];
+pub type Transcript = [u8; TRANSCRIPT_BYTES];
+pub type ContentHash = [u8; HASH_BYTES];
 
 const KEY_BYTES: usize = 32;
 const LENGTH_BYTES: usize = 4;
 const AEAD_TAG_BYTES: usize = 16;
+const TRANSCRIPT_BYTES: usize = 32;
 
 const TAG_PING: u8 = 0x01;
 const TAG_SET: u8 = 0x02;
 const TAG_LEGACY: u8 = 0x03;
 const TAG_GET: u8 = 0x04;
+const TAG_SET_SELECTION: u8 = 0x05;
+const TAG_SET_STREAM: u8 = 0x06;
+const TAG_GET_STREAM: u8 = 0x07;
+const TAG_COMPRESSED: u8 = 0x08;
+const TAG_CLEAR: u8 = 0x09;
+const TAG_SUBSCRIBE: u8 = 0x0a;
+const TAG_HISTORY_LIST: u8 = 0x0b;
+const TAG_HISTORY_GET: u8 = 0x0c;
+const TAG_HISTORY_SEARCH: u8 = 0x0d;
+// Request tags only ever follow TAG_REQUEST_PLAIN or sit inside a seal, so the
+// run may continue into the values the frame tags below use: TAG_SLOT_LIST,
+// TAG_STATUS and TAG_APPEND share 0x10 to 0x12 with TAG_SERVER_HELLO,
+// TAG_CLIENT_HELLO and TAG_DESCRIBE.
+const TAG_SLOT_SET: u8 = 0x0e;
+const TAG_SLOT_GET: u8 = 0x0f;
+const TAG_SLOT_LIST: u8 = 0x10;
+const TAG_STATUS: u8 = 0x11;
+const TAG_APPEND: u8 = 0x12;
+const TAG_SET_VERSIONED: u8 = 0x13;
+const TAG_GET_VERSIONED: u8 = 0x14;
+const TAG_SET_EXPIRING: u8 = 0x15;
+const TAG_SET_SENSITIVE: u8 = 0x16;
+const TAG_OUTCOME_QUERY: u8 = 0x17;
+const TAG_SET_VERIFIED: u8 = 0x18;
+// Frame tags, the first byte of a frame's payload.  Each layer decodes only
+// its own tags, so the request tags above that take the same values are never
+// read where these are; moving either set would break the clients and daemons
+// already deployed.
 const TAG_SERVER_HELLO: u8 = 0x10;
+const TAG_CLIENT_HELLO: u8 = 0x11;
+const TAG_DESCRIBE: u8 = 0x12;
 const TAG_REQUEST_PLAIN: u8 = 0x20;
 const TAG_REQUEST_AUTHENTICATED: u8 = 0x21;
+const TAG_CHUNK_PLAIN: u8 = 0x22;
+const TAG_CHUNK_AUTHENTICATED: u8 = 0x23;
+const TAG_EVENT: u8 = 0x24;
+const TAG_REQUEST_KEYED: u8 = 0x25;
 const TAG_ACK_PLAIN: u8 = 0x30;
 const TAG_ACK_AUTHENTICATED: u8 = 0x31;
 const TAG_ACK_BODY: u8 = 0x01;
 const TAG_ACK_DATA_BODY: u8 = 0x02;
+const TAG_ACK_DEFLATED_BODY: u8 = 0x03;
+const TAG_ACK_HISTORY_BODY: u8 = 0x04;
+const TAG_ACK_SLOTS_BODY: u8 = 0x05;
+const TAG_ACK_REPORT_BODY: u8 = 0x06;
+const TAG_ACK_GENERATION_BODY: u8 = 0x07;
+const TAG_ACK_DATA_GENERATION_BODY: u8 = 0x08;
+const TAG_ACK_OUTCOME_BODY: u8 = 0x09;
+// Set on any of the body tags above when an [`AckCode`] follows the ok byte.
+const ACK_FLAG_CODE: u8 = 0x80;
 const TAG_NONE: u8 = 0x00;
 const TAG_SOME: u8 = 0x01;
 
+const HELLO_FLAG_TOKEN: u8 = 0x01;
+const HELLO_FLAG_KDF: u8 = 0x02;
+const HELLO_FLAG_HOST_KEY: u8 = 0x04;
+const HELLO_KNOWN_FLAGS: u8 = HELLO_FLAG_TOKEN | HELLO_FLAG_KDF | HELLO_FLAG_HOST_KEY;
+
+const KDF_HKDF: u8 = 0x01;
+const KDF_ARGON2ID: u8 = 0x02;
+
+const CLIENT_EXTENSION_SESSION: u8 = 0x01;
+const CLIENT_EXTENSION_KEY_EXCHANGE: u8 = 0x02;
+const CLIENT_EXTENSION_EPHEMERAL: u8 = 0x03;
+const CLIENT_EXTENSION_ACK_CODES: u8 = 0x04;
+
+const CHUNK_FLAG_LAST: u8 = 0x01;
+
+const SUBSCRIBE_FLAG_TEXT: u8 = 0x01;
+
+const COMPRESSED_FLAG_DEFLATE: u8 = 0x01;
+const COMPRESSED_PREFIX_BYTES: usize = 2;
+// Below this a body is sent as is: deflate's own framing would eat most of
+// what it saves, and every status ack is far smaller.
+const MIN_DEFLATE_BYTES: usize = 1024;
+// The fastest level.  The link is what is slow, and clipboard text — source,
+// logs, JSON — shrinks well even here, without holding up the daemon's single
+// thread on a ten-megabyte Get.
+const DEFLATE_LEVEL: u8 = 1;
+
 const SELECTION_CLIPBOARD: u8 = 0x00;
 const SELECTION_PRIMARY: u8 = 0x01;
 
+const ORIGIN_SET: u8 = 0x00;
+const ORIGIN_OBSERVED: u8 = 0x01;
+const OUTCOME_UNKNOWN: u8 = 0x00;
+const OUTCOME_RUNNING: u8 = 0x01;
+const OUTCOME_APPLIED: u8 = 0x02;
+const OUTCOME_FAILED: u8 = 0x03;
+
 const PLAIN_REQUEST_PREFIX_BYTES: usize = 1;
+const SELECTION_BYTES: usize = 1;
 const STRING_PREFIX_BYTES: usize = LENGTH_BYTES;
 const WIRE_PLAIN_PREFIX_BYTES: usize = 1;
 const WIRE_REQUEST_AUTH_OVERHEAD: usize = 1 + NONCE_BYTES + LENGTH_BYTES;
+const WIRE_REQUEST_KEYED_OVERHEAD: usize = WIRE_REQUEST_AUTH_OVERHEAD + KEY_ID_BYTES;
+const HELLO_BYTES: usize = 1 + CHALLENGE_BYTES;
+const HELLO_INFO_BYTES: usize = 2 + 8 + 4 + 1 + 8;
+const HELLO_KDF_BYTES: usize = 1 + KDF_SALT_BYTES;
+const OFFER_BYTES: usize = PUBLIC_KEY_BYTES + EXCHANGE_KEY_BYTES + SIGNATURE_BYTES;
+const CLIENT_HELLO_BYTES: usize = 1 + 2;
+const CLIENT_EXTENSION_HEADER_BYTES: usize = 1 + 2;
 const ACK_BODY_MIN_BYTES: usize = 3;
 const ACK_BODY_WITH_DETAIL_OVERHEAD: usize = 3 + LENGTH_BYTES;
 const ACK_BODY_WITH_TEXT_OVERHEAD: usize = ACK_BODY_WITH_DETAIL_OVERHEAD + LENGTH_BYTES;
 const WIRE_ACK_AUTH_OVERHEAD: usize = 1 + NONCE_BYTES + NONCE_BYTES + LENGTH_BYTES;
 const MIN_REQUEST_CIPHERTEXT_BYTES: usize = AEAD_TAG_BYTES + PLAIN_REQUEST_PREFIX_BYTES;
 const MIN_ACK_CIPHERTEXT_BYTES: usize = AEAD_TAG_BYTES + ACK_BODY_MIN_BYTES;
+const CHUNK_HEADER_BYTES: usize = 1 + LENGTH_BYTES + 1;
+// Every chunk but the last is full, short of at most a split character.  That
+// bounds how many chunks a capped stream can have, and so how long a sender can
+// hold a connection by trickling them.
+const MIN_CHUNK_BYTES: usize = CHUNK_BYTES - 3;
+const EVENT_HEADER_BYTES: usize = 1 + LENGTH_BYTES;
+const CHANGE_BYTES: usize = 1 + SELECTION_BYTES + 8 + HASH_BYTES + 1;
+const MAX_EVENT_BODY_BYTES: usize = CHANGE_BYTES + LENGTH_BYTES + MAX_EVENT_TEXT_BYTES;
+const HISTORY_ID_BYTES: usize = 8;
+const GENERATION_BYTES: usize = 8;
+const ACK_CODE_BYTES: usize = 2;
+// A versioned Set says whether it expects a generation, and which.
+const EXPECTED_GENERATION_BYTES: usize = 1 + GENERATION_BYTES;
+const EXPIRY_BYTES: usize = 4;
+// A sensitive Set says whether it expires, and when.
+const OPTIONAL_EXPIRY_BYTES: usize = 1 + EXPIRY_BYTES;
+const HISTORY_ENTRY_BYTES: usize = HISTORY_ID_BYTES + 8 + SELECTION_BYTES + 1 + 8;
+const SLOT_NAME_PREFIX_BYTES: usize = 1;
+const MAX_SLOT_ENTRY_BYTES: usize = SLOT_NAME_PREFIX_BYTES + MAX_SLOT_NAME_BYTES + 8 + 8;
+const STORE_RECORD_HEADER_BYTES: usize = HISTORY_ID_BYTES + 8 + SELECTION_BYTES + 1;
 
 /// Text size that is guaranteed to fit both a plain and an authenticated Set
-/// request.  Authentication adds a nonce, length and AEAD tag; bounding stdin
-/// by the outer frame size would accept text the protocol can never encode.
+/// request, whichever selection it addresses and whether or not it names its
+/// key.  Authentication adds a nonce, length and AEAD tag, naming the key adds
+/// its id, and a non-default selection adds one byte; bounding stdin by the
+/// outer frame size would accept text the protocol can never encode.
 pub const MAX_SET_TEXT_BYTES: usize = MAX_FRAME_BYTES
-    - WIRE_REQUEST_AUTH_OVERHEAD
+    - WIRE_REQUEST_KEYED_OVERHEAD
     - AEAD_TAG_BYTES
     - PLAIN_REQUEST_PREFIX_BYTES
+    - SELECTION_BYTES
     - STRING_PREFIX_BYTES;
 
+/// The longest separator an Append may put between the selection and its text.
+pub const MAX_SEPARATOR_BYTES: usize = u8::MAX as usize;
+const SEPARATOR_PREFIX_BYTES: usize = 1;
+
+/// The most text an Append under `separator` may carry: what a Set may, less
+/// the separator and its length byte.
+pub fn max_append_text_bytes(separator: &str) -> usize {
+    MAX_SET_TEXT_BYTES.saturating_sub(SEPARATOR_PREFIX_BYTES + separator.len())
+}
+
+/// The most text a versioned Set may carry: what a Set may, less the
+/// generation it may expect.
+pub const MAX_VERSIONED_SET_TEXT_BYTES: usize = MAX_SET_TEXT_BYTES - EXPECTED_GENERATION_BYTES;
+
+/// The most text an expiring Set may carry: what a Set may, less its expiry.
+pub const MAX_EXPIRING_SET_TEXT_BYTES: usize = MAX_SET_TEXT_BYTES - EXPIRY_BYTES;
+
+/// The most text a sensitive Set may carry: what a Set may, less the expiry it
+/// may name.
+pub const MAX_SENSITIVE_SET_TEXT_BYTES: usize = MAX_SET_TEXT_BYTES - OPTIONAL_EXPIRY_BYTES;
+
+// What a revision-1 daemon accepted: it had no selection byte to pay for, and
+// no key id.
+const REVISION_1_MAX_SET_TEXT_BYTES: usize = MAX_SET_TEXT_BYTES + KEY_ID_BYTES + SELECTION_BYTES;
+
 const REQUEST_KEY_DOMAIN: &[u8] = b"simpleclipboard/scb1/aes256gcm/request-key/v1\0";
 const ACK_KEY_DOMAIN: &[u8] = b"simpleclipboard/scb1/aes256gcm/ack-key/v1\0";
 const REQUEST_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/request/v1";
 const ACK_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/ack/v1";
+const SESSION_REQUEST_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/session-request/v1";
+const SESSION_ACK_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/session-ack/v1";
+const TRANSCRIPT_DOMAIN: &[u8] = b"simpleclipboard/scb1/session-transcript/v1\0";
+const REQUEST_CHUNK_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/request-chunk/v1";
+const REPLY_CHUNK_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/reply-chunk/v1";
+const EVENT_AAD: &[u8] = b"simpleclipboard/scb1/aes256gcm/event/v1";
+// The history store is not part of SCB1 and its key never meets the wire, so
+// it sits in a domain of its own: the same token yields unrelated keys.
+const KEY_ID_DOMAIN: &[u8] = b"simpleclipboard/scb1/key-id/v1\0";
+const STORE_KEY_DOMAIN: &[u8] = b"simpleclipboard/history-store/aes256gcm/key/v1\0";
+const HOST_OFFER_DOMAIN: &[u8] = b"simpleclipboard/scb1/ed25519/host-offer/v1\0";
+const CLIENT_OFFER_DOMAIN: &[u8] = b"simpleclipboard/scb1/ed25519/client-offer/v1\0";
+const EXCHANGE_SALT_DOMAIN: &[u8] = b"simpleclipboard/scb1/x25519/salt/v1\0";
+const EPHEMERAL_SALT_DOMAIN: &[u8] = b"simpleclipboard/scb1/x25519/token-salt/v1\0";
+const STORE_RECORD_AAD: &[u8] = b"simpleclipboard/history-store/aes256gcm/record/v1";
+// Argon2id as RFC 9106 and OWASP suggest for an interactive login: 19 MiB and
+// two passes, a few tens of milliseconds once per daemon start and once per
+// client process rather than once per request.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_PASSES: u32 = 2;
+const ARGON2_LANES: u32 = 1;
+
+/// How long the id a request names its key by is.
+pub const KEY_ID_BYTES: usize = 8;
+
+/// Names the keys a request was sealed with, for a daemon that holds several.
+/// It is a hash of the keys, so it tells an observer nothing about the token
+/// beyond which requests share one.
+pub type KeyId = [u8; KEY_ID_BYTES];
+
+/// How long an Ed25519 public key is, a daemon's host key or a client's.
+pub const PUBLIC_KEY_BYTES: usize = 32;
+const EXCHANGE_KEY_BYTES: usize = 32;
+const SIGNATURE_BYTES: usize = 64;
+
+/// How long the salt of a salted token key derivation is.
+pub const KDF_SALT_BYTES: usize = 16;
+
+pub type KdfSalt = [u8; KDF_SALT_BYTES];
+
+/// How both ends turn the shared token into the request and ack keys.
+///
+/// The daemon picks the scheme, and a fresh salt, when it starts and names
+/// them in its hello.  A hello that names none means the single SHA-256 every
+/// daemon used before there was a choice, so an older daemon keeps working
+/// with a newer client; an older client refuses a hello naming a scheme it
+/// does not know instead of deriving keys that cannot match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyDerivation {
+    /// One SHA-256 over the token.
+    Sha256,
+    /// HKDF-SHA256, for a token that is already a random key.
+    Hkdf(KdfSalt),
+    /// Argon2id stretching the token before HKDF-SHA256, for a passphrase.
+    Argon2id(KdfSalt),
+}
+
+impl KeyDerivation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Hkdf(_) => "hkdf",
+            Self::Argon2id(_) => "argon2id",
+        }
+    }
+
+    /// The scheme called `name`, salted with `salt` if it takes a salt.
+    pub fn parse(name: &str, salt: KdfSalt) -> Option<Self> {
+        match name {
+            "sha256" => Some(Self::Sha256),
+            "hkdf" => Some(Self::Hkdf(salt)),
+            "argon2id" => Some(Self::Argon2id(salt)),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        let (scheme, salt) = match self {
+            Self::Sha256 => return,
+            Self::Hkdf(salt) => (KDF_HKDF, salt),
+            Self::Argon2id(salt) => (KDF_ARGON2ID, salt),
+        };
+        output.push(scheme);
+        output.extend_from_slice(salt);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, ProtocolError> {
+        let scheme = decoder.read_u8()?;
+        let salt = decoder.read_array::<KDF_SALT_BYTES>()?;
+        match scheme {
+            KDF_HKDF => Ok(Self::Hkdf(salt)),
+            KDF_ARGON2ID => Ok(Self::Argon2id(salt)),
+            scheme => Err(ProtocolError::UnknownTag(scheme)),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct AuthKeys {
@@ -74,6 +357,35 @@ pub struct AuthKeys {
     ack: [u8; KEY_BYTES],
 }
 
+impl AuthKeys {
+    /// Whether both were derived from the same token.
+    ///
+    /// Every byte is compared whatever the first difference, so the time taken
+    /// says nothing about how much of a key matched.
+    pub fn same_token(&self, other: &Self) -> bool {
+        let difference = self
+            .request
+            .iter()
+            .chain(&self.ack)
+            .zip(other.request.iter().chain(&other.ack))
+            .fold(0_u8, |difference, (left, right)| {
+                difference | (left ^ right)
+            });
+        std::hint::black_box(difference) == 0
+    }
+
+    pub fn id(&self) -> KeyId {
+        let mut digest = Sha256::new();
+        digest.update(KEY_ID_DOMAIN);
+        digest.update(self.request);
+        digest.update(self.ack);
+        let digest: [u8; 32] = digest.finalize().into();
+        let mut id = [0_u8; KEY_ID_BYTES];
+        id.copy_from_slice(&digest[..KEY_ID_BYTES]);
+        id
+    }
+}
+
 impl Drop for AuthKeys {
     fn drop(&mut self) {
         self.request.fill(0);
@@ -81,6 +393,377 @@ impl Drop for AuthKeys {
     }
 }
 
+/// The key a daemon seals its on-disk history with.
+pub struct StoreKey([u8; KEY_BYTES]);
+
+impl Drop for StoreKey {
+    fn drop(&mut self) {
+        self.0.fill(0);
+    }
+}
+
+/// An Ed25519 public key: a daemon's host key, or the identity of one client.
+///
+/// Files and variables hold it as 64 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; PUBLIC_KEY_BYTES]);
+
+impl PublicKey {
+    pub fn parse(text: &str) -> Option<Self> {
+        parse_hex(text).map(Self)
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8; SIGNATURE_BYTES]) -> bool {
+        VerifyingKey::from_bytes(&self.0)
+            .and_then(|key| key.verify_strict(message, &Signature::from_bytes(signature)))
+            .is_ok()
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex(&self.0))
+    }
+}
+
+/// An Ed25519 key pair: a daemon's host key, or a client's identity.
+///
+/// A private key file holds its 32-byte seed as 64 hexadecimal digits.
+pub struct KeyPair(SigningKey);
+
+impl KeyPair {
+    pub fn generate() -> Result<Self, ProtocolError> {
+        let mut seed = [0_u8; 32];
+        getrandom::fill(&mut seed).map_err(|error| ProtocolError::Random(error.to_string()))?;
+        let pair = Self(SigningKey::from_bytes(&seed));
+        seed.fill(0);
+        Ok(pair)
+    }
+
+    /// The key pair a private key file holds, surrounding blanks aside.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut seed = parse_hex::<32>(text.trim())?;
+        let pair = Self(SigningKey::from_bytes(&seed));
+        seed.fill(0);
+        Some(pair)
+    }
+
+    /// What a private key file holds, a line of its own.
+    pub fn to_file_contents(&self) -> String {
+        let mut seed = self.0.to_bytes();
+        let contents = format!("{}\n", hex(&seed));
+        seed.fill(0);
+        contents
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key().to_bytes())
+    }
+
+    fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_BYTES] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+/// What a daemon with a host key says after its hello: the key, an X25519 key
+/// made for this connection alone, and the host key's signature over that and
+/// the challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostOffer {
+    pub host_key: PublicKey,
+    exchange: [u8; EXCHANGE_KEY_BYTES],
+    signature: [u8; SIGNATURE_BYTES],
+}
+
+/// A client's answer to a [`HostOffer`], carried in its [`ClientHello`]: its
+/// identity, an X25519 key of its own, and the identity's signature over both
+/// offers and the challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientOffer {
+    pub identity: PublicKey,
+    exchange: [u8; EXCHANGE_KEY_BYTES],
+    signature: [u8; SIGNATURE_BYTES],
+}
+
+impl HostOffer {
+    fn signed_message(challenge: &Challenge, exchange: &[u8; EXCHANGE_KEY_BYTES]) -> Vec<u8> {
+        [HOST_OFFER_DOMAIN, challenge, exchange].concat()
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.host_key.0);
+        output.extend_from_slice(&self.exchange);
+        output.extend_from_slice(&self.signature);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            host_key: PublicKey(decoder.read_array()?),
+            exchange: decoder.read_array()?,
+            signature: decoder.read_array()?,
+        })
+    }
+}
+
+impl ClientOffer {
+    fn signed_message(
+        challenge: &Challenge,
+        host: &HostOffer,
+        identity: &PublicKey,
+        exchange: &[u8; EXCHANGE_KEY_BYTES],
+    ) -> Vec<u8> {
+        [
+            CLIENT_OFFER_DOMAIN,
+            challenge,
+            &host.host_key.0,
+            &host.exchange,
+            &identity.0,
+            exchange,
+        ]
+        .concat()
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.identity.0);
+        output.extend_from_slice(&self.exchange);
+        output.extend_from_slice(&self.signature);
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            identity: PublicKey(decoder.read_array()?),
+            exchange: decoder.read_array()?,
+            signature: decoder.read_array()?,
+        })
+    }
+}
+
+/// The daemon's half of one connection's key exchange.
+///
+/// Both halves are fresh for every connection and forgotten with it, so the
+/// keys they agree on are too: a host or client key that leaks later opens
+/// nothing recorded before.
+pub struct HostExchange {
+    challenge: Challenge,
+    secret: StaticSecret,
+    offer: HostOffer,
+}
+
+impl HostExchange {
+    pub fn new(host: &KeyPair, challenge: &Challenge) -> Result<Self, ProtocolError> {
+        let secret = new_exchange_secret()?;
+        let exchange = ExchangeKey::from(&secret).to_bytes();
+        let offer = HostOffer {
+            host_key: host.public_key(),
+            exchange,
+            signature: host.sign(&HostOffer::signed_message(challenge, &exchange)),
+        };
+        Ok(Self {
+            challenge: *challenge,
+            secret,
+            offer,
+        })
+    }
+
+    pub fn offer(&self) -> HostOffer {
+        self.offer
+    }
+
+    /// The keys a client that answered with `offer` seals with, once its
+    /// signature over this connection checks out.  Whether that identity may
+    /// connect at all is for the daemon to decide.
+    pub fn accept(&self, offer: &ClientOffer) -> Result<AuthKeys, ProtocolError> {
+        let message = ClientOffer::signed_message(
+            &self.challenge,
+            &self.offer,
+            &offer.identity,
+            &offer.exchange,
+        );
+        if !offer.identity.verify(&message, &offer.signature) {
+            return Err(ProtocolError::AuthenticationFailed);
+        }
+        exchange_keys(
+            &self.challenge,
+            &self.offer,
+            offer,
+            &self.secret,
+            &offer.exchange,
+        )
+    }
+}
+
+/// The client's half: checks that the daemon proved it holds `host_key`, and
+/// answers its offer as `identity`.
+pub fn answer_host_offer(
+    identity: &KeyPair,
+    host_key: &PublicKey,
+    challenge: &Challenge,
+    host: &HostOffer,
+) -> Result<(AuthKeys, ClientOffer), ProtocolError> {
+    let message = HostOffer::signed_message(challenge, &host.exchange);
+    if host.host_key != *host_key || !host.host_key.verify(&message, &host.signature) {
+        return Err(ProtocolError::HostKey);
+    }
+    let secret = new_exchange_secret()?;
+    let exchange = ExchangeKey::from(&secret).to_bytes();
+    let public = identity.public_key();
+    let offer = ClientOffer {
+        identity: public,
+        exchange,
+        signature: identity.sign(&ClientOffer::signed_message(
+            challenge, host, &public, &exchange,
+        )),
+    };
+    let keys = exchange_keys(challenge, host, &offer, &secret, &host.exchange)?;
+    Ok((keys, offer))
+}
+
+/// The daemon's half of the exchange that keeps token-sealed requests secret
+/// after the token leaks.
+///
+/// Its public key is the hello's challenge, as random and as fresh as the
+/// challenge ever was, so a client that knows nothing of the exchange uses the
+/// hello exactly as before.  A client that answers it mixes the shared secret
+/// into its token's keys, so only a holder of the token can agree on the
+/// result, and nobody can once both halves are forgotten with the connection.
+pub struct EphemeralExchange {
+    secret: StaticSecret,
+    challenge: Challenge,
+}
+
+impl EphemeralExchange {
+    pub fn new() -> Result<Self, ProtocolError> {
+        let secret = new_exchange_secret()?;
+        let challenge = ExchangeKey::from(&secret).to_bytes();
+        Ok(Self { secret, challenge })
+    }
+
+    /// The challenge a hello must carry for a client to answer this exchange.
+    pub fn challenge(&self) -> Challenge {
+        self.challenge
+    }
+
+    /// What this exchange agrees on with a client that answered with `client`.
+    pub fn accept(
+        &self,
+        client: &[u8; EXCHANGE_KEY_BYTES],
+    ) -> Result<ForwardSecret, ProtocolError> {
+        ForwardSecret::agree(&self.challenge, client, &self.secret, client)
+    }
+}
+
+/// The client's half of an [`EphemeralExchange`]: the key its hello carries,
+/// and what it agrees on with the daemon that sent `challenge`.
+pub fn answer_ephemeral(
+    challenge: &Challenge,
+) -> Result<([u8; EXCHANGE_KEY_BYTES], ForwardSecret), ProtocolError> {
+    let secret = new_exchange_secret()?;
+    let client = ExchangeKey::from(&secret).to_bytes();
+    let agreed = ForwardSecret::agree(challenge, &client, &secret, challenge)?;
+    Ok((client, agreed))
+}
+
+/// The shared secret of one [`EphemeralExchange`], which turns a token's keys
+/// into keys for its connection alone.
+pub struct ForwardSecret {
+    salt: [u8; 32],
+    shared: [u8; 32],
+}
+
+impl ForwardSecret {
+    // As in `exchange_keys`, a peer key of low order is refused.
+    fn agree(
+        challenge: &Challenge,
+        client: &[u8; EXCHANGE_KEY_BYTES],
+        secret: &StaticSecret,
+        peer: &[u8; EXCHANGE_KEY_BYTES],
+    ) -> Result<Self, ProtocolError> {
+        let shared = secret.diffie_hellman(&ExchangeKey::from(*peer));
+        if !shared.was_contributory() {
+            return Err(ProtocolError::AuthenticationFailed);
+        }
+        let salt = Sha256::new()
+            .chain_update(EPHEMERAL_SALT_DOMAIN)
+            .chain_update(challenge)
+            .chain_update(client)
+            .finalize()
+            .into();
+        Ok(Self {
+            salt,
+            shared: shared.to_bytes(),
+        })
+    }
+
+    /// The keys to seal with on this connection in place of `token`'s.
+    pub fn keys(&self, token: &AuthKeys) -> Result<AuthKeys, ProtocolError> {
+        let mut secret = [0_u8; 3 * KEY_BYTES];
+        secret[..KEY_BYTES].copy_from_slice(&self.shared);
+        secret[KEY_BYTES..2 * KEY_BYTES].copy_from_slice(&token.request);
+        secret[2 * KEY_BYTES..].copy_from_slice(&token.ack);
+        let keys = expand_auth_keys(&self.salt, &secret);
+        secret.fill(0);
+        keys
+    }
+}
+
+impl Drop for ForwardSecret {
+    fn drop(&mut self) {
+        self.shared.fill(0);
+    }
+}
+
+fn new_exchange_secret() -> Result<StaticSecret, ProtocolError> {
+    let mut bytes = [0_u8; EXCHANGE_KEY_BYTES];
+    getrandom::fill(&mut bytes).map_err(|error| ProtocolError::Random(error.to_string()))?;
+    let secret = StaticSecret::from(bytes);
+    bytes.fill(0);
+    Ok(secret)
+}
+
+// Either end's secret meets the other's public half, `peer`.  The salt covers
+// both offers whole, so the keys belong to this exchange and no other.  A peer
+// key of low order would leave the shared secret known to everyone, and is
+// refused.
+fn exchange_keys(
+    challenge: &Challenge,
+    host: &HostOffer,
+    client: &ClientOffer,
+    secret: &StaticSecret,
+    peer: &[u8; EXCHANGE_KEY_BYTES],
+) -> Result<AuthKeys, ProtocolError> {
+    let shared = secret.diffie_hellman(&ExchangeKey::from(*peer));
+    if !shared.was_contributory() {
+        return Err(ProtocolError::AuthenticationFailed);
+    }
+    let mut transcript = Vec::with_capacity(2 * OFFER_BYTES);
+    host.encode(&mut transcript);
+    client.encode(&mut transcript);
+    let salt: [u8; 32] = Sha256::new()
+        .chain_update(EXCHANGE_SALT_DOMAIN)
+        .chain_update(challenge)
+        .chain_update(&transcript)
+        .finalize()
+        .into();
+    expand_auth_keys(&salt, shared.as_bytes())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let digits = text.as_bytes();
+    if digits.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0_u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(digits.chunks_exact(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(bytes)
+}
+
 /// Which of the platform's selections a request addresses.
 ///
 /// X11 and Wayland expose two independent buffers: CLIPBOARD, filled by an
@@ -127,893 +810,4228 @@ impl Selection {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum PlainRequest {
-    Ping,
-    Set { text: String },
-    Legacy { text: String },
-    Get { selection: Selection },
-}
+/// The name of one of the daemon's slots: 1 to [`MAX_SLOT_NAME_BYTES`] ASCII
+/// letters, digits, `-`, `_` or `.`, so that it reads the same in a shell, a
+/// Vim command and a log line.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SlotName(String);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum WireRequest {
-    Plain(PlainRequest),
-    Authenticated { nonce: Nonce, ciphertext: Vec<u8> },
-}
+impl SlotName {
+    pub fn parse(name: &str) -> Option<Self> {
+        let valid = (1..=MAX_SLOT_NAME_BYTES).contains(&name.len())
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'));
+        valid.then(|| Self(name.to_owned()))
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ServerHello {
-    pub challenge: Challenge,
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        // `parse` bounds the length well below a byte's range.
+        output.push(self.0.len() as u8);
+        output.extend_from_slice(self.0.as_bytes());
+    }
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, ProtocolError> {
+        let length = decoder.read_u8()? as usize;
+        let bytes = decoder.read_bytes(length)?;
+        std::str::from_utf8(bytes)
+            .ok()
+            .and_then(Self::parse)
+            .ok_or(ProtocolError::InvalidSlotName)
+    }
 }
 
-/// The daemon's answer to one request.
+/// One request, before any sealing.
 ///
-/// `text` is `Some` only for a Get reply, and it is what splits an ack into two
-/// wire shapes: a status body that is always tiny, and a data body that carries
-/// a clipboard.  Keeping them one type keeps the sealing, framing and response
-/// binding identical for both — only the length bound differs.
+/// A Set addressed to CLIPBOARD keeps the original `TAG_SET` layout, byte for
+/// byte, so every daemon since 0.2 still understands the common case.  Any
+/// other selection travels under its own tag with a selection byte, the way
+/// Get always has: a daemon that predates it refuses the tag outright instead
+/// of writing CLIPBOARD, which is what reusing `TAG_SET` would have done.
+///
+/// `SetStream` and `GetStream` carry no text themselves: it follows the request,
+/// or the ack, as a run of [`WireChunk`] frames on the same connection.
+/// `Subscribe` is answered by its ack and then by [`SealedEvent`] frames for as
+/// long as the connection stays open.  The history requests address no
+/// selection: an entry records its own.  The slot requests address a
+/// [`SlotName`] instead of a selection, and never reach the system clipboard.
+/// `Status` is answered with a [`StatusReport`] about the daemon itself.
+/// `Append` adds its text to the end of a selection, after `separator` unless
+/// the selection is empty.
+///
+/// `SetVersioned` and `GetVersioned` are a Set and a Get whose ack carries the
+/// selection's generation, which goes up each time the daemon sees it change.
+/// They travel under tags of their own because an older client could not read
+/// that ack.  A `SetVersioned` with `expected` writes only while the selection
+/// is still at that generation, and is otherwise refused with
+/// `generation_conflict`.
+///
+/// `SetExpiring` is a Set that the daemon takes back after `seconds`, between
+/// one and [`MAX_EXPIRY_SECONDS`]: it then clears the selection, but only if
+/// the selection still holds that text.
+///
+/// `SetSensitive` is a Set of a secret: the daemon offers it with the hints
+/// clipboard managers read as "do not record this", keeps it out of its own
+/// history, and takes it back after `seconds` if it names an expiry.
+///
+/// `OutcomeQuery` asks what became of an earlier authenticated write, by the
+/// nonce it was sealed under, and is answered with a [`WriteOutcome`].
+///
+/// `SetVerified` is a Set the daemon reads back once the selection has had a
+/// moment to settle, and refuses with `clipboard_overwritten` if another owner
+/// has already replaced the text.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Ack {
-    pub ok: bool,
-    pub detail: Option<String>,
-    pub text: Option<String>,
+pub enum PlainRequest {
+    Ping,
+    Set {
+        selection: Selection,
+        text: String,
+    },
+    Legacy {
+        text: String,
+    },
+    Get {
+        selection: Selection,
+    },
+    SetStream {
+        selection: Selection,
+    },
+    GetStream {
+        selection: Selection,
+    },
+    Clear {
+        selection: Selection,
+    },
+    Subscribe {
+        selection: Selection,
+        text: bool,
+    },
+    HistoryList,
+    HistoryGet {
+        id: u64,
+    },
+    HistorySearch {
+        query: String,
+    },
+    SlotSet {
+        name: SlotName,
+        text: String,
+    },
+    SlotGet {
+        name: SlotName,
+    },
+    SlotList,
+    Status,
+    Append {
+        selection: Selection,
+        separator: String,
+        text: String,
+    },
+    SetVersioned {
+        selection: Selection,
+        expected: Option<u64>,
+        text: String,
+    },
+    GetVersioned {
+        selection: Selection,
+    },
+    SetExpiring {
+        selection: Selection,
+        seconds: u32,
+        text: String,
+    },
+    SetSensitive {
+        selection: Selection,
+        seconds: Option<u32>,
+        text: String,
+    },
+    OutcomeQuery {
+        nonce: Nonce,
+    },
+    SetVerified {
+        selection: Selection,
+        text: String,
+    },
 }
 
-impl Ack {
-    pub fn status(ok: bool, detail: Option<String>) -> Self {
-        Self {
-            ok,
-            detail,
-            text: None,
+impl PlainRequest {
+    /// The capability a daemon must advertise to understand this request.
+    pub fn capability(&self) -> Capabilities {
+        match self {
+            Self::Ping => Capabilities::PING,
+            Self::Set {
+                selection: Selection::Clipboard,
+                ..
+            } => Capabilities::SET,
+            Self::Set { .. } => Capabilities::SET_SELECTION,
+            Self::Legacy { .. } => Capabilities::LEGACY,
+            Self::Get { .. } => Capabilities::GET,
+            Self::SetStream { .. } | Self::GetStream { .. } => Capabilities::STREAM,
+            Self::Clear { .. } => Capabilities::CLEAR,
+            Self::Subscribe { .. } => Capabilities::SUBSCRIBE,
+            Self::HistoryList | Self::HistoryGet { .. } | Self::HistorySearch { .. } => {
+                Capabilities::HISTORY
+            }
+            Self::SlotSet { .. } | Self::SlotGet { .. } | Self::SlotList => Capabilities::SLOTS,
+            Self::Status => Capabilities::STATUS,
+            Self::Append { .. } => Capabilities::APPEND,
+            Self::SetVersioned { .. } | Self::GetVersioned { .. } => Capabilities::GENERATION,
+            Self::SetExpiring { .. } => Capabilities::EXPIRY,
+            Self::SetSensitive { .. } => Capabilities::SENSITIVE,
+            Self::OutcomeQuery { .. } => Capabilities::OUTCOME,
+            Self::SetVerified { .. } => Capabilities::VERIFY,
         }
     }
 
-    pub fn data(text: String, detail: Option<String>) -> Self {
-        Self {
-            ok: true,
-            detail,
-            text: Some(text),
+    /// The clipboard text this request carries, if it carries any.  A search
+    /// query is not clipboard text, and the Set limit does not apply to it.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Self::Set { text, .. }
+            | Self::Legacy { text }
+            | Self::SlotSet { text, .. }
+            | Self::Append { text, .. }
+            | Self::SetVersioned { text, .. }
+            | Self::SetExpiring { text, .. }
+            | Self::SetSensitive { text, .. }
+            | Self::SetVerified { text, .. } => Some(text),
+            Self::Ping
+            | Self::Get { .. }
+            | Self::SetStream { .. }
+            | Self::GetStream { .. }
+            | Self::Clear { .. }
+            | Self::Subscribe { .. }
+            | Self::HistoryList
+            | Self::HistoryGet { .. }
+            | Self::HistorySearch { .. }
+            | Self::SlotGet { .. }
+            | Self::SlotList
+            | Self::Status
+            | Self::GetVersioned { .. }
+            | Self::OutcomeQuery { .. } => None,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum WireAck {
-    Plain(Ack),
+pub enum WireRequest {
+    Plain(PlainRequest),
+    /// `key` is `None` for a request that does not say which of the daemon's
+    /// tokens sealed it, which every daemon before [`Capabilities::KEY_ID`]
+    /// expects.
     Authenticated {
-        request_nonce: Nonce,
+        key: Option<KeyId>,
         nonce: Nonce,
         ciphertext: Vec<u8>,
     },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ProtocolError {
-    InvalidMagic,
-    InvalidLength(usize),
-    UnexpectedEof,
-    TrailingBytes,
-    UnknownTag(u8),
-    InvalidBoolean(u8),
-    InvalidUtf8,
-    AuthenticationFailed,
-    Random(String),
-    UnexpectedProtection,
-    ResponseBinding,
-}
-
-impl fmt::Display for ProtocolError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl WireRequest {
+    /// The same request, naming the keys it was sealed with.  Only a daemon
+    /// advertising [`Capabilities::KEY_ID`] reads a request that does.
+    pub fn identified(self, id: KeyId) -> Self {
         match self {
-            Self::InvalidMagic => f.write_str("invalid frame magic"),
-            Self::InvalidLength(length) => write!(f, "invalid frame length: {length}"),
-            Self::UnexpectedEof => f.write_str("payload is truncated"),
-            Self::TrailingBytes => f.write_str("trailing bytes in payload"),
-            Self::UnknownTag(tag) => write!(f, "unknown protocol tag: 0x{tag:02x}"),
-            Self::InvalidBoolean(value) => write!(f, "invalid boolean value: {value}"),
-            Self::InvalidUtf8 => f.write_str("string is not valid UTF-8"),
-            Self::AuthenticationFailed => f.write_str("authenticated message rejected"),
-            Self::Random(detail) => write!(f, "secure random generation failed: {detail}"),
-            Self::UnexpectedProtection => f.write_str("unexpected message protection mode"),
-            Self::ResponseBinding => f.write_str("response is not bound to this request"),
+            Self::Authenticated {
+                nonce, ciphertext, ..
+            } => Self::Authenticated {
+                key: Some(id),
+                nonce,
+                ciphertext,
+            },
+            plain => plain,
         }
     }
 }
 
-impl std::error::Error for ProtocolError {}
+/// The request kinds a daemon understands, one bit each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(u64);
 
-struct Decoder<'a> {
-    payload: &'a [u8],
-    position: usize,
-}
+impl Capabilities {
+    pub const PING: Self = Self(1 << 0);
+    pub const SET: Self = Self(1 << 1);
+    pub const LEGACY: Self = Self(1 << 2);
+    pub const GET: Self = Self(1 << 3);
+    pub const SET_SELECTION: Self = Self(1 << 4);
+    /// The connection may carry a [`ClientHello`] and then many requests.
+    pub const SESSION: Self = Self(1 << 5);
+    /// `SetStream` and `GetStream`, whose text travels in chunks.
+    pub const STREAM: Self = Self(1 << 6);
+    /// A sealed request may offer [`Compression::Deflate`].
+    pub const COMPRESSION: Self = Self(1 << 7);
+    pub const CLEAR: Self = Self(1 << 8);
+    /// `Subscribe`, answered with change events until the client goes away.
+    pub const SUBSCRIBE: Self = Self(1 << 9);
+    /// `HistoryList`, `HistoryGet` and `HistorySearch`.
+    pub const HISTORY: Self = Self(1 << 10);
+    /// `SlotSet`, `SlotGet` and `SlotList`.
+    pub const SLOTS: Self = Self(1 << 11);
+    /// A sealed request may name the keys it was sealed with.
+    pub const KEY_ID: Self = Self(1 << 12);
+    /// A client hello may answer the daemon's host key with a [`ClientOffer`].
+    pub const PUBLIC_KEY: Self = Self(1 << 13);
+    /// The challenge is the daemon's half of an [`EphemeralExchange`], which a
+    /// client hello may answer to seal with keys of this connection alone.
+    pub const FORWARD_SECRECY: Self = Self(1 << 14);
+    /// `Status`, answered with a [`StatusReport`].
+    pub const STATUS: Self = Self(1 << 15);
+    /// `Append`.
+    pub const APPEND: Self = Self(1 << 16);
+    /// `SetVersioned` and `GetVersioned`, whose acks carry a generation.
+    pub const GENERATION: Self = Self(1 << 17);
+    /// `SetExpiring`.
+    pub const EXPIRY: Self = Self(1 << 18);
+    /// `SetSensitive`.
+    pub const SENSITIVE: Self = Self(1 << 19);
+    /// A client hello may ask for every ack to carry an [`AckCode`].
+    pub const ACK_CODE: Self = Self(1 << 20);
+    /// `OutcomeQuery`, answered with a [`WriteOutcome`].
+    pub const OUTCOME: Self = Self(1 << 21);
+    /// `SetVerified`.
+    pub const VERIFY: Self = Self(1 << 22);
 
-impl<'a> Decoder<'a> {
-    fn new(payload: &'a [u8]) -> Self {
-        Self {
-            payload,
-            position: 0,
-        }
-    }
+    /// What a revision-1 daemon understands without saying so.
+    pub const REVISION_1: Self = Self(Self::PING.0 | Self::SET.0 | Self::LEGACY.0 | Self::GET.0);
+    /// Everything this build implements.
+    pub const ALL: Self = Self(
+        Self::REVISION_1.0
+            | Self::SET_SELECTION.0
+            | Self::SESSION.0
+            | Self::STREAM.0
+            | Self::COMPRESSION.0
+            | Self::CLEAR.0
+            | Self::SUBSCRIBE.0
+            | Self::HISTORY.0
+            | Self::SLOTS.0
+            | Self::KEY_ID.0
+            | Self::PUBLIC_KEY.0
+            | Self::FORWARD_SECRECY.0
+            | Self::STATUS.0
+            | Self::APPEND.0
+            | Self::GENERATION.0
+            | Self::EXPIRY.0
+            | Self::SENSITIVE.0
+            | Self::ACK_CODE.0
+            | Self::OUTCOME.0
+            | Self::VERIFY.0,
+    );
 
-    fn read_u8(&mut self) -> Result<u8, ProtocolError> {
-        let value = self
-            .payload
-            .get(self.position)
-            .copied()
-            .ok_or(ProtocolError::UnexpectedEof)?;
-        self.position += 1;
-        Ok(value)
+    pub const fn bits(self) -> u64 {
+        self.0
     }
 
-    fn read_u32(&mut self) -> Result<u32, ProtocolError> {
-        let bytes = self.read_array::<LENGTH_BYTES>()?;
-        Ok(u32::from_be_bytes(bytes))
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
     }
 
-    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
-        let bytes = self.read_bytes(N)?;
-        let mut output = [0_u8; N];
-        output.copy_from_slice(bytes);
-        Ok(output)
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
     }
+}
 
-    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], ProtocolError> {
-        let end = self
-            .position
-            .checked_add(length)
-            .ok_or(ProtocolError::InvalidLength(length))?;
-        let bytes = self
-            .payload
-            .get(self.position..end)
-            .ok_or(ProtocolError::UnexpectedEof)?;
-        self.position = end;
-        Ok(bytes)
-    }
+/// Whether a sealed request offers compression.
+///
+/// It is agreed one request at a time.  A client offers it only to a daemon
+/// advertising [`Capabilities::COMPRESSION`]; the offer lets the client deflate
+/// the request's own body, and lets the daemon deflate the ack that answers it
+/// and no other.  Either side still sends a body as is when deflating would
+/// not make it smaller.  Plaintext messages are never compressed: they only
+/// ever cross loopback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    Off,
+    Deflate,
+}
 
-    fn read_length_prefixed(
-        &mut self,
-        minimum: usize,
-        maximum: usize,
-    ) -> Result<&'a [u8], ProtocolError> {
-        let length = self.read_u32()? as usize;
-        if length < minimum || length > maximum {
-            return Err(ProtocolError::InvalidLength(length));
+/// What the daemon on the other end says about itself.
+///
+/// It travels in the clear ahead of any request, so it is advice rather than
+/// authority: it lets a client refuse early, before a byte of the request is
+/// sent, instead of failing ambiguously afterwards.  The daemon still enforces
+/// every limit itself, so a forged hello can only make a client refuse, or
+/// seal with keys the daemon does not hold and be refused by it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: u16,
+    pub capabilities: Capabilities,
+    pub max_text_bytes: u32,
+    /// `None` when the daemon predates the field and therefore did not say.
+    pub token_configured: Option<bool>,
+    /// The most text the daemon assembles from one streamed Set.
+    pub max_stream_bytes: u64,
+    /// How the daemon derives its keys from the token.
+    pub key_derivation: KeyDerivation,
+}
+
+impl ServerInfo {
+    /// The description this build gives of itself.
+    pub fn current(token_configured: bool) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::ALL,
+            max_text_bytes: MAX_SET_TEXT_BYTES as u32,
+            token_configured: Some(token_configured),
+            max_stream_bytes: DEFAULT_MAX_STREAM_BYTES,
+            key_derivation: KeyDerivation::Sha256,
         }
-        self.read_bytes(length)
     }
 
-    fn remaining(&self) -> &'a [u8] {
-        &self.payload[self.position..]
+    fn revision_1() -> Self {
+        Self {
+            version: 1,
+            capabilities: Capabilities::REVISION_1,
+            max_text_bytes: REVISION_1_MAX_SET_TEXT_BYTES as u32,
+            token_configured: None,
+            max_stream_bytes: 0,
+            key_derivation: KeyDerivation::Sha256,
+        }
     }
 
-    fn finish(self) -> Result<(), ProtocolError> {
-        if self.position == self.payload.len() {
-            Ok(())
-        } else {
-            Err(ProtocolError::TrailingBytes)
+    /// Why this daemon cannot carry `request`, in the daemon's own refusal
+    /// vocabulary, or `None` when nothing it advertised rules it out.
+    pub fn refusal(&self, request: &PlainRequest, authenticated: bool) -> Option<&'static str> {
+        if !self.capabilities.contains(request.capability()) {
+            return Some("request_unsupported");
+        }
+        if request
+            .text()
+            .is_some_and(|text| text.len() > self.max_text_bytes as usize)
+        {
+            return Some("text_too_large");
+        }
+        match (self.token_configured, authenticated) {
+            (Some(true), false) => Some("authentication_required"),
+            (Some(false), true) => Some("authentication_not_configured"),
+            _ => None,
         }
     }
 }
 
-pub fn derive_auth_keys(token: &str) -> AuthKeys {
-    AuthKeys {
-        request: derive_key(REQUEST_KEY_DOMAIN, token),
-        ack: derive_key(ACK_KEY_DOMAIN, token),
-    }
+/// The daemon's first frame on every connection, and its answer to a client's
+/// describe frame.
+///
+/// The first hello is always the bare challenge, byte for byte what a
+/// revision-1 daemon sends, so a client built before the description existed
+/// reads it as it always has.  A client that wants more sends a describe frame
+/// (see [`encode_describe_frame`]) in place of its first request, and the
+/// daemon answers with the same challenge followed by `info` and `host`.  A
+/// revision-1 daemon closes the connection on a describe frame instead.
+///
+/// `info` is `None` for a bare hello; [`ServerHello::info`] fills in what a
+/// revision-1 daemon implicitly supports.  `host` is the daemon's half of a
+/// key exchange, present when it has a host key; like the challenge, it
+/// belongs to this connection alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    pub challenge: Challenge,
+    pub info: Option<ServerInfo>,
+    pub host: Option<HostOffer>,
 }
 
-fn derive_key(domain: &[u8], token: &str) -> [u8; KEY_BYTES] {
-    let mut digest = Sha256::new();
-    digest.update(domain);
-    digest.update((token.len() as u64).to_be_bytes());
-    digest.update(token.as_bytes());
-    digest.finalize().into()
+impl ServerHello {
+    pub fn info(&self) -> ServerInfo {
+        self.info.unwrap_or_else(ServerInfo::revision_1)
+    }
+
+    /// The challenge alone, as every connection opens with.
+    pub fn bare(&self) -> Self {
+        Self {
+            challenge: self.challenge,
+            info: None,
+            host: None,
+        }
+    }
 }
 
-fn random_nonce() -> Result<Nonce, ProtocolError> {
-    let mut nonce = [0_u8; NONCE_BYTES];
-    getrandom::fill(&mut nonce).map_err(|error| ProtocolError::Random(error.to_string()))?;
-    Ok(nonce)
+/// The client's optional first frame, sent only to a daemon advertising
+/// [`Capabilities::SESSION`].
+///
+/// Sending one is what puts a connection in session mode: every request after
+/// it is bound to the hello exchange and to its own sequence number rather than
+/// to the bare challenge, and the daemon answers each one without closing.
+/// `session: false` keeps that binding for a single request; the daemon also
+/// falls back to that when it has no room for another open session, so a
+/// client finds out only by seeing the connection close after its ack.
+/// `offer` answers the daemon's [`HostOffer`], and is sent only to a daemon
+/// that made one.  `ephemeral` answers the daemon's [`EphemeralExchange`], and
+/// is sent only to a daemon advertising [`Capabilities::FORWARD_SECRECY`] by a
+/// client sealing with a token; a hello never carries both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientHello {
+    pub version: u16,
+    pub session: bool,
+    pub offer: Option<ClientOffer>,
+    pub ephemeral: Option<[u8; EXCHANGE_KEY_BYTES]>,
+    /// Whether every ack on the connection should carry an [`AckCode`].  Only
+    /// a daemon advertising [`Capabilities::ACK_CODE`] reads a hello that asks.
+    pub ack_codes: bool,
 }
 
-pub fn new_server_hello() -> Result<ServerHello, ProtocolError> {
-    let mut challenge = [0_u8; CHALLENGE_BYTES];
-    getrandom::fill(&mut challenge).map_err(|error| ProtocolError::Random(error.to_string()))?;
-    Ok(ServerHello { challenge })
+impl ClientHello {
+    pub fn session() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            session: true,
+            offer: None,
+            ephemeral: None,
+            ack_codes: false,
+        }
+    }
 }
 
-fn cipher(key: &[u8; KEY_BYTES]) -> Result<Aes256Gcm, ProtocolError> {
-    Aes256Gcm::new_from_slice(key).map_err(|_| ProtocolError::AuthenticationFailed)
+/// The state both ends keep for one session.
+///
+/// The transcript is a hash of both hello frames exactly as they crossed the
+/// wire, so the daemon's unauthenticated self-description is authenticated
+/// after the fact by the first request that opens.  The sequence number is
+/// never sent: each end counts, so a request replayed, dropped or reordered
+/// within the session fails to open instead of being carried out twice.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    transcript: Transcript,
+    sequence: u64,
 }
 
-fn checked_size(parts: &[usize], maximum: usize) -> Result<usize, ProtocolError> {
-    let mut total = 0_usize;
-    for part in parts {
-        total = total
-            .checked_add(*part)
-            .ok_or(ProtocolError::InvalidLength(usize::MAX))?;
+impl Session {
+    pub fn new(hello_payload: &[u8], client_hello_payload: &[u8]) -> Self {
+        let mut digest = Sha256::new();
+        digest.update(TRANSCRIPT_DOMAIN);
+        for payload in [hello_payload, client_hello_payload] {
+            digest.update((payload.len() as u64).to_be_bytes());
+            digest.update(payload);
+        }
+        Self {
+            transcript: digest.finalize().into(),
+            sequence: 0,
+        }
     }
-    if total == 0 || total > maximum {
-        Err(ProtocolError::InvalidLength(total))
-    } else {
-        Ok(total)
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
     }
-}
 
-fn append_length(output: &mut Vec<u8>, length: usize) -> Result<(), ProtocolError> {
-    let length = u32::try_from(length).map_err(|_| ProtocolError::InvalidLength(length))?;
-    output.extend_from_slice(&length.to_be_bytes());
-    Ok(())
+    /// Moves on to the next request, once the current one has been answered.
+    pub fn advance(&mut self) {
+        self.sequence += 1;
+    }
 }
 
-fn append_length_prefixed(output: &mut Vec<u8>, bytes: &[u8]) -> Result<(), ProtocolError> {
-    append_length(output, bytes.len())?;
-    output.extend_from_slice(bytes);
-    Ok(())
+/// What an authenticated message is bound to, besides its keys.
+#[derive(Debug, Clone, Copy)]
+pub enum Binding<'a> {
+    /// The only request on its connection, bound to the daemon's challenge.
+    Connection(&'a Challenge),
+    /// The current request of a session.
+    Session(&'a Session),
 }
 
-fn encode_plain_request(request: &PlainRequest) -> Result<Vec<u8>, ProtocolError> {
-    match request {
-        PlainRequest::Ping => Ok(vec![TAG_PING]),
-        PlainRequest::Get { selection } => Ok(vec![TAG_GET, selection.tag()]),
-        PlainRequest::Set { text } | PlainRequest::Legacy { text } => {
-            let length = checked_size(
-                &[PLAIN_REQUEST_PREFIX_BYTES, STRING_PREFIX_BYTES, text.len()],
-                MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
-            )?;
-            let mut output = Vec::with_capacity(length);
-            output.push(if matches!(request, PlainRequest::Set { .. }) {
-                TAG_SET
-            } else {
-                TAG_LEGACY
-            });
-            append_length_prefixed(&mut output, text.as_bytes())?;
-            Ok(output)
+impl Binding<'_> {
+    fn request_aad(&self) -> Vec<u8> {
+        match self {
+            Self::Connection(challenge) => request_aad(challenge),
+            Self::Session(session) => session_aad(SESSION_REQUEST_AAD, session, None),
         }
     }
-}
 
-fn decode_plain_request(payload: &[u8]) -> Result<PlainRequest, ProtocolError> {
-    checked_size(&[payload.len()], MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES)?;
-    let mut decoder = Decoder::new(payload);
-    let tag = decoder.read_u8()?;
-    let request = match tag {
-        TAG_PING => PlainRequest::Ping,
-        TAG_GET => PlainRequest::Get {
-            selection: Selection::from_tag(decoder.read_u8()?)?,
-        },
-        TAG_SET | TAG_LEGACY => {
-            let maximum = MAX_FRAME_BYTES
-                - WIRE_PLAIN_PREFIX_BYTES
-                - PLAIN_REQUEST_PREFIX_BYTES
-                - STRING_PREFIX_BYTES;
-            let bytes = decoder.read_length_prefixed(0, maximum)?;
-            let text = std::str::from_utf8(bytes)
-                .map_err(|_| ProtocolError::InvalidUtf8)?
-                .to_owned();
-            if tag == TAG_SET {
-                PlainRequest::Set { text }
-            } else {
-                PlainRequest::Legacy { text }
-            }
+    fn ack_aad(&self, request_nonce: &Nonce) -> Vec<u8> {
+        match self {
+            Self::Connection(challenge) => ack_aad(challenge, request_nonce),
+            Self::Session(session) => session_aad(SESSION_ACK_AAD, session, Some(request_nonce)),
         }
-        _ => return Err(ProtocolError::UnknownTag(tag)),
-    };
-    decoder.finish()?;
-    Ok(request)
-}
+    }
 
-// How big this particular ack is allowed to get: a data body carries the
-// clipboard, everything else is a fixed-vocabulary status line.
-fn ack_body_limit(ack: &Ack) -> usize {
-    if ack.text.is_some() {
-        MAX_DATA_ACK_BYTES
-    } else {
-        MAX_ACK_BYTES
+    pub fn seal_request(
+        &self,
+        keys: &AuthKeys,
+        request: &PlainRequest,
+        compression: Compression,
+    ) -> Result<(WireRequest, Nonce), ProtocolError> {
+        let aad = self.request_aad();
+        seal_request_with_aad(keys, &aad, request, compression, random_nonce()?)
     }
-}
 
-fn wire_ack_limit(ack: &WireAck) -> usize {
-    match ack {
-        WireAck::Plain(ack) => ack_body_limit(ack),
-        // The body is opaque once sealed, so the frame bound is the wider one;
-        // the plaintext was already bounded by its own kind before sealing.
-        WireAck::Authenticated { .. } => MAX_DATA_ACK_BYTES,
+    /// Opens a request, along with whether it offered compression for its ack.
+    pub fn open_request(
+        &self,
+        keys: &AuthKeys,
+        nonce: &Nonce,
+        ciphertext: &[u8],
+    ) -> Result<(PlainRequest, Compression), ProtocolError> {
+        open_request_with_aad(keys, &self.request_aad(), nonce, ciphertext)
     }
-}
 
-fn encode_ack_body(ack: &Ack) -> Result<Vec<u8>, ProtocolError> {
-    let maximum = ack_body_limit(ack) - WIRE_PLAIN_PREFIX_BYTES;
-    let detail_bytes = ack.detail.as_deref().map(str::as_bytes);
-    let text_bytes = ack.text.as_deref().map(str::as_bytes);
-    let mut parts = vec![ACK_BODY_MIN_BYTES];
-    if let Some(detail) = detail_bytes {
-        // The detail keeps the status bound even in a data ack, matching what
-        // the decoder is willing to read back.
-        checked_size(
-            &[ACK_BODY_WITH_DETAIL_OVERHEAD, detail.len()],
-            MAX_ACK_BYTES - WIRE_PLAIN_PREFIX_BYTES,
-        )?;
-        parts.push(LENGTH_BYTES);
-        parts.push(detail.len());
+    /// Seals an ack, deflating it only if `compression` is what its request
+    /// offered.
+    pub fn seal_ack(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: Nonce,
+        ack: &Ack,
+        compression: Compression,
+    ) -> Result<WireAck, ProtocolError> {
+        let aad = self.ack_aad(&request_nonce);
+        seal_ack_with_aad(keys, &aad, request_nonce, ack, compression, random_nonce()?)
     }
-    if let Some(text) = text_bytes {
-        parts.push(LENGTH_BYTES);
-        parts.push(text.len());
+
+    pub fn open_ack(
+        &self,
+        keys: &AuthKeys,
+        expected_request_nonce: &Nonce,
+        response: &WireAck,
+        limit: usize,
+    ) -> Result<Ack, ProtocolError> {
+        let aad = self.ack_aad(expected_request_nonce);
+        open_ack_with_aad(keys, &aad, expected_request_nonce, response, limit)
     }
-    let length = checked_size(&parts, maximum)?;
-    let mut output = Vec::with_capacity(length);
-    output.push(if text_bytes.is_some() {
-        TAG_ACK_DATA_BODY
-    } else {
-        TAG_ACK_BODY
-    });
-    output.push(u8::from(ack.ok));
-    match detail_bytes {
-        None => output.push(TAG_NONE),
-        Some(detail) => {
-            output.push(TAG_SOME);
-            append_length_prefixed(&mut output, detail)?;
-        }
+
+    // A chunk belongs to one request, at one place in its stream, and the last
+    // one says so: a stream cut short, reordered or spliced from another
+    // request fails to open instead of being assembled.
+    fn chunk_aad(&self, domain: &[u8], request_nonce: &Nonce, index: u32, last: bool) -> Vec<u8> {
+        let mut aad = self.follower_aad(domain, request_nonce, index);
+        aad.push(u8::from(last));
+        aad
     }
-    if let Some(text) = text_bytes {
-        append_length_prefixed(&mut output, text)?;
+
+    // What every message that follows a request is bound to: the request and
+    // its place after it.  An event stream has no last message, so only chunks
+    // add one.
+    fn follower_aad(&self, domain: &[u8], request_nonce: &Nonce, index: u32) -> Vec<u8> {
+        let mut aad = match self {
+            Self::Connection(challenge) => [domain, &challenge[..]].concat(),
+            Self::Session(session) => session_aad(domain, session, None),
+        };
+        aad.extend_from_slice(request_nonce);
+        aad.extend_from_slice(&index.to_be_bytes());
+        aad
     }
-    Ok(output)
-}
 
-fn decode_ack_body(payload: &[u8]) -> Result<Ack, ProtocolError> {
-    // The body tag decides the bound before a single length is trusted, so a
-    // status ack still cannot claim more than MAX_ACK_BYTES.
-    let body_tag = *payload.first().ok_or(ProtocolError::UnexpectedEof)?;
-    let maximum = match body_tag {
-        TAG_ACK_BODY => MAX_ACK_BYTES,
-        TAG_ACK_DATA_BODY => MAX_DATA_ACK_BYTES,
-        tag => return Err(ProtocolError::UnknownTag(tag)),
-    } - WIRE_PLAIN_PREFIX_BYTES;
-    checked_size(&[payload.len()], maximum)?;
-    let mut decoder = Decoder::new(payload);
-    decoder.read_u8()?;
-    let ok = match decoder.read_u8()? {
-        0 => false,
-        1 => true,
-        value => return Err(ProtocolError::InvalidBoolean(value)),
-    };
-    let detail = match decoder.read_u8()? {
-        TAG_NONE => None,
-        TAG_SOME => {
-            // A detail is a short status word whichever body carries it, so it
-            // keeps the tight bound even inside a data ack.
-            let bytes = decoder.read_length_prefixed(
-                0,
-                MAX_ACK_BYTES - WIRE_PLAIN_PREFIX_BYTES - ACK_BODY_WITH_DETAIL_OVERHEAD,
-            )?;
-            Some(
-                std::str::from_utf8(bytes)
-                    .map_err(|_| ProtocolError::InvalidUtf8)?
-                    .to_owned(),
-            )
+    /// Seals one chunk of the text a `SetStream` request sends.
+    pub fn seal_request_chunk(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: &Nonce,
+        chunk: &Chunk,
+    ) -> Result<WireChunk, ProtocolError> {
+        let aad = self.chunk_aad(REQUEST_CHUNK_AAD, request_nonce, chunk.index, chunk.last);
+        seal_chunk(&keys.request, &aad, chunk, random_nonce()?)
+    }
+
+    /// Opens the chunk expected at `index` of a `SetStream` request.
+    pub fn open_request_chunk(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: &Nonce,
+        index: u32,
+        chunk: &WireChunk,
+    ) -> Result<Chunk, ProtocolError> {
+        open_chunk(&keys.request, index, chunk, |last| {
+            self.chunk_aad(REQUEST_CHUNK_AAD, request_nonce, index, last)
+        })
+    }
+
+    /// Seals one chunk of the text a `GetStream` reply carries.
+    pub fn seal_reply_chunk(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: &Nonce,
+        chunk: &Chunk,
+    ) -> Result<WireChunk, ProtocolError> {
+        let aad = self.chunk_aad(REPLY_CHUNK_AAD, request_nonce, chunk.index, chunk.last);
+        seal_chunk(&keys.ack, &aad, chunk, random_nonce()?)
+    }
+
+    /// Opens the chunk expected at `index` of a `GetStream` reply.
+    pub fn open_reply_chunk(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: &Nonce,
+        index: u32,
+        chunk: &WireChunk,
+    ) -> Result<Chunk, ProtocolError> {
+        open_chunk(&keys.ack, index, chunk, |last| {
+            self.chunk_aad(REPLY_CHUNK_AAD, request_nonce, index, last)
+        })
+    }
+
+    /// Seals one event of the subscription `request_nonce` opened.
+    pub fn seal_event(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: &Nonce,
+        event: &Event,
+    ) -> Result<SealedEvent, ProtocolError> {
+        let body = encode_event_body(event.change.as_ref())?;
+        let aad = self.follower_aad(EVENT_AAD, request_nonce, event.index);
+        let nonce = random_nonce()?;
+        Ok(SealedEvent {
+            index: event.index,
+            nonce,
+            ciphertext: encrypt(&keys.ack, &nonce, &body, &aad)?,
+        })
+    }
+
+    /// Opens the event expected at `index` of a subscription.
+    pub fn open_event(
+        &self,
+        keys: &AuthKeys,
+        request_nonce: &Nonce,
+        index: u32,
+        event: &SealedEvent,
+    ) -> Result<Event, ProtocolError> {
+        if event.index != index {
+            return Err(ProtocolError::EventOrder(event.index));
         }
-        tag => return Err(ProtocolError::UnknownTag(tag)),
-    };
-    let text = if body_tag == TAG_ACK_DATA_BODY {
-        let bytes = decoder.read_length_prefixed(0, maximum - ACK_BODY_WITH_TEXT_OVERHEAD)?;
-        Some(
-            std::str::from_utf8(bytes)
-                .map_err(|_| ProtocolError::InvalidUtf8)?
-                .to_owned(),
-        )
-    } else {
-        None
-    };
-    decoder.finish()?;
-    Ok(Ack { ok, detail, text })
+        let aad = self.follower_aad(EVENT_AAD, request_nonce, index);
+        let body = decrypt(&keys.ack, &event.nonce, &event.ciphertext, &aad)?;
+        Ok(Event {
+            index,
+            change: decode_event_body(&body)?,
+        })
+    }
 }
 
-fn encode_wire_request(request: &WireRequest) -> Result<Vec<u8>, ProtocolError> {
-    match request {
-        WireRequest::Plain(request) => {
-            let body = encode_plain_request(request)?;
-            let length = checked_size(&[WIRE_PLAIN_PREFIX_BYTES, body.len()], MAX_FRAME_BYTES)?;
-            let mut output = Vec::with_capacity(length);
-            output.push(TAG_REQUEST_PLAIN);
-            output.extend_from_slice(&body);
-            Ok(output)
+/// The daemon's answer to one request.
+///
+/// `text` is `Some` only for a Get reply, and it is what splits an ack into two
+/// wire shapes: a status body that is always tiny, and a data body that carries
+/// a clipboard.  Keeping them one type keeps the sealing, framing and response
+/// binding identical for both — only the length bound differs.  `entries` is
+/// `Some` only for a history list or search, `slots` only for a slot list and
+/// `report` only for a Status reply; all three bodies stay status-sized.
+/// `generation` is `Some` only for a versioned Set or Get, and rides on a
+/// status or data body under a tag of its own.  `outcome` is `Some` only for an
+/// OutcomeQuery reply, on a status body of its own.  `code` is `Some` only on a
+/// connection whose client hello asked for codes; [`Ack::code`] is the one to
+/// branch on either way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+    pub ok: bool,
+    pub detail: Option<String>,
+    pub text: Option<String>,
+    pub entries: Option<Vec<HistoryEntry>>,
+    pub slots: Option<Vec<SlotEntry>>,
+    pub report: Option<Box<StatusReport>>,
+    pub generation: Option<u64>,
+    pub outcome: Option<WriteOutcome>,
+    pub code: Option<AckCode>,
+}
+
+impl Ack {
+    pub fn status(ok: bool, detail: Option<String>) -> Self {
+        Self {
+            ok,
+            detail,
+            text: None,
+            entries: None,
+            slots: None,
+            report: None,
+            generation: None,
+            outcome: None,
+            code: None,
         }
-        WireRequest::Authenticated { nonce, ciphertext } => {
-            let length = checked_size(
-                &[WIRE_REQUEST_AUTH_OVERHEAD, ciphertext.len()],
-                MAX_FRAME_BYTES,
-            )?;
-            if ciphertext.len() < MIN_REQUEST_CIPHERTEXT_BYTES {
-                return Err(ProtocolError::InvalidLength(ciphertext.len()));
-            }
-            let mut output = Vec::with_capacity(length);
-            output.push(TAG_REQUEST_AUTHENTICATED);
-            output.extend_from_slice(nonce);
-            append_length_prefixed(&mut output, ciphertext)?;
-            Ok(output)
+    }
+
+    pub fn data(text: String, detail: Option<String>) -> Self {
+        Self {
+            ok: true,
+            detail,
+            text: Some(text),
+            entries: None,
+            slots: None,
+            report: None,
+            generation: None,
+            outcome: None,
+            code: None,
         }
     }
-}
 
-fn decode_wire_request(payload: &[u8]) -> Result<WireRequest, ProtocolError> {
-    validate_length(payload.len())?;
-    let mut decoder = Decoder::new(payload);
-    match decoder.read_u8()? {
-        TAG_REQUEST_PLAIN => decode_plain_request(decoder.remaining()).map(WireRequest::Plain),
-        TAG_REQUEST_AUTHENTICATED => {
-            let nonce = decoder.read_array::<NONCE_BYTES>()?;
-            let maximum = MAX_FRAME_BYTES - WIRE_REQUEST_AUTH_OVERHEAD;
-            let ciphertext = decoder
-                .read_length_prefixed(MIN_REQUEST_CIPHERTEXT_BYTES, maximum)?
-                .to_vec();
-            decoder.finish()?;
-            Ok(WireRequest::Authenticated { nonce, ciphertext })
+    pub fn history(entries: Vec<HistoryEntry>, detail: Option<String>) -> Self {
+        Self {
+            ok: true,
+            detail,
+            text: None,
+            entries: Some(entries),
+            slots: None,
+            report: None,
+            generation: None,
+            outcome: None,
+            code: None,
         }
-        tag => Err(ProtocolError::UnknownTag(tag)),
+    }
+
+    pub fn slots(slots: Vec<SlotEntry>, detail: Option<String>) -> Self {
+        Self {
+            ok: true,
+            detail,
+            text: None,
+            entries: None,
+            slots: Some(slots),
+            report: None,
+            generation: None,
+            outcome: None,
+            code: None,
+        }
+    }
+
+    pub fn report(report: StatusReport, detail: Option<String>) -> Self {
+        Self {
+            ok: true,
+            detail,
+            text: None,
+            entries: None,
+            slots: None,
+            report: Some(Box::new(report)),
+            generation: None,
+            outcome: None,
+            code: None,
+        }
+    }
+
+    pub fn outcome(outcome: WriteOutcome, detail: Option<String>) -> Self {
+        Self {
+            outcome: Some(outcome),
+            ..Self::status(true, detail)
+        }
+    }
+
+    /// The same ack, saying which generation its selection is at.
+    pub fn with_generation(self, generation: u64) -> Self {
+        Self {
+            generation: Some(generation),
+            ..self
+        }
+    }
+
+    /// The same ack, carrying its code on the wire.
+    pub fn coded(self) -> Self {
+        Self {
+            code: Some(self.code()),
+            ..self
+        }
+    }
+
+    /// What became of the request.  An ack from a daemon that sent no code is
+    /// read by its detail instead.
+    pub fn code(&self) -> AckCode {
+        self.code
+            .unwrap_or_else(|| AckCode::from_detail(self.ok, self.detail.as_deref()))
     }
 }
 
-fn encode_server_hello(hello: &ServerHello) -> Vec<u8> {
-    let mut output = Vec::with_capacity(1 + CHALLENGE_BYTES);
-    output.push(TAG_SERVER_HELLO);
-    output.extend_from_slice(&hello.challenge);
-    output
+/// What became of a request, as a number a caller can branch on.
+///
+/// The detail beside it is for people and logs, and several details may share
+/// a code: `clipboard_busy` and `subscribe_busy` are both [`AckCode::Busy`].
+/// A code this build does not know reads as [`AckCode::Ok`] on an ack that
+/// says it succeeded and [`AckCode::Unknown`] on one that does not, so a newer
+/// daemon's new reason is never taken for a different, known one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckCode {
+    Ok,
+    /// The clipboard queue, or the places for subscriptions, are full.
+    Busy,
+    /// The request waited too long to start, and nothing was done.
+    TimedOut,
+    /// A write was started and not seen to finish: it may have happened.
+    OutcomeUnknown,
+    /// The daemon cannot reach a clipboard at all.
+    Unavailable,
+    /// The clipboard was reached and the operation failed.
+    Failed,
+    SelectionUnsupported,
+    RequestUnsupported,
+    AuthenticationRequired,
+    AuthenticationNotConfigured,
+    /// The token is valid but not granted this operation or selection.
+    NotPermitted,
+    ReplayRejected,
+    GenerationConflict,
+    /// No slot or history entry by that name.
+    NotFound,
+    TooLarge,
+    /// No room for another slot.
+    LimitReached,
+    /// The secret detector flagged the text and its policy refuses it.
+    SecretRefused,
+    /// A verified Set found another owner had already replaced its text.
+    Overwritten,
+    Unknown,
 }
 
-fn decode_server_hello(payload: &[u8]) -> Result<ServerHello, ProtocolError> {
-    validate_ack_length(payload.len(), MAX_ACK_BYTES)?;
-    let mut decoder = Decoder::new(payload);
-    let tag = decoder.read_u8()?;
-    if tag != TAG_SERVER_HELLO {
-        return Err(ProtocolError::UnknownTag(tag));
+impl AckCode {
+    const UNKNOWN: u16 = 0xffff;
+
+    pub fn number(self) -> u16 {
+        match self {
+            Self::Ok => 0,
+            Self::Busy => 1,
+            Self::TimedOut => 2,
+            Self::OutcomeUnknown => 3,
+            Self::Unavailable => 4,
+            Self::Failed => 5,
+            Self::SelectionUnsupported => 6,
+            Self::RequestUnsupported => 7,
+            Self::AuthenticationRequired => 8,
+            Self::AuthenticationNotConfigured => 9,
+            Self::NotPermitted => 10,
+            Self::ReplayRejected => 11,
+            Self::GenerationConflict => 12,
+            Self::NotFound => 13,
+            Self::TooLarge => 14,
+            Self::LimitReached => 15,
+            Self::SecretRefused => 16,
+            Self::Overwritten => 17,
+            Self::Unknown => Self::UNKNOWN,
+        }
     }
-    let challenge = decoder.read_array::<CHALLENGE_BYTES>()?;
-    decoder.finish()?;
-    Ok(ServerHello { challenge })
-}
 
-fn encode_wire_ack(ack: &WireAck) -> Result<Vec<u8>, ProtocolError> {
-    let maximum = wire_ack_limit(ack);
-    match ack {
-        WireAck::Plain(ack) => {
-            let body = encode_ack_body(ack)?;
-            let length = checked_size(&[WIRE_PLAIN_PREFIX_BYTES, body.len()], maximum)?;
-            let mut output = Vec::with_capacity(length);
-            output.push(TAG_ACK_PLAIN);
-            output.extend_from_slice(&body);
-            Ok(output)
+    /// The code `number` stands for on an ack whose ok byte is `ok`.
+    pub fn from_number(ok: bool, number: u16) -> Self {
+        match number {
+            0 => Self::Ok,
+            1 => Self::Busy,
+            2 => Self::TimedOut,
+            3 => Self::OutcomeUnknown,
+            4 => Self::Unavailable,
+            5 => Self::Failed,
+            6 => Self::SelectionUnsupported,
+            7 => Self::RequestUnsupported,
+            8 => Self::AuthenticationRequired,
+            9 => Self::AuthenticationNotConfigured,
+            10 => Self::NotPermitted,
+            11 => Self::ReplayRejected,
+            12 => Self::GenerationConflict,
+            13 => Self::NotFound,
+            14 => Self::TooLarge,
+            15 => Self::LimitReached,
+            16 => Self::SecretRefused,
+            17 => Self::Overwritten,
+            _ if ok => Self::Ok,
+            _ => Self::Unknown,
         }
-        WireAck::Authenticated {
-            request_nonce,
-            nonce,
-            ciphertext,
-        } => {
-            let length = checked_size(&[WIRE_ACK_AUTH_OVERHEAD, ciphertext.len()], maximum)?;
-            if ciphertext.len() < MIN_ACK_CIPHERTEXT_BYTES {
-                return Err(ProtocolError::InvalidLength(ciphertext.len()));
+    }
+
+    /// The code of a detail every daemon so far has sent, which is how the
+    /// code of an ack without one is known.  This is also where the daemon's
+    /// own details get their codes, so the two cannot drift apart.
+    pub fn from_detail(ok: bool, detail: Option<&str>) -> Self {
+        if ok {
+            return Self::Ok;
+        }
+        match detail.unwrap_or_default() {
+            "clipboard_busy" | "subscribe_busy" => Self::Busy,
+            "clipboard_expired" => Self::TimedOut,
+            "clipboard_outcome_unknown" => Self::OutcomeUnknown,
+            "clipboard_unavailable" | "clipboard_worker_stopped" => Self::Unavailable,
+            "clipboard_get_failed" | "clipboard_set_failed" | "clipboard_clear_failed" => {
+                Self::Failed
             }
-            let mut output = Vec::with_capacity(length);
-            output.push(TAG_ACK_AUTHENTICATED);
-            output.extend_from_slice(request_nonce);
-            output.extend_from_slice(nonce);
-            append_length_prefixed(&mut output, ciphertext)?;
-            Ok(output)
+            "selection_unsupported" => Self::SelectionUnsupported,
+            "request_unsupported" => Self::RequestUnsupported,
+            "authentication_required"
+            | "get_requires_authentication"
+            | "history_requires_authentication"
+            | "outcome_requires_authentication"
+            | "slot_requires_authentication"
+            | "status_requires_authentication"
+            | "subscribe_requires_authentication" => Self::AuthenticationRequired,
+            "authentication_not_configured" => Self::AuthenticationNotConfigured,
+            "token_not_permitted" => Self::NotPermitted,
+            "replay_rejected" => Self::ReplayRejected,
+            "generation_conflict" => Self::GenerationConflict,
+            "slot_not_found" | "history_entry_not_found" => Self::NotFound,
+            "slot_too_large" | "append_too_large" => Self::TooLarge,
+            "slot_limit_reached" => Self::LimitReached,
+            "secret_refused" => Self::SecretRefused,
+            "clipboard_overwritten" => Self::Overwritten,
+            _ => Self::Unknown,
         }
     }
 }
 
-fn decode_wire_ack(payload: &[u8], limit: usize) -> Result<WireAck, ProtocolError> {
-    validate_ack_length(payload.len(), limit)?;
-    let mut decoder = Decoder::new(payload);
-    match decoder.read_u8()? {
-        TAG_ACK_PLAIN => decode_ack_body(decoder.remaining()).map(WireAck::Plain),
-        TAG_ACK_AUTHENTICATED => {
-            let request_nonce = decoder.read_array::<NONCE_BYTES>()?;
-            let nonce = decoder.read_array::<NONCE_BYTES>()?;
-            let maximum = limit - WIRE_ACK_AUTH_OVERHEAD;
-            let ciphertext = decoder
-                .read_length_prefixed(MIN_ACK_CIPHERTEXT_BYTES, maximum)?
-                .to_vec();
-            decoder.finish()?;
-            Ok(WireAck::Authenticated {
-                request_nonce,
-                nonce,
-                ciphertext,
-            })
+/// What became of an authenticated write, as far as the daemon remembers.
+///
+/// The daemon keeps the last few writes for a minute or so, and settles each
+/// when its worker finishes it, even one it stopped waiting for and answered
+/// `clipboard_outcome_unknown`.  `Unknown` is a write it has no record of:
+/// never received, refused before it reached the clipboard, or forgotten —
+/// or one whose worker died carrying it out, which may or may not have landed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Unknown,
+    Running,
+    Applied,
+    /// Refused or failed, for the reason the code gives.
+    Failed(AckCode),
+}
+
+impl WriteOutcome {
+    fn encode(self, output: &mut Vec<u8>) {
+        match self {
+            Self::Unknown => output.push(OUTCOME_UNKNOWN),
+            Self::Running => output.push(OUTCOME_RUNNING),
+            Self::Applied => output.push(OUTCOME_APPLIED),
+            Self::Failed(code) => {
+                output.push(OUTCOME_FAILED);
+                output.extend_from_slice(&code.number().to_be_bytes());
+            }
         }
-        tag => Err(ProtocolError::UnknownTag(tag)),
     }
-}
 
-fn encrypt(
-    key: &[u8; KEY_BYTES],
-    nonce: &Nonce,
-    plaintext: &[u8],
-    aad: &[u8],
-) -> Result<Vec<u8>, ProtocolError> {
-    cipher(key)?
-        .encrypt(
-            &AesNonce::from(*nonce),
-            Payload {
-                msg: plaintext,
-                aad,
-            },
-        )
-        .map_err(|_| ProtocolError::AuthenticationFailed)
-}
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, ProtocolError> {
+        match decoder.read_u8()? {
+            OUTCOME_UNKNOWN => Ok(Self::Unknown),
+            OUTCOME_RUNNING => Ok(Self::Running),
+            OUTCOME_APPLIED => Ok(Self::Applied),
+            OUTCOME_FAILED => {
+                let number = u16::from_be_bytes(decoder.read_array::<ACK_CODE_BYTES>()?);
+                Ok(Self::Failed(AckCode::from_number(false, number)))
+            }
+            tag => Err(ProtocolError::UnknownTag(tag)),
+        }
+    }
 
-fn decrypt(
-    key: &[u8; KEY_BYTES],
-    nonce: &Nonce,
-    ciphertext: &[u8],
-    aad: &[u8],
-) -> Result<Vec<u8>, ProtocolError> {
-    cipher(key)?
-        .decrypt(
-            &AesNonce::from(*nonce),
-            Payload {
-                msg: ciphertext,
-                aad,
-            },
-        )
-        .map_err(|_| ProtocolError::AuthenticationFailed)
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Running => "running",
+            Self::Applied => "applied",
+            Self::Failed(_) => "failed",
+        }
+    }
 }
 
-fn request_aad(challenge: &Challenge) -> Vec<u8> {
-    let mut aad = Vec::with_capacity(REQUEST_AAD.len() + challenge.len());
-    aad.extend_from_slice(REQUEST_AAD);
-    aad.extend_from_slice(challenge);
-    aad
+/// How a value came to be in the daemon's history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Written by a request to this daemon.
+    Set,
+    /// Seen by the daemon while it watched a selection for a subscriber, and
+    /// written by something else.
+    Observed,
 }
 
-fn ack_aad(challenge: &Challenge, request_nonce: &Nonce) -> Vec<u8> {
-    let mut aad = Vec::with_capacity(ACK_AAD.len() + challenge.len() + request_nonce.len());
-    aad.extend_from_slice(ACK_AAD);
-    aad.extend_from_slice(challenge);
-    aad.extend_from_slice(request_nonce);
-    aad
-}
+impl Origin {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Set => ORIGIN_SET,
+            Self::Observed => ORIGIN_OBSERVED,
+        }
+    }
 
-pub fn seal_request(
-    keys: &AuthKeys,
-    challenge: &Challenge,
-    request: &PlainRequest,
-) -> Result<(WireRequest, Nonce), ProtocolError> {
-    seal_request_with_nonce(keys, challenge, request, random_nonce()?)
+    fn from_tag(tag: u8) -> Result<Self, ProtocolError> {
+        match tag {
+            ORIGIN_SET => Ok(Self::Set),
+            ORIGIN_OBSERVED => Ok(Self::Observed),
+            tag => Err(ProtocolError::UnknownTag(tag)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Set => "set",
+            Self::Observed => "observed",
+        }
+    }
 }
 
-fn seal_request_with_nonce(
-    keys: &AuthKeys,
-    challenge: &Challenge,
-    request: &PlainRequest,
-    nonce: Nonce,
-) -> Result<(WireRequest, Nonce), ProtocolError> {
-    let plaintext = encode_plain_request(request)?;
-    checked_size(
-        &[WIRE_REQUEST_AUTH_OVERHEAD, plaintext.len(), AEAD_TAG_BYTES],
-        MAX_FRAME_BYTES,
-    )?;
-    let aad = request_aad(challenge);
-    let ciphertext = encrypt(&keys.request, &nonce, &plaintext, &aad)?;
-    Ok((WireRequest::Authenticated { nonce, ciphertext }, nonce))
+/// What a history list says about one past value, without the value itself:
+/// `HistoryGet` fetches that by `id`.
+///
+/// Ids grow by one per entry and are never reused while the daemon runs, so a
+/// client can fetch an entry it listed without it turning into another one.
+/// `time` is in seconds since the Unix epoch, by the daemon's clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub time: u64,
+    pub selection: Selection,
+    pub origin: Origin,
+    pub size: u64,
 }
 
-pub fn open_request(
-    keys: &AuthKeys,
-    challenge: &Challenge,
-    nonce: &Nonce,
-    ciphertext: &[u8],
-) -> Result<PlainRequest, ProtocolError> {
-    if ciphertext.len() < MIN_REQUEST_CIPHERTEXT_BYTES
-        || ciphertext.len() > MAX_FRAME_BYTES - WIRE_REQUEST_AUTH_OVERHEAD
-    {
-        return Err(ProtocolError::InvalidLength(ciphertext.len()));
-    }
-    let aad = request_aad(challenge);
-    let plaintext = decrypt(&keys.request, nonce, ciphertext, &aad)?;
-    decode_plain_request(&plaintext)
+/// What a slot list says about one slot, without its text.  `time` is when it
+/// was last set, in seconds since the Unix epoch, by the daemon's clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotEntry {
+    pub name: SlotName,
+    pub time: u64,
+    pub size: u64,
 }
 
-pub fn seal_ack(
-    keys: &AuthKeys,
-    challenge: &Challenge,
-    request_nonce: Nonce,
-    ack: &Ack,
-) -> Result<WireAck, ProtocolError> {
-    seal_ack_with_nonce(keys, challenge, request_nonce, ack, random_nonce()?)
+/// How a daemon authenticates the requests it serves.  A daemon that does not
+/// answers no Status, so there is no mode for that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMode {
+    Tokens,
+    Keys,
+    TokensAndKeys,
 }
 
-fn seal_ack_with_nonce(
-    keys: &AuthKeys,
-    challenge: &Challenge,
-    request_nonce: Nonce,
-    ack: &Ack,
-    nonce: Nonce,
-) -> Result<WireAck, ProtocolError> {
-    let plaintext = encode_ack_body(ack)?;
-    checked_size(
-        &[WIRE_ACK_AUTH_OVERHEAD, plaintext.len(), AEAD_TAG_BYTES],
-        ack_body_limit(ack),
-    )?;
-    let aad = ack_aad(challenge, &request_nonce);
-    let ciphertext = encrypt(&keys.ack, &nonce, &plaintext, &aad)?;
-    Ok(WireAck::Authenticated {
-        request_nonce,
-        nonce,
-        ciphertext,
-    })
+impl AuthMode {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Tokens => 1,
+            Self::Keys => 2,
+            Self::TokensAndKeys => 3,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self, ProtocolError> {
+        match tag {
+            1 => Ok(Self::Tokens),
+            2 => Ok(Self::Keys),
+            3 => Ok(Self::TokensAndKeys),
+            tag => Err(ProtocolError::UnknownTag(tag)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tokens => "token",
+            Self::Keys => "key",
+            Self::TokensAndKeys => "token+key",
+        }
+    }
 }
 
-/// Opens an authenticated ack, refusing anything larger than `limit`.
+/// What a daemon says about itself in answer to `Status`.
 ///
-/// The caller knows which request it sent and therefore how large a legitimate
-/// answer can be; passing that bound in is what stops a ping from being
-/// answered with a ten-megabyte allocation.
-pub fn open_ack(
-    keys: &AuthKeys,
-    challenge: &Challenge,
-    expected_request_nonce: &Nonce,
-    response: &WireAck,
-    limit: usize,
-) -> Result<Ack, ProtocolError> {
-    let WireAck::Authenticated {
-        request_nonce,
-        nonce,
-        ciphertext,
-    } = response
-    else {
-        return Err(ProtocolError::UnexpectedProtection);
-    };
-    if request_nonce != expected_request_nonce {
-        return Err(ProtocolError::ResponseBinding);
+/// `uptime` is in seconds.  `backend` names how the daemon reaches the
+/// clipboard — `x11`, `wayland-data-control`, `macos` or `windows` — and is
+/// `none` until it first has.  `last_operation` is whether the last clipboard
+/// operation succeeded, if there has been one.  `queue_depth` is how many
+/// operations wait for the clipboard, and `connections` how many connections
+/// are open, this one included.  `details` counts the acks sent since start by
+/// their detail, in the order of the details' names.  `expiries` are the
+/// selections an expiring Set will clear, each with the seconds left before it
+/// does.  `restarts` is how many times the clipboard worker has been replaced
+/// since start, after a panic or after sticking past its deadline; it trails
+/// the report, so a daemon that predates it sends none.  Every string is at
+/// most [`MAX_STATUS_NAME_BYTES`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusReport {
+    pub version: String,
+    pub uptime: u64,
+    pub address: String,
+    pub auth: AuthMode,
+    pub backend: String,
+    pub last_operation: Option<bool>,
+    pub queue_depth: u32,
+    pub connections: u32,
+    pub details: Vec<(String, u64)>,
+    pub expiries: Vec<(Selection, u32)>,
+    pub restarts: Option<u32>,
+}
+
+impl StatusReport {
+    fn encode(&self, output: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        if self.details.len() > MAX_STATUS_DETAILS {
+            return Err(ProtocolError::InvalidLength(self.details.len()));
+        }
+        if self.expiries.len() > MAX_STATUS_EXPIRIES {
+            return Err(ProtocolError::InvalidLength(self.expiries.len()));
+        }
+        append_status_name(output, &self.version)?;
+        output.extend_from_slice(&self.uptime.to_be_bytes());
+        append_status_name(output, &self.address)?;
+        output.push(self.auth.tag());
+        append_status_name(output, &self.backend)?;
+        match self.last_operation {
+            None => output.push(TAG_NONE),
+            Some(ok) => output.extend_from_slice(&[TAG_SOME, u8::from(ok)]),
+        }
+        output.extend_from_slice(&self.queue_depth.to_be_bytes());
+        output.extend_from_slice(&self.connections.to_be_bytes());
+        append_length(output, self.details.len())?;
+        for (detail, count) in &self.details {
+            append_status_name(output, detail)?;
+            output.extend_from_slice(&count.to_be_bytes());
+        }
+        append_length(output, self.expiries.len())?;
+        for (selection, seconds) in &self.expiries {
+            output.push(selection.tag());
+            output.extend_from_slice(&seconds.to_be_bytes());
+        }
+        if let Some(restarts) = self.restarts {
+            output.extend_from_slice(&restarts.to_be_bytes());
+        }
+        Ok(())
     }
-    if ciphertext.len() < MIN_ACK_CIPHERTEXT_BYTES
-        || ciphertext.len() > limit - WIRE_ACK_AUTH_OVERHEAD
-    {
-        return Err(ProtocolError::InvalidLength(ciphertext.len()));
+
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, ProtocolError> {
+        let version = read_status_name(decoder)?;
+        let uptime = u64::from_be_bytes(decoder.read_array::<8>()?);
+        let address = read_status_name(decoder)?;
+        let auth = AuthMode::from_tag(decoder.read_u8()?)?;
+        let backend = read_status_name(decoder)?;
+        let last_operation = match decoder.read_u8()? {
+            TAG_NONE => None,
+            TAG_SOME => match decoder.read_u8()? {
+                0 => Some(false),
+                1 => Some(true),
+                value => return Err(ProtocolError::InvalidBoolean(value)),
+            },
+            tag => return Err(ProtocolError::UnknownTag(tag)),
+        };
+        let queue_depth = decoder.read_u32()?;
+        let connections = decoder.read_u32()?;
+        let count = decoder.read_u32()? as usize;
+        if count > MAX_STATUS_DETAILS {
+            return Err(ProtocolError::InvalidLength(count));
+        }
+        let mut details = Vec::with_capacity(count);
+        for _ in 0..count {
+            let detail = read_status_name(decoder)?;
+            details.push((detail, u64::from_be_bytes(decoder.read_array::<8>()?)));
+        }
+        let count = decoder.read_u32()? as usize;
+        if count > MAX_STATUS_EXPIRIES {
+            return Err(ProtocolError::InvalidLength(count));
+        }
+        let mut expiries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            expiries.push((selection, decoder.read_u32()?));
+        }
+        let restarts = if decoder.remaining().is_empty() {
+            None
+        } else {
+            Some(decoder.read_u32()?)
+        };
+        Ok(Self {
+            version,
+            uptime,
+            address,
+            auth,
+            backend,
+            last_operation,
+            queue_depth,
+            connections,
+            details,
+            expiries,
+            restarts,
+        })
     }
-    let aad = ack_aad(challenge, request_nonce);
-    let plaintext = decrypt(&keys.ack, nonce, ciphertext, &aad)?;
-    decode_ack_body(&plaintext)
 }
 
-fn frame(payload: Vec<u8>) -> Result<Vec<u8>, ProtocolError> {
-    validate_length(payload.len())?;
-    let mut output = Vec::with_capacity(FRAME_HEADER_BYTES + payload.len());
-    output.extend_from_slice(&FRAME_MAGIC);
-    append_length(&mut output, payload.len())?;
-    output.extend_from_slice(&payload);
-    Ok(output)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireAck {
+    Plain(Ack),
+    Authenticated {
+        request_nonce: Nonce,
+        nonce: Nonce,
+        ciphertext: Vec<u8>,
+    },
 }
 
-pub fn encode_request_frame(request: &WireRequest) -> Result<Vec<u8>, ProtocolError> {
-    frame(encode_wire_request(request)?)
+/// One piece of a streamed text, before any sealing.
+///
+/// Chunks are numbered from zero and the last one is marked, so the receiver
+/// knows both where each belongs and that nothing is missing.  Each carries
+/// whole characters: a chunk is valid UTF-8 on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub index: u32,
+    pub last: bool,
+    pub text: String,
 }
 
-pub fn encode_hello_frame(hello: &ServerHello) -> Result<Vec<u8>, ProtocolError> {
-    let payload = encode_server_hello(hello);
-    validate_ack_length(payload.len(), MAX_ACK_BYTES)?;
-    frame(payload)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireChunk {
+    Plain(Chunk),
+    Authenticated {
+        index: u32,
+        last: bool,
+        nonce: Nonce,
+        ciphertext: Vec<u8>,
+    },
 }
 
-pub fn encode_ack_frame(ack: &WireAck) -> Result<Vec<u8>, ProtocolError> {
-    let payload = encode_wire_ack(ack)?;
-    validate_ack_length(payload.len(), wire_ack_limit(ack))?;
-    frame(payload)
+/// What a subscriber learns about one change to a selection.
+///
+/// `size` and `hash` describe the whole new text, which `text` carries only
+/// when the subscription asked for it and it fits [`MAX_EVENT_TEXT_BYTES`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub selection: Selection,
+    pub size: u64,
+    pub hash: ContentHash,
+    pub text: Option<String>,
 }
 
-pub fn decode_request_payload(payload: &[u8]) -> Result<WireRequest, ProtocolError> {
-    decode_wire_request(payload)
+/// One message of a subscription, before any sealing.
+///
+/// Events are numbered from zero like chunks.  `change` is `None` for a
+/// heartbeat, which says only that the subscription is still there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub index: u32,
+    pub change: Option<Change>,
 }
 
-pub fn decode_hello_payload(payload: &[u8]) -> Result<ServerHello, ProtocolError> {
-    decode_server_hello(payload)
+/// An event as it crosses the wire.  Only an authenticated request may
+/// subscribe, so there is no plaintext form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedEvent {
+    pub index: u32,
+    pub nonce: Nonce,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    InvalidMagic,
+    InvalidLength(usize),
+    UnexpectedEof,
+    TrailingBytes,
+    UnknownTag(u8),
+    InvalidBoolean(u8),
+    InvalidUtf8,
+    AuthenticationFailed,
+    UnsupportedRequest(u8),
+    UnsupportedVersion(u16),
+    Random(String),
+    HostKey,
+    UnexpectedProtection,
+    ResponseBinding,
+    ChunkOrder(u32),
+    Decompression,
+    EventOrder(u32),
+    InvalidSlotName,
+    KeyDerivation(String),
+    InvalidExpiry(u32),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => f.write_str("invalid frame magic"),
+            Self::InvalidLength(length) => write!(f, "invalid frame length: {length}"),
+            Self::UnexpectedEof => f.write_str("payload is truncated"),
+            Self::TrailingBytes => f.write_str("trailing bytes in payload"),
+            Self::UnknownTag(tag) => write!(f, "unknown protocol tag: 0x{tag:02x}"),
+            Self::InvalidBoolean(value) => write!(f, "invalid boolean value: {value}"),
+            Self::InvalidUtf8 => f.write_str("string is not valid UTF-8"),
+            Self::AuthenticationFailed => f.write_str("authenticated message rejected"),
+            Self::UnsupportedRequest(tag) => write!(f, "unsupported request: 0x{tag:02x}"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version: {version}")
+            }
+            Self::Random(detail) => write!(f, "secure random generation failed: {detail}"),
+            Self::HostKey => f.write_str("the daemon did not prove it holds the pinned host key"),
+            Self::UnexpectedProtection => f.write_str("unexpected message protection mode"),
+            Self::ResponseBinding => f.write_str("response is not bound to this request"),
+            Self::ChunkOrder(index) => write!(f, "chunk {index} is out of order"),
+            Self::Decompression => f.write_str("compressed body is malformed or too large"),
+            Self::EventOrder(index) => write!(f, "event {index} is out of order"),
+            Self::InvalidSlotName => f.write_str("invalid slot name"),
+            Self::KeyDerivation(detail) => write!(f, "token key derivation failed: {detail}"),
+            Self::InvalidExpiry(seconds) => write!(f, "invalid expiry: {seconds} seconds"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+struct Decoder<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(payload: &'a [u8]) -> Self {
+        Self {
+            payload,
+            position: 0,
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, ProtocolError> {
+        let value = self
+            .payload
+            .get(self.position)
+            .copied()
+            .ok_or(ProtocolError::UnexpectedEof)?;
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ProtocolError> {
+        let bytes = self.read_array::<LENGTH_BYTES>()?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let bytes = self.read_bytes(N)?;
+        let mut output = [0_u8; N];
+        output.copy_from_slice(bytes);
+        Ok(output)
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], ProtocolError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .ok_or(ProtocolError::InvalidLength(length))?;
+        let bytes = self
+            .payload
+            .get(self.position..end)
+            .ok_or(ProtocolError::UnexpectedEof)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_length_prefixed(
+        &mut self,
+        minimum: usize,
+        maximum: usize,
+    ) -> Result<&'a [u8], ProtocolError> {
+        let length = self.read_u32()? as usize;
+        if length < minimum || length > maximum {
+            return Err(ProtocolError::InvalidLength(length));
+        }
+        self.read_bytes(length)
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.payload[self.position..]
+    }
+
+    fn finish(self) -> Result<(), ProtocolError> {
+        if self.position == self.payload.len() {
+            Ok(())
+        } else {
+            Err(ProtocolError::TrailingBytes)
+        }
+    }
+}
+
+/// The keys of the original scheme, `KeyDerivation::Sha256`.
+pub fn derive_auth_keys(token: &str) -> AuthKeys {
+    AuthKeys {
+        request: derive_key(REQUEST_KEY_DOMAIN, token.as_bytes()),
+        ack: derive_key(ACK_KEY_DOMAIN, token.as_bytes()),
+    }
+}
+
+/// The keys `derivation` yields for `token`.  Argon2id is slow on purpose, so
+/// a caller deriving more than once for the same daemon keeps the result.
+pub fn derive_keys(token: &str, derivation: &KeyDerivation) -> Result<AuthKeys, ProtocolError> {
+    match derivation {
+        KeyDerivation::Sha256 => Ok(derive_auth_keys(token)),
+        KeyDerivation::Hkdf(salt) => expand_auth_keys(salt, token.as_bytes()),
+        KeyDerivation::Argon2id(salt) => {
+            let params = Params::new(
+                ARGON2_MEMORY_KIB,
+                ARGON2_PASSES,
+                ARGON2_LANES,
+                Some(KEY_BYTES),
+            )
+            .map_err(|error| ProtocolError::KeyDerivation(error.to_string()))?;
+            let mut stretched = [0_u8; KEY_BYTES];
+            let result = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(token.as_bytes(), salt, &mut stretched)
+                .map_err(|error| ProtocolError::KeyDerivation(error.to_string()))
+                .and_then(|()| expand_auth_keys(salt, &stretched));
+            stretched.fill(0);
+            result
+        }
+    }
+}
+
+/// A fresh salt for a salted `KeyDerivation`.
+pub fn new_kdf_salt() -> Result<KdfSalt, ProtocolError> {
+    let mut salt = [0_u8; KDF_SALT_BYTES];
+    getrandom::fill(&mut salt).map_err(|error| ProtocolError::Random(error.to_string()))?;
+    Ok(salt)
+}
+
+fn expand_auth_keys(salt: &[u8], secret: &[u8]) -> Result<AuthKeys, ProtocolError> {
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), secret);
+    let mut keys = AuthKeys {
+        request: [0; KEY_BYTES],
+        ack: [0; KEY_BYTES],
+    };
+    hkdf.expand(REQUEST_KEY_DOMAIN, &mut keys.request)
+        .and_then(|()| hkdf.expand(ACK_KEY_DOMAIN, &mut keys.ack))
+        .map_err(|error| ProtocolError::KeyDerivation(error.to_string()))?;
+    Ok(keys)
+}
+
+/// Derives the history store key from the token or from a key file's bytes.
+pub fn derive_store_key(secret: &[u8]) -> StoreKey {
+    StoreKey(derive_key(STORE_KEY_DOMAIN, secret))
+}
+
+fn derive_key(domain: &[u8], secret: &[u8]) -> [u8; KEY_BYTES] {
+    let mut digest = Sha256::new();
+    digest.update(domain);
+    digest.update((secret.len() as u64).to_be_bytes());
+    digest.update(secret);
+    digest.finalize().into()
+}
+
+fn random_nonce() -> Result<Nonce, ProtocolError> {
+    let mut nonce = [0_u8; NONCE_BYTES];
+    getrandom::fill(&mut nonce).map_err(|error| ProtocolError::Random(error.to_string()))?;
+    Ok(nonce)
+}
+
+pub fn new_server_hello(info: ServerInfo) -> Result<ServerHello, ProtocolError> {
+    let mut challenge = [0_u8; CHALLENGE_BYTES];
+    getrandom::fill(&mut challenge).map_err(|error| ProtocolError::Random(error.to_string()))?;
+    Ok(ServerHello {
+        challenge,
+        info: Some(info),
+        host: None,
+    })
 }
 
-pub fn decode_ack_payload(payload: &[u8], limit: usize) -> Result<WireAck, ProtocolError> {
-    decode_wire_ack(payload, limit)
-}
+fn cipher(key: &[u8; KEY_BYTES]) -> Result<Aes256Gcm, ProtocolError> {
+    Aes256Gcm::new_from_slice(key).map_err(|_| ProtocolError::AuthenticationFailed)
+}
+
+fn checked_size(parts: &[usize], maximum: usize) -> Result<usize, ProtocolError> {
+    let mut total = 0_usize;
+    for part in parts {
+        total = total
+            .checked_add(*part)
+            .ok_or(ProtocolError::InvalidLength(usize::MAX))?;
+    }
+    if total == 0 || total > maximum {
+        Err(ProtocolError::InvalidLength(total))
+    } else {
+        Ok(total)
+    }
+}
+
+fn append_length(output: &mut Vec<u8>, length: usize) -> Result<(), ProtocolError> {
+    let length = u32::try_from(length).map_err(|_| ProtocolError::InvalidLength(length))?;
+    output.extend_from_slice(&length.to_be_bytes());
+    Ok(())
+}
+
+fn append_length_prefixed(output: &mut Vec<u8>, bytes: &[u8]) -> Result<(), ProtocolError> {
+    append_length(output, bytes.len())?;
+    output.extend_from_slice(bytes);
+    Ok(())
+}
+
+// The strings of a Status report are names rather than content, each behind
+// one length byte.
+fn append_status_name(output: &mut Vec<u8>, text: &str) -> Result<(), ProtocolError> {
+    if text.len() > MAX_STATUS_NAME_BYTES {
+        return Err(ProtocolError::InvalidLength(text.len()));
+    }
+    output.push(text.len() as u8);
+    output.extend_from_slice(text.as_bytes());
+    Ok(())
+}
+
+fn read_status_name(decoder: &mut Decoder<'_>) -> Result<String, ProtocolError> {
+    let length = decoder.read_u8()? as usize;
+    if length > MAX_STATUS_NAME_BYTES {
+        return Err(ProtocolError::InvalidLength(length));
+    }
+    std::str::from_utf8(decoder.read_bytes(length)?)
+        .map(str::to_owned)
+        .map_err(|_| ProtocolError::InvalidUtf8)
+}
+
+fn encode_plain_request(request: &PlainRequest) -> Result<Vec<u8>, ProtocolError> {
+    match request {
+        PlainRequest::Ping => Ok(vec![TAG_PING]),
+        PlainRequest::Get { selection } => Ok(vec![TAG_GET, selection.tag()]),
+        PlainRequest::Set {
+            selection: Selection::Clipboard,
+            text,
+        } => encode_text_request(TAG_SET, None, text),
+        PlainRequest::Set { selection, text } => {
+            encode_text_request(TAG_SET_SELECTION, Some(*selection), text)
+        }
+        PlainRequest::Legacy { text } => encode_text_request(TAG_LEGACY, None, text),
+        PlainRequest::SetStream { selection } => Ok(vec![TAG_SET_STREAM, selection.tag()]),
+        PlainRequest::GetStream { selection } => Ok(vec![TAG_GET_STREAM, selection.tag()]),
+        PlainRequest::Clear { selection } => Ok(vec![TAG_CLEAR, selection.tag()]),
+        PlainRequest::Subscribe { selection, text } => Ok(vec![
+            TAG_SUBSCRIBE,
+            selection.tag(),
+            if *text { SUBSCRIBE_FLAG_TEXT } else { 0 },
+        ]),
+        PlainRequest::HistoryList => Ok(vec![TAG_HISTORY_LIST]),
+        PlainRequest::HistoryGet { id } => Ok([&[TAG_HISTORY_GET][..], &id.to_be_bytes()].concat()),
+        PlainRequest::HistorySearch { query } => {
+            encode_text_request(TAG_HISTORY_SEARCH, None, query)
+        }
+        PlainRequest::SlotSet { name, text } => {
+            let length = checked_size(
+                &[
+                    PLAIN_REQUEST_PREFIX_BYTES,
+                    SLOT_NAME_PREFIX_BYTES,
+                    name.as_str().len(),
+                    STRING_PREFIX_BYTES,
+                    text.len(),
+                ],
+                MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+            )?;
+            let mut output = Vec::with_capacity(length);
+            output.push(TAG_SLOT_SET);
+            name.encode(&mut output);
+            append_length_prefixed(&mut output, text.as_bytes())?;
+            Ok(output)
+        }
+        PlainRequest::SlotGet { name } => {
+            let mut output = vec![TAG_SLOT_GET];
+            name.encode(&mut output);
+            Ok(output)
+        }
+        PlainRequest::SlotList => Ok(vec![TAG_SLOT_LIST]),
+        PlainRequest::Status => Ok(vec![TAG_STATUS]),
+        PlainRequest::Append {
+            selection,
+            separator,
+            text,
+        } => {
+            if separator.len() > MAX_SEPARATOR_BYTES {
+                return Err(ProtocolError::InvalidLength(separator.len()));
+            }
+            let length = checked_size(
+                &[
+                    PLAIN_REQUEST_PREFIX_BYTES,
+                    SELECTION_BYTES,
+                    SEPARATOR_PREFIX_BYTES,
+                    separator.len(),
+                    STRING_PREFIX_BYTES,
+                    text.len(),
+                ],
+                MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+            )?;
+            let mut output = Vec::with_capacity(length);
+            output.extend_from_slice(&[TAG_APPEND, selection.tag(), separator.len() as u8]);
+            output.extend_from_slice(separator.as_bytes());
+            append_length_prefixed(&mut output, text.as_bytes())?;
+            Ok(output)
+        }
+        PlainRequest::SetVersioned {
+            selection,
+            expected,
+            text,
+        } => {
+            let length = checked_size(
+                &[
+                    PLAIN_REQUEST_PREFIX_BYTES,
+                    SELECTION_BYTES,
+                    EXPECTED_GENERATION_BYTES,
+                    STRING_PREFIX_BYTES,
+                    text.len(),
+                ],
+                MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+            )?;
+            let mut output = Vec::with_capacity(length);
+            output.extend_from_slice(&[TAG_SET_VERSIONED, selection.tag()]);
+            match expected {
+                None => output.push(TAG_NONE),
+                Some(generation) => {
+                    output.push(TAG_SOME);
+                    output.extend_from_slice(&generation.to_be_bytes());
+                }
+            }
+            append_length_prefixed(&mut output, text.as_bytes())?;
+            Ok(output)
+        }
+        PlainRequest::SetExpiring {
+            selection,
+            seconds,
+            text,
+        } => {
+            check_expiry(*seconds)?;
+            let length = checked_size(
+                &[
+                    PLAIN_REQUEST_PREFIX_BYTES,
+                    SELECTION_BYTES,
+                    EXPIRY_BYTES,
+                    STRING_PREFIX_BYTES,
+                    text.len(),
+                ],
+                MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+            )?;
+            let mut output = Vec::with_capacity(length);
+            output.extend_from_slice(&[TAG_SET_EXPIRING, selection.tag()]);
+            output.extend_from_slice(&seconds.to_be_bytes());
+            append_length_prefixed(&mut output, text.as_bytes())?;
+            Ok(output)
+        }
+        PlainRequest::SetSensitive {
+            selection,
+            seconds,
+            text,
+        } => {
+            if let Some(seconds) = seconds {
+                check_expiry(*seconds)?;
+            }
+            let length = checked_size(
+                &[
+                    PLAIN_REQUEST_PREFIX_BYTES,
+                    SELECTION_BYTES,
+                    OPTIONAL_EXPIRY_BYTES,
+                    STRING_PREFIX_BYTES,
+                    text.len(),
+                ],
+                MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+            )?;
+            let mut output = Vec::with_capacity(length);
+            output.extend_from_slice(&[TAG_SET_SENSITIVE, selection.tag()]);
+            match seconds {
+                None => output.push(TAG_NONE),
+                Some(seconds) => {
+                    output.push(TAG_SOME);
+                    output.extend_from_slice(&seconds.to_be_bytes());
+                }
+            }
+            append_length_prefixed(&mut output, text.as_bytes())?;
+            Ok(output)
+        }
+        PlainRequest::GetVersioned { selection } => Ok(vec![TAG_GET_VERSIONED, selection.tag()]),
+        PlainRequest::OutcomeQuery { nonce } => Ok([&[TAG_OUTCOME_QUERY][..], nonce].concat()),
+        PlainRequest::SetVerified { selection, text } => {
+            encode_text_request(TAG_SET_VERIFIED, Some(*selection), text)
+        }
+    }
+}
+
+fn encode_text_request(
+    tag: u8,
+    selection: Option<Selection>,
+    text: &str,
+) -> Result<Vec<u8>, ProtocolError> {
+    let selection_bytes = if selection.is_some() {
+        SELECTION_BYTES
+    } else {
+        0
+    };
+    let length = checked_size(
+        &[
+            PLAIN_REQUEST_PREFIX_BYTES,
+            selection_bytes,
+            STRING_PREFIX_BYTES,
+            text.len(),
+        ],
+        MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+    )?;
+    let mut output = Vec::with_capacity(length);
+    output.push(tag);
+    if let Some(selection) = selection {
+        output.push(selection.tag());
+    }
+    append_length_prefixed(&mut output, text.as_bytes())?;
+    Ok(output)
+}
+
+fn decode_plain_request(payload: &[u8]) -> Result<PlainRequest, ProtocolError> {
+    checked_size(&[payload.len()], MAX_FRAME_BYTES - WIRE_PLAIN_PREFIX_BYTES)?;
+    let mut decoder = Decoder::new(payload);
+    let tag = decoder.read_u8()?;
+    let request = match tag {
+        TAG_PING => PlainRequest::Ping,
+        TAG_GET => PlainRequest::Get {
+            selection: Selection::from_tag(decoder.read_u8()?)?,
+        },
+        TAG_SET => PlainRequest::Set {
+            selection: Selection::Clipboard,
+            text: read_request_text(&mut decoder, 0)?,
+        },
+        TAG_SET_SELECTION => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            PlainRequest::Set {
+                selection,
+                text: read_request_text(&mut decoder, SELECTION_BYTES)?,
+            }
+        }
+        TAG_LEGACY => PlainRequest::Legacy {
+            text: read_request_text(&mut decoder, 0)?,
+        },
+        TAG_SET_STREAM => PlainRequest::SetStream {
+            selection: Selection::from_tag(decoder.read_u8()?)?,
+        },
+        TAG_GET_STREAM => PlainRequest::GetStream {
+            selection: Selection::from_tag(decoder.read_u8()?)?,
+        },
+        TAG_CLEAR => PlainRequest::Clear {
+            selection: Selection::from_tag(decoder.read_u8()?)?,
+        },
+        TAG_SUBSCRIBE => PlainRequest::Subscribe {
+            selection: Selection::from_tag(decoder.read_u8()?)?,
+            text: match decoder.read_u8()? {
+                0 => false,
+                SUBSCRIBE_FLAG_TEXT => true,
+                flags => return Err(ProtocolError::UnknownTag(flags)),
+            },
+        },
+        TAG_HISTORY_LIST => PlainRequest::HistoryList,
+        TAG_HISTORY_GET => PlainRequest::HistoryGet {
+            id: u64::from_be_bytes(decoder.read_array::<HISTORY_ID_BYTES>()?),
+        },
+        TAG_HISTORY_SEARCH => PlainRequest::HistorySearch {
+            query: read_request_text(&mut decoder, 0)?,
+        },
+        TAG_SLOT_SET => {
+            let name = SlotName::decode(&mut decoder)?;
+            let fixed = SLOT_NAME_PREFIX_BYTES + name.as_str().len();
+            PlainRequest::SlotSet {
+                name,
+                text: read_request_text(&mut decoder, fixed)?,
+            }
+        }
+        TAG_SLOT_GET => PlainRequest::SlotGet {
+            name: SlotName::decode(&mut decoder)?,
+        },
+        TAG_SLOT_LIST => PlainRequest::SlotList,
+        TAG_STATUS => PlainRequest::Status,
+        TAG_APPEND => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            let length = decoder.read_u8()? as usize;
+            let separator = std::str::from_utf8(decoder.read_bytes(length)?)
+                .map_err(|_| ProtocolError::InvalidUtf8)?
+                .to_owned();
+            let fixed = SELECTION_BYTES + SEPARATOR_PREFIX_BYTES + length;
+            PlainRequest::Append {
+                selection,
+                separator,
+                text: read_request_text(&mut decoder, fixed)?,
+            }
+        }
+        TAG_SET_VERSIONED => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            let expected = match decoder.read_u8()? {
+                TAG_NONE => None,
+                TAG_SOME => Some(u64::from_be_bytes(
+                    decoder.read_array::<GENERATION_BYTES>()?,
+                )),
+                tag => return Err(ProtocolError::UnknownTag(tag)),
+            };
+            let fixed = SELECTION_BYTES + 1 + expected.map_or(0, |_| GENERATION_BYTES);
+            PlainRequest::SetVersioned {
+                selection,
+                expected,
+                text: read_request_text(&mut decoder, fixed)?,
+            }
+        }
+        TAG_GET_VERSIONED => PlainRequest::GetVersioned {
+            selection: Selection::from_tag(decoder.read_u8()?)?,
+        },
+        TAG_SET_EXPIRING => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            let seconds = decoder.read_u32()?;
+            check_expiry(seconds)?;
+            PlainRequest::SetExpiring {
+                selection,
+                seconds,
+                text: read_request_text(&mut decoder, SELECTION_BYTES + EXPIRY_BYTES)?,
+            }
+        }
+        TAG_SET_SENSITIVE => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            let seconds = match decoder.read_u8()? {
+                TAG_NONE => None,
+                TAG_SOME => {
+                    let seconds = decoder.read_u32()?;
+                    check_expiry(seconds)?;
+                    Some(seconds)
+                }
+                tag => return Err(ProtocolError::UnknownTag(tag)),
+            };
+            let fixed = SELECTION_BYTES + 1 + seconds.map_or(0, |_| EXPIRY_BYTES);
+            PlainRequest::SetSensitive {
+                selection,
+                seconds,
+                text: read_request_text(&mut decoder, fixed)?,
+            }
+        }
+        TAG_OUTCOME_QUERY => PlainRequest::OutcomeQuery {
+            nonce: decoder.read_array::<NONCE_BYTES>()?,
+        },
+        TAG_SET_VERIFIED => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            PlainRequest::SetVerified {
+                selection,
+                text: read_request_text(&mut decoder, SELECTION_BYTES)?,
+            }
+        }
+        // Distinct from a malformed field: the frame is well formed but asks
+        // for something this daemon does not implement, and the daemon answers
+        // that with a refusal rather than by dropping the connection.
+        _ => return Err(ProtocolError::UnsupportedRequest(tag)),
+    };
+    decoder.finish()?;
+    Ok(request)
+}
+
+fn check_expiry(seconds: u32) -> Result<(), ProtocolError> {
+    if (1..=MAX_EXPIRY_SECONDS).contains(&seconds) {
+        Ok(())
+    } else {
+        Err(ProtocolError::InvalidExpiry(seconds))
+    }
+}
+
+fn read_request_text(decoder: &mut Decoder<'_>, fixed: usize) -> Result<String, ProtocolError> {
+    let maximum = MAX_FRAME_BYTES
+        - WIRE_PLAIN_PREFIX_BYTES
+        - PLAIN_REQUEST_PREFIX_BYTES
+        - fixed
+        - STRING_PREFIX_BYTES;
+    let bytes = decoder.read_length_prefixed(0, maximum)?;
+    Ok(std::str::from_utf8(bytes)
+        .map_err(|_| ProtocolError::InvalidUtf8)?
+        .to_owned())
+}
+
+// How big this particular ack is allowed to get: a data body carries the
+// clipboard, everything else is a fixed-vocabulary status line.
+fn ack_body_limit(ack: &Ack) -> usize {
+    if ack.text.is_some() {
+        MAX_DATA_ACK_BYTES
+    } else {
+        MAX_ACK_BYTES
+    }
+}
+
+fn wire_ack_limit(ack: &WireAck) -> usize {
+    match ack {
+        WireAck::Plain(ack) => ack_body_limit(ack),
+        // The body is opaque once sealed, so the frame bound is the wider one;
+        // the plaintext was already bounded by its own kind before sealing.
+        WireAck::Authenticated { .. } => MAX_DATA_ACK_BYTES,
+    }
+}
+
+fn encode_ack_body(ack: &Ack) -> Result<Vec<u8>, ProtocolError> {
+    let maximum = ack_body_limit(ack) - WIRE_PLAIN_PREFIX_BYTES;
+    let detail_bytes = ack.detail.as_deref().map(str::as_bytes);
+    let text_bytes = ack.text.as_deref().map(str::as_bytes);
+    let entries = ack.entries.as_deref();
+    let slots = ack.slots.as_deref();
+    if let Some(entries) = entries
+        && (text_bytes.is_some() || entries.len() > MAX_HISTORY_ENTRIES)
+    {
+        return Err(ProtocolError::InvalidLength(entries.len()));
+    }
+    if let Some(slots) = slots
+        && (text_bytes.is_some() || entries.is_some() || slots.len() > MAX_SLOTS)
+    {
+        return Err(ProtocolError::InvalidLength(slots.len()));
+    }
+    let report = match &ack.report {
+        Some(_) if text_bytes.is_some() || entries.is_some() || slots.is_some() => {
+            return Err(ProtocolError::InvalidLength(0));
+        }
+        Some(report) => {
+            let mut encoded = Vec::new();
+            report.encode(&mut encoded)?;
+            Some(encoded)
+        }
+        None => None,
+    };
+    if ack.generation.is_some() && (entries.is_some() || slots.is_some() || report.is_some()) {
+        return Err(ProtocolError::InvalidLength(GENERATION_BYTES));
+    }
+    let outcome = match ack.outcome {
+        Some(_)
+            if text_bytes.is_some()
+                || entries.is_some()
+                || slots.is_some()
+                || report.is_some()
+                || ack.generation.is_some() =>
+        {
+            return Err(ProtocolError::InvalidLength(0));
+        }
+        Some(outcome) => {
+            let mut encoded = Vec::new();
+            outcome.encode(&mut encoded);
+            Some(encoded)
+        }
+        None => None,
+    };
+    let code_bytes = ack.code.map_or(0, |_| ACK_CODE_BYTES);
+    let mut parts = vec![ACK_BODY_MIN_BYTES, code_bytes];
+    if let Some(detail) = detail_bytes {
+        // The detail keeps the status bound even in a data ack, matching what
+        // the decoder is willing to read back.
+        checked_size(
+            &[ACK_BODY_WITH_DETAIL_OVERHEAD, code_bytes, detail.len()],
+            MAX_ACK_BYTES - WIRE_PLAIN_PREFIX_BYTES,
+        )?;
+        parts.push(LENGTH_BYTES);
+        parts.push(detail.len());
+    }
+    if let Some(text) = text_bytes {
+        parts.push(LENGTH_BYTES);
+        parts.push(text.len());
+    }
+    if let Some(entries) = entries {
+        parts.push(LENGTH_BYTES);
+        parts.push(entries.len() * HISTORY_ENTRY_BYTES);
+    }
+    if let Some(slots) = slots {
+        parts.push(LENGTH_BYTES);
+        parts.push(slots.len() * MAX_SLOT_ENTRY_BYTES);
+    }
+    if let Some(report) = &report {
+        parts.push(report.len());
+    }
+    if ack.generation.is_some() {
+        parts.push(GENERATION_BYTES);
+    }
+    if let Some(outcome) = &outcome {
+        parts.push(outcome.len());
+    }
+    let length = checked_size(&parts, maximum)?;
+    let mut output = Vec::with_capacity(length);
+    let flags = ack.code.map_or(0, |_| ACK_FLAG_CODE);
+    output.push(
+        flags
+            | match (text_bytes, entries, slots, &report, ack.generation) {
+                (Some(_), _, _, _, Some(_)) => TAG_ACK_DATA_GENERATION_BODY,
+                (Some(_), _, _, _, None) => TAG_ACK_DATA_BODY,
+                (None, Some(_), _, _, _) => TAG_ACK_HISTORY_BODY,
+                (None, None, Some(_), _, _) => TAG_ACK_SLOTS_BODY,
+                (None, None, None, Some(_), _) => TAG_ACK_REPORT_BODY,
+                (None, None, None, None, Some(_)) => TAG_ACK_GENERATION_BODY,
+                (None, None, None, None, None) if outcome.is_some() => TAG_ACK_OUTCOME_BODY,
+                (None, None, None, None, None) => TAG_ACK_BODY,
+            },
+    );
+    output.push(u8::from(ack.ok));
+    if let Some(code) = ack.code {
+        output.extend_from_slice(&code.number().to_be_bytes());
+    }
+    match detail_bytes {
+        None => output.push(TAG_NONE),
+        Some(detail) => {
+            output.push(TAG_SOME);
+            append_length_prefixed(&mut output, detail)?;
+        }
+    }
+    if let Some(text) = text_bytes {
+        append_length_prefixed(&mut output, text)?;
+    }
+    if let Some(entries) = entries {
+        append_length(&mut output, entries.len())?;
+        for entry in entries {
+            output.extend_from_slice(&entry.id.to_be_bytes());
+            output.extend_from_slice(&entry.time.to_be_bytes());
+            output.push(entry.selection.tag());
+            output.push(entry.origin.tag());
+            output.extend_from_slice(&entry.size.to_be_bytes());
+        }
+    }
+    if let Some(slots) = slots {
+        append_length(&mut output, slots.len())?;
+        for slot in slots {
+            slot.name.encode(&mut output);
+            output.extend_from_slice(&slot.time.to_be_bytes());
+            output.extend_from_slice(&slot.size.to_be_bytes());
+        }
+    }
+    if let Some(report) = report {
+        output.extend_from_slice(&report);
+    }
+    if let Some(generation) = ack.generation {
+        output.extend_from_slice(&generation.to_be_bytes());
+    }
+    if let Some(outcome) = outcome {
+        output.extend_from_slice(&outcome);
+    }
+    Ok(output)
+}
+
+fn decode_ack_body(payload: &[u8]) -> Result<Ack, ProtocolError> {
+    // The body tag decides the bound before a single length is trusted, so a
+    // status ack still cannot claim more than MAX_ACK_BYTES.
+    let first = *payload.first().ok_or(ProtocolError::UnexpectedEof)?;
+    let code_bytes = if first & ACK_FLAG_CODE == 0 {
+        0
+    } else {
+        ACK_CODE_BYTES
+    };
+    let body_tag = first & !ACK_FLAG_CODE;
+    let maximum = match body_tag {
+        TAG_ACK_BODY
+        | TAG_ACK_HISTORY_BODY
+        | TAG_ACK_SLOTS_BODY
+        | TAG_ACK_REPORT_BODY
+        | TAG_ACK_GENERATION_BODY
+        | TAG_ACK_OUTCOME_BODY => MAX_ACK_BYTES,
+        TAG_ACK_DATA_BODY | TAG_ACK_DATA_GENERATION_BODY => MAX_DATA_ACK_BYTES,
+        _ => return Err(ProtocolError::UnknownTag(first)),
+    } - WIRE_PLAIN_PREFIX_BYTES;
+    checked_size(&[payload.len()], maximum)?;
+    let mut decoder = Decoder::new(payload);
+    decoder.read_u8()?;
+    let ok = match decoder.read_u8()? {
+        0 => false,
+        1 => true,
+        value => return Err(ProtocolError::InvalidBoolean(value)),
+    };
+    let code = if code_bytes == 0 {
+        None
+    } else {
+        let number = u16::from_be_bytes(decoder.read_array::<ACK_CODE_BYTES>()?);
+        Some(AckCode::from_number(ok, number))
+    };
+    let detail = match decoder.read_u8()? {
+        TAG_NONE => None,
+        TAG_SOME => {
+            // A detail is a short status word whichever body carries it, so it
+            // keeps the tight bound even inside a data ack.
+            let bytes = decoder.read_length_prefixed(
+                0,
+                MAX_ACK_BYTES
+                    - WIRE_PLAIN_PREFIX_BYTES
+                    - ACK_BODY_WITH_DETAIL_OVERHEAD
+                    - code_bytes,
+            )?;
+            Some(
+                std::str::from_utf8(bytes)
+                    .map_err(|_| ProtocolError::InvalidUtf8)?
+                    .to_owned(),
+            )
+        }
+        tag => return Err(ProtocolError::UnknownTag(tag)),
+    };
+    let versioned = matches!(
+        body_tag,
+        TAG_ACK_GENERATION_BODY | TAG_ACK_DATA_GENERATION_BODY
+    );
+    let text = if matches!(body_tag, TAG_ACK_DATA_BODY | TAG_ACK_DATA_GENERATION_BODY) {
+        let generation_bytes = if versioned { GENERATION_BYTES } else { 0 };
+        let bytes = decoder.read_length_prefixed(
+            0,
+            maximum - ACK_BODY_WITH_TEXT_OVERHEAD - generation_bytes - code_bytes,
+        )?;
+        Some(
+            std::str::from_utf8(bytes)
+                .map_err(|_| ProtocolError::InvalidUtf8)?
+                .to_owned(),
+        )
+    } else {
+        None
+    };
+    let entries = if body_tag == TAG_ACK_HISTORY_BODY {
+        let count = decoder.read_u32()? as usize;
+        if count > MAX_HISTORY_ENTRIES {
+            return Err(ProtocolError::InvalidLength(count));
+        }
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push(HistoryEntry {
+                id: u64::from_be_bytes(decoder.read_array::<HISTORY_ID_BYTES>()?),
+                time: u64::from_be_bytes(decoder.read_array::<8>()?),
+                selection: Selection::from_tag(decoder.read_u8()?)?,
+                origin: Origin::from_tag(decoder.read_u8()?)?,
+                size: u64::from_be_bytes(decoder.read_array::<8>()?),
+            });
+        }
+        Some(entries)
+    } else {
+        None
+    };
+    let slots = if body_tag == TAG_ACK_SLOTS_BODY {
+        let count = decoder.read_u32()? as usize;
+        if count > MAX_SLOTS {
+            return Err(ProtocolError::InvalidLength(count));
+        }
+        let mut slots = Vec::with_capacity(count);
+        for _ in 0..count {
+            slots.push(SlotEntry {
+                name: SlotName::decode(&mut decoder)?,
+                time: u64::from_be_bytes(decoder.read_array::<8>()?),
+                size: u64::from_be_bytes(decoder.read_array::<8>()?),
+            });
+        }
+        Some(slots)
+    } else {
+        None
+    };
+    let report = if body_tag == TAG_ACK_REPORT_BODY {
+        Some(Box::new(StatusReport::decode(&mut decoder)?))
+    } else {
+        None
+    };
+    let generation = if versioned {
+        Some(u64::from_be_bytes(
+            decoder.read_array::<GENERATION_BYTES>()?,
+        ))
+    } else {
+        None
+    };
+    let outcome = if body_tag == TAG_ACK_OUTCOME_BODY {
+        Some(WriteOutcome::decode(&mut decoder)?)
+    } else {
+        None
+    };
+    decoder.finish()?;
+    Ok(Ack {
+        ok,
+        detail,
+        text,
+        entries,
+        slots,
+        report,
+        generation,
+        outcome,
+        code,
+    })
+}
+
+fn encode_wire_request(request: &WireRequest) -> Result<Vec<u8>, ProtocolError> {
+    match request {
+        WireRequest::Plain(request) => {
+            let body = encode_plain_request(request)?;
+            let length = checked_size(&[WIRE_PLAIN_PREFIX_BYTES, body.len()], MAX_FRAME_BYTES)?;
+            let mut output = Vec::with_capacity(length);
+            output.push(TAG_REQUEST_PLAIN);
+            output.extend_from_slice(&body);
+            Ok(output)
+        }
+        WireRequest::Authenticated {
+            key,
+            nonce,
+            ciphertext,
+        } => {
+            let overhead = match key {
+                Some(_) => WIRE_REQUEST_KEYED_OVERHEAD,
+                None => WIRE_REQUEST_AUTH_OVERHEAD,
+            };
+            let length = checked_size(&[overhead, ciphertext.len()], MAX_FRAME_BYTES)?;
+            if ciphertext.len() < MIN_REQUEST_CIPHERTEXT_BYTES {
+                return Err(ProtocolError::InvalidLength(ciphertext.len()));
+            }
+            let mut output = Vec::with_capacity(length);
+            match key {
+                Some(key) => {
+                    output.push(TAG_REQUEST_KEYED);
+                    output.extend_from_slice(key);
+                }
+                None => output.push(TAG_REQUEST_AUTHENTICATED),
+            }
+            output.extend_from_slice(nonce);
+            append_length_prefixed(&mut output, ciphertext)?;
+            Ok(output)
+        }
+    }
+}
+
+fn decode_wire_request(payload: &[u8]) -> Result<WireRequest, ProtocolError> {
+    validate_length(payload.len())?;
+    let mut decoder = Decoder::new(payload);
+    match decoder.read_u8()? {
+        TAG_REQUEST_PLAIN => decode_plain_request(decoder.remaining()).map(WireRequest::Plain),
+        tag @ (TAG_REQUEST_AUTHENTICATED | TAG_REQUEST_KEYED) => {
+            let (key, overhead) = if tag == TAG_REQUEST_KEYED {
+                let key = decoder.read_array::<KEY_ID_BYTES>()?;
+                (Some(key), WIRE_REQUEST_KEYED_OVERHEAD)
+            } else {
+                (None, WIRE_REQUEST_AUTH_OVERHEAD)
+            };
+            let nonce = decoder.read_array::<NONCE_BYTES>()?;
+            let maximum = MAX_FRAME_BYTES - overhead;
+            let ciphertext = decoder
+                .read_length_prefixed(MIN_REQUEST_CIPHERTEXT_BYTES, maximum)?
+                .to_vec();
+            decoder.finish()?;
+            Ok(WireRequest::Authenticated {
+                key,
+                nonce,
+                ciphertext,
+            })
+        }
+        tag => Err(ProtocolError::UnknownTag(tag)),
+    }
+}
+
+// The bare hello is a prefix of the described one, so the server description
+// is a tail that only a describe frame asks for.  The version leads the tail:
+// a newer daemon may append fields after the ones known here, and those are
+// skipped rather than rejected, while a hello claiming this build's own
+// revision is decoded as strictly as every other message.
+fn encode_server_hello(hello: &ServerHello) -> Vec<u8> {
+    let mut output =
+        Vec::with_capacity(HELLO_BYTES + HELLO_INFO_BYTES + HELLO_KDF_BYTES + OFFER_BYTES);
+    output.push(TAG_SERVER_HELLO);
+    output.extend_from_slice(&hello.challenge);
+    if let Some(info) = &hello.info {
+        output.extend_from_slice(&info.version.to_be_bytes());
+        output.extend_from_slice(&info.capabilities.bits().to_be_bytes());
+        output.extend_from_slice(&info.max_text_bytes.to_be_bytes());
+        let mut flags = 0;
+        if info.token_configured == Some(true) {
+            flags |= HELLO_FLAG_TOKEN;
+        }
+        if info.key_derivation != KeyDerivation::Sha256 {
+            flags |= HELLO_FLAG_KDF;
+        }
+        if hello.host.is_some() {
+            flags |= HELLO_FLAG_HOST_KEY;
+        }
+        output.push(flags);
+        output.extend_from_slice(&info.max_stream_bytes.to_be_bytes());
+        info.key_derivation.encode(&mut output);
+        if let Some(host) = &hello.host {
+            host.encode(&mut output);
+        }
+    }
+    output
+}
+
+fn decode_server_hello(payload: &[u8]) -> Result<ServerHello, ProtocolError> {
+    validate_ack_length(payload.len(), MAX_ACK_BYTES)?;
+    let mut decoder = Decoder::new(payload);
+    let tag = decoder.read_u8()?;
+    if tag != TAG_SERVER_HELLO {
+        return Err(ProtocolError::UnknownTag(tag));
+    }
+    let challenge = decoder.read_array::<CHALLENGE_BYTES>()?;
+    if decoder.remaining().is_empty() {
+        return Ok(ServerHello {
+            challenge,
+            info: None,
+            host: None,
+        });
+    }
+    let version = u16::from_be_bytes(decoder.read_array::<2>()?);
+    if version < 2 {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    let capabilities = Capabilities::from_bits(u64::from_be_bytes(decoder.read_array::<8>()?));
+    let max_text_bytes = decoder.read_u32()?;
+    let flags = decoder.read_u8()?;
+    let max_stream_bytes = u64::from_be_bytes(decoder.read_array::<8>()?);
+    let key_derivation = if flags & HELLO_FLAG_KDF != 0 {
+        KeyDerivation::decode(&mut decoder)?
+    } else {
+        KeyDerivation::Sha256
+    };
+    let host = if flags & HELLO_FLAG_HOST_KEY != 0 {
+        Some(HostOffer::decode(&mut decoder)?)
+    } else {
+        None
+    };
+    if version == PROTOCOL_VERSION {
+        if flags & !HELLO_KNOWN_FLAGS != 0 {
+            return Err(ProtocolError::UnknownTag(flags));
+        }
+        decoder.finish()?;
+    }
+    Ok(ServerHello {
+        challenge,
+        info: Some(ServerInfo {
+            version,
+            capabilities,
+            max_text_bytes,
+            token_configured: Some(flags & HELLO_FLAG_TOKEN != 0),
+            max_stream_bytes,
+            key_derivation,
+        }),
+        host,
+    })
+}
+
+// Extensions are type, length, value, so that a later revision can say more
+// than "session" without another frame.  A daemon only ever receives the
+// extensions it advertised the capability for, so anything it does not know,
+// or sees twice, is a malformed hello rather than something to skip.
+fn encode_client_hello(hello: &ClientHello) -> Vec<u8> {
+    let mut output =
+        Vec::with_capacity(CLIENT_HELLO_BYTES + 3 * CLIENT_EXTENSION_HEADER_BYTES + OFFER_BYTES);
+    output.push(TAG_CLIENT_HELLO);
+    output.extend_from_slice(&hello.version.to_be_bytes());
+    if hello.session {
+        output.push(CLIENT_EXTENSION_SESSION);
+        output.extend_from_slice(&0_u16.to_be_bytes());
+    }
+    if let Some(offer) = &hello.offer {
+        output.push(CLIENT_EXTENSION_KEY_EXCHANGE);
+        output.extend_from_slice(&(OFFER_BYTES as u16).to_be_bytes());
+        offer.encode(&mut output);
+    }
+    if let Some(ephemeral) = &hello.ephemeral {
+        output.push(CLIENT_EXTENSION_EPHEMERAL);
+        output.extend_from_slice(&(EXCHANGE_KEY_BYTES as u16).to_be_bytes());
+        output.extend_from_slice(ephemeral);
+    }
+    if hello.ack_codes {
+        output.push(CLIENT_EXTENSION_ACK_CODES);
+        output.extend_from_slice(&0_u16.to_be_bytes());
+    }
+    output
+}
+
+fn decode_client_hello(payload: &[u8]) -> Result<ClientHello, ProtocolError> {
+    validate_ack_length(payload.len(), MAX_ACK_BYTES)?;
+    let mut decoder = Decoder::new(payload);
+    let tag = decoder.read_u8()?;
+    if tag != TAG_CLIENT_HELLO {
+        return Err(ProtocolError::UnknownTag(tag));
+    }
+    let version = u16::from_be_bytes(decoder.read_array::<2>()?);
+    if version < 2 {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    let mut hello = ClientHello {
+        version,
+        session: false,
+        offer: None,
+        ephemeral: None,
+        ack_codes: false,
+    };
+    while !decoder.remaining().is_empty() {
+        let extension = decoder.read_u8()?;
+        let length = u16::from_be_bytes(decoder.read_array::<2>()?) as usize;
+        let value = decoder.read_bytes(length)?;
+        match extension {
+            CLIENT_EXTENSION_SESSION if !hello.session => {
+                if !value.is_empty() {
+                    return Err(ProtocolError::InvalidLength(value.len()));
+                }
+                hello.session = true;
+            }
+            CLIENT_EXTENSION_KEY_EXCHANGE if hello.offer.is_none() => {
+                if value.len() != OFFER_BYTES {
+                    return Err(ProtocolError::InvalidLength(value.len()));
+                }
+                let mut value = Decoder::new(value);
+                hello.offer = Some(ClientOffer::decode(&mut value)?);
+                value.finish()?;
+            }
+            CLIENT_EXTENSION_EPHEMERAL if hello.ephemeral.is_none() => {
+                let value = <[u8; EXCHANGE_KEY_BYTES]>::try_from(value)
+                    .map_err(|_| ProtocolError::InvalidLength(value.len()))?;
+                hello.ephemeral = Some(value);
+            }
+            CLIENT_EXTENSION_ACK_CODES if !hello.ack_codes => {
+                if !value.is_empty() {
+                    return Err(ProtocolError::InvalidLength(value.len()));
+                }
+                hello.ack_codes = true;
+            }
+            extension => return Err(ProtocolError::UnknownTag(extension)),
+        }
+    }
+    // An identity's exchange already keeps its keys to the connection; a
+    // token mixed into it as well would be a second answer to one question.
+    if hello.offer.is_some() && hello.ephemeral.is_some() {
+        return Err(ProtocolError::UnknownTag(CLIENT_EXTENSION_EPHEMERAL));
+    }
+    Ok(hello)
+}
+
+fn encode_wire_ack(ack: &WireAck) -> Result<Vec<u8>, ProtocolError> {
+    let maximum = wire_ack_limit(ack);
+    match ack {
+        WireAck::Plain(ack) => {
+            let body = encode_ack_body(ack)?;
+            let length = checked_size(&[WIRE_PLAIN_PREFIX_BYTES, body.len()], maximum)?;
+            let mut output = Vec::with_capacity(length);
+            output.push(TAG_ACK_PLAIN);
+            output.extend_from_slice(&body);
+            Ok(output)
+        }
+        WireAck::Authenticated {
+            request_nonce,
+            nonce,
+            ciphertext,
+        } => {
+            let length = checked_size(&[WIRE_ACK_AUTH_OVERHEAD, ciphertext.len()], maximum)?;
+            if ciphertext.len() < MIN_ACK_CIPHERTEXT_BYTES {
+                return Err(ProtocolError::InvalidLength(ciphertext.len()));
+            }
+            let mut output = Vec::with_capacity(length);
+            output.push(TAG_ACK_AUTHENTICATED);
+            output.extend_from_slice(request_nonce);
+            output.extend_from_slice(nonce);
+            append_length_prefixed(&mut output, ciphertext)?;
+            Ok(output)
+        }
+    }
+}
+
+fn decode_wire_ack(payload: &[u8], limit: usize) -> Result<WireAck, ProtocolError> {
+    validate_ack_length(payload.len(), limit)?;
+    let mut decoder = Decoder::new(payload);
+    match decoder.read_u8()? {
+        TAG_ACK_PLAIN => decode_ack_body(decoder.remaining()).map(WireAck::Plain),
+        TAG_ACK_AUTHENTICATED => {
+            let request_nonce = decoder.read_array::<NONCE_BYTES>()?;
+            let nonce = decoder.read_array::<NONCE_BYTES>()?;
+            let maximum = limit - WIRE_ACK_AUTH_OVERHEAD;
+            let ciphertext = decoder
+                .read_length_prefixed(MIN_ACK_CIPHERTEXT_BYTES, maximum)?
+                .to_vec();
+            decoder.finish()?;
+            Ok(WireAck::Authenticated {
+                request_nonce,
+                nonce,
+                ciphertext,
+            })
+        }
+        tag => Err(ProtocolError::UnknownTag(tag)),
+    }
+}
+
+// The index and the last flag travel in the clear so that a receiver knows
+// when to stop reading; sealing binds both, so neither can be altered.
+fn encode_chunk(chunk: &WireChunk) -> Result<Vec<u8>, ProtocolError> {
+    let (tag, index, last) = match chunk {
+        WireChunk::Plain(chunk) => (TAG_CHUNK_PLAIN, chunk.index, chunk.last),
+        WireChunk::Authenticated { index, last, .. } => (TAG_CHUNK_AUTHENTICATED, *index, *last),
+    };
+    let mut output = Vec::with_capacity(MAX_CHUNK_PAYLOAD_BYTES);
+    output.push(tag);
+    output.extend_from_slice(&index.to_be_bytes());
+    output.push(if last { CHUNK_FLAG_LAST } else { 0 });
+    match chunk {
+        WireChunk::Plain(chunk) => {
+            check_chunk_length(chunk.text.len(), last)?;
+            append_length_prefixed(&mut output, chunk.text.as_bytes())?;
+        }
+        WireChunk::Authenticated {
+            nonce, ciphertext, ..
+        } => {
+            check_chunk_length(ciphertext.len().saturating_sub(AEAD_TAG_BYTES), last)?;
+            if ciphertext.len() < AEAD_TAG_BYTES {
+                return Err(ProtocolError::InvalidLength(ciphertext.len()));
+            }
+            output.extend_from_slice(nonce);
+            append_length_prefixed(&mut output, ciphertext)?;
+        }
+    }
+    Ok(output)
+}
+
+fn check_chunk_length(length: usize, last: bool) -> Result<(), ProtocolError> {
+    let minimum = if last { 0 } else { MIN_CHUNK_BYTES };
+    if length < minimum || length > CHUNK_BYTES {
+        Err(ProtocolError::InvalidLength(length))
+    } else {
+        Ok(())
+    }
+}
+
+fn decode_chunk(payload: &[u8]) -> Result<WireChunk, ProtocolError> {
+    validate_ack_length(payload.len(), MAX_CHUNK_PAYLOAD_BYTES)?;
+    let mut decoder = Decoder::new(payload);
+    let tag = decoder.read_u8()?;
+    let index = decoder.read_u32()?;
+    let last = match decoder.read_u8()? {
+        0 => false,
+        CHUNK_FLAG_LAST => true,
+        flags => return Err(ProtocolError::UnknownTag(flags)),
+    };
+    let minimum = if last { 0 } else { MIN_CHUNK_BYTES };
+    let chunk = match tag {
+        TAG_CHUNK_PLAIN => {
+            let bytes = decoder.read_length_prefixed(minimum, CHUNK_BYTES)?;
+            let text = std::str::from_utf8(bytes)
+                .map_err(|_| ProtocolError::InvalidUtf8)?
+                .to_owned();
+            WireChunk::Plain(Chunk { index, last, text })
+        }
+        TAG_CHUNK_AUTHENTICATED => {
+            let nonce = decoder.read_array::<NONCE_BYTES>()?;
+            let ciphertext = decoder
+                .read_length_prefixed(minimum + AEAD_TAG_BYTES, CHUNK_BYTES + AEAD_TAG_BYTES)?
+                .to_vec();
+            WireChunk::Authenticated {
+                index,
+                last,
+                nonce,
+                ciphertext,
+            }
+        }
+        tag => return Err(ProtocolError::UnknownTag(tag)),
+    };
+    decoder.finish()?;
+    Ok(chunk)
+}
+
+fn seal_chunk(
+    key: &[u8; KEY_BYTES],
+    aad: &[u8],
+    chunk: &Chunk,
+    nonce: Nonce,
+) -> Result<WireChunk, ProtocolError> {
+    check_chunk_length(chunk.text.len(), chunk.last)?;
+    Ok(WireChunk::Authenticated {
+        index: chunk.index,
+        last: chunk.last,
+        nonce,
+        ciphertext: encrypt(key, &nonce, chunk.text.as_bytes(), aad)?,
+    })
+}
+
+// The AAD is built from the index the receiver expects, not the one on the
+// wire; the comparison only gives a reordered stream its own error.
+fn open_chunk(
+    key: &[u8; KEY_BYTES],
+    expected_index: u32,
+    chunk: &WireChunk,
+    aad: impl FnOnce(bool) -> Vec<u8>,
+) -> Result<Chunk, ProtocolError> {
+    let WireChunk::Authenticated {
+        index,
+        last,
+        nonce,
+        ciphertext,
+    } = chunk
+    else {
+        return Err(ProtocolError::UnexpectedProtection);
+    };
+    if *index != expected_index {
+        return Err(ProtocolError::ChunkOrder(*index));
+    }
+    let plaintext = decrypt(key, nonce, ciphertext, &aad(*last))?;
+    Ok(Chunk {
+        index: *index,
+        last: *last,
+        text: String::from_utf8(plaintext).map_err(|_| ProtocolError::InvalidUtf8)?,
+    })
+}
+
+/// Takes the chunk expected at `index` of a stream that has no token to seal
+/// it with.
+pub fn open_plain_chunk(index: u32, chunk: WireChunk) -> Result<Chunk, ProtocolError> {
+    match chunk {
+        WireChunk::Plain(chunk) if chunk.index == index => Ok(chunk),
+        WireChunk::Plain(chunk) => Err(ProtocolError::ChunkOrder(chunk.index)),
+        WireChunk::Authenticated { .. } => Err(ProtocolError::UnexpectedProtection),
+    }
+}
+
+// A heartbeat is `[TAG_NONE]`.  A change is `[TAG_SOME, selection, size, hash]`
+// followed by its text the way an ack carries its detail: `TAG_NONE`, or
+// `TAG_SOME` and a length-prefixed string, whose length must be the size.
+fn encode_event_body(change: Option<&Change>) -> Result<Vec<u8>, ProtocolError> {
+    let Some(change) = change else {
+        return Ok(vec![TAG_NONE]);
+    };
+    let text = change.text.as_deref().map(str::as_bytes);
+    if let Some(text) = text
+        && (text.len() > MAX_EVENT_TEXT_BYTES || text.len() as u64 != change.size)
+    {
+        return Err(ProtocolError::InvalidLength(text.len()));
+    }
+    let mut output =
+        Vec::with_capacity(CHANGE_BYTES + text.map_or(0, |text| LENGTH_BYTES + text.len()));
+    output.push(TAG_SOME);
+    output.push(change.selection.tag());
+    output.extend_from_slice(&change.size.to_be_bytes());
+    output.extend_from_slice(&change.hash);
+    match text {
+        None => output.push(TAG_NONE),
+        Some(text) => {
+            output.push(TAG_SOME);
+            append_length_prefixed(&mut output, text)?;
+        }
+    }
+    Ok(output)
+}
+
+fn decode_event_body(payload: &[u8]) -> Result<Option<Change>, ProtocolError> {
+    let mut decoder = Decoder::new(payload);
+    let change = match decoder.read_u8()? {
+        TAG_NONE => None,
+        TAG_SOME => {
+            let selection = Selection::from_tag(decoder.read_u8()?)?;
+            let size = u64::from_be_bytes(decoder.read_array::<8>()?);
+            let hash = decoder.read_array::<HASH_BYTES>()?;
+            let text = match decoder.read_u8()? {
+                TAG_NONE => None,
+                TAG_SOME => {
+                    let bytes = decoder.read_length_prefixed(0, MAX_EVENT_TEXT_BYTES)?;
+                    if bytes.len() as u64 != size {
+                        return Err(ProtocolError::InvalidLength(bytes.len()));
+                    }
+                    Some(
+                        std::str::from_utf8(bytes)
+                            .map_err(|_| ProtocolError::InvalidUtf8)?
+                            .to_owned(),
+                    )
+                }
+                tag => return Err(ProtocolError::UnknownTag(tag)),
+            };
+            Some(Change {
+                selection,
+                size,
+                hash,
+                text,
+            })
+        }
+        tag => return Err(ProtocolError::UnknownTag(tag)),
+    };
+    decoder.finish()?;
+    Ok(change)
+}
+
+fn encode_event(event: &SealedEvent) -> Result<Vec<u8>, ProtocolError> {
+    let length = checked_size(
+        &[
+            EVENT_HEADER_BYTES,
+            NONCE_BYTES,
+            LENGTH_BYTES,
+            event.ciphertext.len(),
+        ],
+        MAX_EVENT_PAYLOAD_BYTES,
+    )?;
+    if event.ciphertext.len() <= AEAD_TAG_BYTES {
+        return Err(ProtocolError::InvalidLength(event.ciphertext.len()));
+    }
+    let mut output = Vec::with_capacity(length);
+    output.push(TAG_EVENT);
+    output.extend_from_slice(&event.index.to_be_bytes());
+    output.extend_from_slice(&event.nonce);
+    append_length_prefixed(&mut output, &event.ciphertext)?;
+    Ok(output)
+}
+
+fn decode_event(payload: &[u8]) -> Result<SealedEvent, ProtocolError> {
+    validate_ack_length(payload.len(), MAX_EVENT_PAYLOAD_BYTES)?;
+    let mut decoder = Decoder::new(payload);
+    let tag = decoder.read_u8()?;
+    if tag != TAG_EVENT {
+        return Err(ProtocolError::UnknownTag(tag));
+    }
+    let index = decoder.read_u32()?;
+    let nonce = decoder.read_array::<NONCE_BYTES>()?;
+    let ciphertext = decoder
+        .read_length_prefixed(AEAD_TAG_BYTES + 1, MAX_EVENT_BODY_BYTES + AEAD_TAG_BYTES)?
+        .to_vec();
+    decoder.finish()?;
+    Ok(SealedEvent {
+        index,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// The SHA-256 of `text`, as a change event reports it.  A subscriber that
+/// knows what it last wrote can recognise its own write without the text
+/// crossing the wire.
+pub fn content_hash(text: &str) -> ContentHash {
+    Sha256::digest(text.as_bytes()).into()
+}
+
+/// Seals one history entry and its text for the store: `[nonce, ciphertext]`.
+///
+/// Everything about the entry is inside the seal, its time and selection as
+/// much as its text, since those alone say when the user copied something.
+pub fn seal_store_record(
+    key: &StoreKey,
+    entry: &HistoryEntry,
+    text: &str,
+) -> Result<Vec<u8>, ProtocolError> {
+    let length = checked_size(
+        &[STORE_RECORD_HEADER_BYTES, STRING_PREFIX_BYTES, text.len()],
+        MAX_FRAME_BYTES,
+    )?;
+    let mut plaintext = Vec::with_capacity(length);
+    plaintext.extend_from_slice(&entry.id.to_be_bytes());
+    plaintext.extend_from_slice(&entry.time.to_be_bytes());
+    plaintext.push(entry.selection.tag());
+    plaintext.push(entry.origin.tag());
+    append_length_prefixed(&mut plaintext, text.as_bytes())?;
+    let nonce = random_nonce()?;
+    let ciphertext = encrypt(&key.0, &nonce, &plaintext, STORE_RECORD_AAD)?;
+    Ok([&nonce[..], &ciphertext].concat())
+}
+
+/// Opens a record [`seal_store_record`] made, with the same key.
+pub fn open_store_record(
+    key: &StoreKey,
+    record: &[u8],
+) -> Result<(HistoryEntry, String), ProtocolError> {
+    let mut decoder = Decoder::new(record);
+    let nonce = decoder.read_array::<NONCE_BYTES>()?;
+    let plaintext = decrypt(&key.0, &nonce, decoder.remaining(), STORE_RECORD_AAD)?;
+    let mut decoder = Decoder::new(&plaintext);
+    let id = u64::from_be_bytes(decoder.read_array::<HISTORY_ID_BYTES>()?);
+    let time = u64::from_be_bytes(decoder.read_array::<8>()?);
+    let selection = Selection::from_tag(decoder.read_u8()?)?;
+    let origin = Origin::from_tag(decoder.read_u8()?)?;
+    let text = decoder.read_length_prefixed(0, MAX_FRAME_BYTES)?;
+    let text = std::str::from_utf8(text)
+        .map_err(|_| ProtocolError::InvalidUtf8)?
+        .to_owned();
+    decoder.finish()?;
+    let entry = HistoryEntry {
+        id,
+        time,
+        selection,
+        origin,
+        size: text.len() as u64,
+    };
+    Ok((entry, text))
+}
+
+/// Splits `text` into the pieces a stream carries, each at most
+/// [`CHUNK_BYTES`] and ending on a character boundary.  Empty text is one
+/// empty chunk, so that every stream has a last one.
+pub fn text_chunks(text: &str) -> Vec<&str> {
+    split_text(text, CHUNK_BYTES)
+}
+
+fn split_text(mut text: &str, size: usize) -> Vec<&str> {
+    let mut chunks = Vec::with_capacity(text.len() / size + 1);
+    while text.len() > size {
+        let mut end = size;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, rest) = text.split_at(end);
+        chunks.push(chunk);
+        text = rest;
+    }
+    chunks.push(text);
+    chunks
+}
+
+fn encrypt(
+    key: &[u8; KEY_BYTES],
+    nonce: &Nonce,
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    cipher(key)?
+        .encrypt(
+            &AesNonce::from(*nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| ProtocolError::AuthenticationFailed)
+}
+
+fn decrypt(
+    key: &[u8; KEY_BYTES],
+    nonce: &Nonce,
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, ProtocolError> {
+    cipher(key)?
+        .decrypt(
+            &AesNonce::from(*nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| ProtocolError::AuthenticationFailed)
+}
+
+fn request_aad(challenge: &Challenge) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(REQUEST_AAD.len() + challenge.len());
+    aad.extend_from_slice(REQUEST_AAD);
+    aad.extend_from_slice(challenge);
+    aad
+}
+
+fn ack_aad(challenge: &Challenge, request_nonce: &Nonce) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(ACK_AAD.len() + challenge.len() + request_nonce.len());
+    aad.extend_from_slice(ACK_AAD);
+    aad.extend_from_slice(challenge);
+    aad.extend_from_slice(request_nonce);
+    aad
+}
+
+// The transcript stands in for the challenge, which it already covers, and the
+// sequence number is what a session adds: it is the only thing that tells one
+// request of a session apart from the next.
+fn session_aad(domain: &[u8], session: &Session, request_nonce: Option<&Nonce>) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(domain.len() + TRANSCRIPT_BYTES + 8 + NONCE_BYTES);
+    aad.extend_from_slice(domain);
+    aad.extend_from_slice(&session.transcript);
+    aad.extend_from_slice(&session.sequence.to_be_bytes());
+    if let Some(request_nonce) = request_nonce {
+        aad.extend_from_slice(request_nonce);
+    }
+    aad
+}
+
+pub fn seal_request(
+    keys: &AuthKeys,
+    challenge: &Challenge,
+    request: &PlainRequest,
+) -> Result<(WireRequest, Nonce), ProtocolError> {
+    Binding::Connection(challenge).seal_request(keys, request, Compression::Off)
+}
+
+#[cfg(test)]
+fn seal_request_with_nonce(
+    keys: &AuthKeys,
+    challenge: &Challenge,
+    request: &PlainRequest,
+    nonce: Nonce,
+) -> Result<(WireRequest, Nonce), ProtocolError> {
+    let aad = request_aad(challenge);
+    seal_request_with_aad(keys, &aad, request, Compression::Off, nonce)
+}
+
+// The largest sealed request body, before any compression and after it.
+const MAX_SEALED_REQUEST_BODY_BYTES: usize =
+    MAX_FRAME_BYTES - WIRE_REQUEST_AUTH_OVERHEAD - AEAD_TAG_BYTES;
+
+fn seal_request_with_aad(
+    keys: &AuthKeys,
+    aad: &[u8],
+    request: &PlainRequest,
+    compression: Compression,
+    nonce: Nonce,
+) -> Result<(WireRequest, Nonce), ProtocolError> {
+    let body = encode_plain_request(request)?;
+    checked_size(&[body.len()], MAX_SEALED_REQUEST_BODY_BYTES)?;
+    let plaintext = match compression {
+        Compression::Off => body,
+        Compression::Deflate => wrap_request_body(body),
+    };
+    let ciphertext = encrypt(&keys.request, &nonce, &plaintext, aad)?;
+    Ok((
+        WireRequest::Authenticated {
+            key: None,
+            nonce,
+            ciphertext,
+        },
+        nonce,
+    ))
+}
+
+pub fn open_request(
+    keys: &AuthKeys,
+    challenge: &Challenge,
+    nonce: &Nonce,
+    ciphertext: &[u8],
+) -> Result<PlainRequest, ProtocolError> {
+    Binding::Connection(challenge)
+        .open_request(keys, nonce, ciphertext)
+        .map(|(request, _)| request)
+}
+
+fn open_request_with_aad(
+    keys: &AuthKeys,
+    aad: &[u8],
+    nonce: &Nonce,
+    ciphertext: &[u8],
+) -> Result<(PlainRequest, Compression), ProtocolError> {
+    if ciphertext.len() < MIN_REQUEST_CIPHERTEXT_BYTES
+        || ciphertext.len() > MAX_FRAME_BYTES - WIRE_REQUEST_AUTH_OVERHEAD
+    {
+        return Err(ProtocolError::InvalidLength(ciphertext.len()));
+    }
+    let plaintext = decrypt(&keys.request, nonce, ciphertext, aad)?;
+    let Some((&TAG_COMPRESSED, wrapped)) = plaintext.split_first() else {
+        return Ok((decode_plain_request(&plaintext)?, Compression::Off));
+    };
+    let (&flags, body) = wrapped.split_first().ok_or(ProtocolError::UnexpectedEof)?;
+    let request = match flags {
+        0 => decode_plain_request(body)?,
+        COMPRESSED_FLAG_DEFLATE => {
+            decode_plain_request(&inflate(body, MAX_SEALED_REQUEST_BODY_BYTES)?)?
+        }
+        flags => return Err(ProtocolError::UnknownTag(flags)),
+    };
+    Ok((request, Compression::Deflate))
+}
+
+// A request that offers compression is wrapped whether or not its own body
+// shrinks, because the wrapper is the offer: `[TAG_COMPRESSED, flags, body]`.
+// Text at the very limit has no room for the two bytes, and goes unwrapped
+// instead; all it loses is the offer, which only ever pays off for a Get.
+fn wrap_request_body(body: Vec<u8>) -> Vec<u8> {
+    let (flags, body) = match deflate(&body) {
+        Some(deflated) => (COMPRESSED_FLAG_DEFLATE, deflated),
+        None if body.len() + COMPRESSED_PREFIX_BYTES > MAX_SEALED_REQUEST_BODY_BYTES => {
+            return body;
+        }
+        None => (0, body),
+    };
+    let mut output = Vec::with_capacity(COMPRESSED_PREFIX_BYTES + body.len());
+    output.push(TAG_COMPRESSED);
+    output.push(flags);
+    output.extend_from_slice(&body);
+    output
+}
+
+fn deflate(body: &[u8]) -> Option<Vec<u8>> {
+    if body.len() < MIN_DEFLATE_BYTES {
+        return None;
+    }
+    let deflated = miniz_oxide::deflate::compress_to_vec(body, DEFLATE_LEVEL);
+    (deflated.len() < body.len()).then_some(deflated)
+}
+
+// `limit` bounds what comes out, not what goes in: a few kilobytes of deflate
+// can describe gigabytes, so inflation stops at the limit rather than
+// allocating first and checking after.
+fn inflate(deflated: &[u8], limit: usize) -> Result<Vec<u8>, ProtocolError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(deflated, limit)
+        .map_err(|_| ProtocolError::Decompression)
+}
+
+pub fn seal_ack(
+    keys: &AuthKeys,
+    challenge: &Challenge,
+    request_nonce: Nonce,
+    ack: &Ack,
+) -> Result<WireAck, ProtocolError> {
+    Binding::Connection(challenge).seal_ack(keys, request_nonce, ack, Compression::Off)
+}
+
+#[cfg(test)]
+fn seal_ack_with_nonce(
+    keys: &AuthKeys,
+    challenge: &Challenge,
+    request_nonce: Nonce,
+    ack: &Ack,
+    nonce: Nonce,
+) -> Result<WireAck, ProtocolError> {
+    let aad = ack_aad(challenge, &request_nonce);
+    seal_ack_with_aad(keys, &aad, request_nonce, ack, Compression::Off, nonce)
+}
+
+// A deflated ack is `[TAG_ACK_DEFLATED_BODY, deflate(body)]`.  The body must
+// fit uncompressed all the same, since that is the bound the receiver inflates
+// it against.
+fn seal_ack_with_aad(
+    keys: &AuthKeys,
+    aad: &[u8],
+    request_nonce: Nonce,
+    ack: &Ack,
+    compression: Compression,
+    nonce: Nonce,
+) -> Result<WireAck, ProtocolError> {
+    let body = encode_ack_body(ack)?;
+    checked_size(
+        &[WIRE_ACK_AUTH_OVERHEAD, body.len(), AEAD_TAG_BYTES],
+        ack_body_limit(ack),
+    )?;
+    let plaintext = match (compression, deflate(&body)) {
+        (Compression::Deflate, Some(deflated)) => {
+            [&[TAG_ACK_DEFLATED_BODY][..], &deflated].concat()
+        }
+        _ => body,
+    };
+    let ciphertext = encrypt(&keys.ack, &nonce, &plaintext, aad)?;
+    Ok(WireAck::Authenticated {
+        request_nonce,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// Opens an authenticated ack, refusing anything larger than `limit`.
+///
+/// The caller knows which request it sent and therefore how large a legitimate
+/// answer can be; passing that bound in is what stops a ping from being
+/// answered with a ten-megabyte allocation.
+pub fn open_ack(
+    keys: &AuthKeys,
+    challenge: &Challenge,
+    expected_request_nonce: &Nonce,
+    response: &WireAck,
+    limit: usize,
+) -> Result<Ack, ProtocolError> {
+    Binding::Connection(challenge).open_ack(keys, expected_request_nonce, response, limit)
+}
+
+fn open_ack_with_aad(
+    keys: &AuthKeys,
+    aad: &[u8],
+    expected_request_nonce: &Nonce,
+    response: &WireAck,
+    limit: usize,
+) -> Result<Ack, ProtocolError> {
+    let WireAck::Authenticated {
+        request_nonce,
+        nonce,
+        ciphertext,
+    } = response
+    else {
+        return Err(ProtocolError::UnexpectedProtection);
+    };
+    if request_nonce != expected_request_nonce {
+        return Err(ProtocolError::ResponseBinding);
+    }
+    if ciphertext.len() < MIN_ACK_CIPHERTEXT_BYTES
+        || ciphertext.len() > limit - WIRE_ACK_AUTH_OVERHEAD
+    {
+        return Err(ProtocolError::InvalidLength(ciphertext.len()));
+    }
+    let plaintext = decrypt(&keys.ack, nonce, ciphertext, aad)?;
+    match plaintext.split_first() {
+        Some((&TAG_ACK_DEFLATED_BODY, deflated)) => {
+            let limit = limit - WIRE_ACK_AUTH_OVERHEAD - AEAD_TAG_BYTES;
+            decode_ack_body(&inflate(deflated, limit)?)
+        }
+        _ => decode_ack_body(&plaintext),
+    }
+}
+
+fn frame(payload: Vec<u8>) -> Result<Vec<u8>, ProtocolError> {
+    validate_length(payload.len())?;
+    let mut output = Vec::with_capacity(FRAME_HEADER_BYTES + payload.len());
+    output.extend_from_slice(&FRAME_MAGIC);
+    append_length(&mut output, payload.len())?;
+    output.extend_from_slice(&payload);
+    Ok(output)
+}
+
+pub fn encode_request_frame(request: &WireRequest) -> Result<Vec<u8>, ProtocolError> {
+    frame(encode_wire_request(request)?)
+}
+
+pub fn encode_hello_frame(hello: &ServerHello) -> Result<Vec<u8>, ProtocolError> {
+    let payload = encode_server_hello(hello);
+    validate_ack_length(payload.len(), MAX_ACK_BYTES)?;
+    frame(payload)
+}
+
+pub fn encode_client_hello_frame(hello: &ClientHello) -> Result<Vec<u8>, ProtocolError> {
+    frame(encode_client_hello(hello))
+}
+
+pub fn encode_chunk_frame(chunk: &WireChunk) -> Result<Vec<u8>, ProtocolError> {
+    frame(encode_chunk(chunk)?)
+}
+
+pub fn encode_event_frame(event: &SealedEvent) -> Result<Vec<u8>, ProtocolError> {
+    frame(encode_event(event)?)
+}
+
+pub fn encode_ack_frame(ack: &WireAck) -> Result<Vec<u8>, ProtocolError> {
+    let payload = encode_wire_ack(ack)?;
+    validate_ack_length(payload.len(), wire_ack_limit(ack))?;
+    frame(payload)
+}
+
+pub fn decode_request_payload(payload: &[u8]) -> Result<WireRequest, ProtocolError> {
+    decode_wire_request(payload)
+}
+
+pub fn decode_hello_payload(payload: &[u8]) -> Result<ServerHello, ProtocolError> {
+    decode_server_hello(payload)
+}
+
+pub fn decode_client_hello_payload(payload: &[u8]) -> Result<ClientHello, ProtocolError> {
+    decode_client_hello(payload)
+}
+
+/// Whether the client's first frame is a [`ClientHello`] rather than a request.
+pub fn is_client_hello(payload: &[u8]) -> bool {
+    payload.first() == Some(&TAG_CLIENT_HELLO)
+}
+
+/// The frame a client sends in answer to a bare hello to have the daemon
+/// describe itself.  It carries nothing but its tag.
+pub fn encode_describe_frame() -> Result<Vec<u8>, ProtocolError> {
+    frame(vec![TAG_DESCRIBE])
+}
+
+/// Whether the client's first frame asks the daemon to describe itself.
+pub fn is_describe(payload: &[u8]) -> bool {
+    payload.first() == Some(&TAG_DESCRIBE)
+}
+
+pub fn decode_describe_payload(payload: &[u8]) -> Result<(), ProtocolError> {
+    let mut decoder = Decoder::new(payload);
+    let tag = decoder.read_u8()?;
+    if tag != TAG_DESCRIBE {
+        return Err(ProtocolError::UnknownTag(tag));
+    }
+    decoder.finish()
+}
+
+pub fn decode_chunk_payload(payload: &[u8]) -> Result<WireChunk, ProtocolError> {
+    decode_chunk(payload)
+}
+
+pub fn decode_event_payload(payload: &[u8]) -> Result<SealedEvent, ProtocolError> {
+    decode_event(payload)
+}
+
+pub fn decode_ack_payload(payload: &[u8], limit: usize) -> Result<WireAck, ProtocolError> {
+    decode_wire_ack(payload, limit)
+}
+
+/// The largest ack the given request may legitimately be answered with.
+pub fn ack_limit(request: &PlainRequest) -> usize {
+    match request {
+        PlainRequest::Get { .. }
+        | PlainRequest::GetVersioned { .. }
+        | PlainRequest::HistoryGet { .. }
+        | PlainRequest::SlotGet { .. } => MAX_DATA_ACK_BYTES,
+        _ => MAX_ACK_BYTES,
+    }
+}
+
+pub fn validate_ack_length(length: usize, limit: usize) -> Result<(), ProtocolError> {
+    if length == 0 || length > limit {
+        Err(ProtocolError::InvalidLength(length))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn parse_header(header: &[u8; FRAME_HEADER_BYTES]) -> Result<usize, ProtocolError> {
+    if header[..FRAME_MAGIC.len()] != FRAME_MAGIC {
+        return Err(ProtocolError::InvalidMagic);
+    }
+    let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    validate_length(length)?;
+    Ok(length)
+}
+
+pub fn validate_length(length: usize) -> Result<(), ProtocolError> {
+    if length == 0 || length > MAX_FRAME_BYTES {
+        Err(ProtocolError::InvalidLength(length))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_frame(frame: &[u8]) -> (&[u8; FRAME_HEADER_BYTES], &[u8]) {
+        let (header, payload) = frame.split_at(FRAME_HEADER_BYTES);
+        (header.try_into().unwrap(), payload)
+    }
+
+    #[test]
+    fn plaintext_request_round_trip_preserves_unicode_and_delimiters() {
+        let request = WireRequest::Plain(PlainRequest::Set {
+            selection: Selection::Clipboard,
+            text: "第一行\ncontrol:\u{1}:✅".to_owned(),
+        });
+        let encoded = encode_request_frame(&request).unwrap();
+        let (header, payload) = split_frame(&encoded);
+
+        assert_eq!(parse_header(header).unwrap(), payload.len());
+        assert_eq!(decode_request_payload(payload).unwrap(), request);
+        assert_eq!(payload[0], TAG_REQUEST_PLAIN);
+        assert_eq!(payload[1], TAG_SET);
+    }
+
+    #[test]
+    fn every_request_variant_round_trips() {
+        for request in [
+            PlainRequest::Ping,
+            PlainRequest::Set {
+                selection: Selection::Clipboard,
+                text: String::new(),
+            },
+            PlainRequest::Set {
+                selection: Selection::Primary,
+                text: "primary".to_owned(),
+            },
+            PlainRequest::Legacy {
+                text: "legacy".to_owned(),
+            },
+            PlainRequest::Get {
+                selection: Selection::Clipboard,
+            },
+            PlainRequest::Get {
+                selection: Selection::Primary,
+            },
+            PlainRequest::SetStream {
+                selection: Selection::Primary,
+            },
+            PlainRequest::GetStream {
+                selection: Selection::Clipboard,
+            },
+            PlainRequest::Clear {
+                selection: Selection::Primary,
+            },
+            PlainRequest::Subscribe {
+                selection: Selection::Clipboard,
+                text: true,
+            },
+            PlainRequest::Subscribe {
+                selection: Selection::Primary,
+                text: false,
+            },
+            PlainRequest::HistoryList,
+            PlainRequest::HistoryGet { id: u64::MAX },
+            PlainRequest::HistorySearch {
+                query: "第二行".to_owned(),
+            },
+            PlainRequest::SlotSet {
+                name: SlotName::parse("a").unwrap(),
+                text: "第二行".to_owned(),
+            },
+            PlainRequest::SlotGet {
+                name: SlotName::parse("snippet.rs-2_b").unwrap(),
+            },
+            PlainRequest::SlotList,
+            PlainRequest::Status,
+            PlainRequest::Append {
+                selection: Selection::Primary,
+                separator: "\n".to_owned(),
+                text: "第二行".to_owned(),
+            },
+            PlainRequest::Append {
+                selection: Selection::Clipboard,
+                separator: String::new(),
+                text: String::new(),
+            },
+            PlainRequest::SetVersioned {
+                selection: Selection::Primary,
+                expected: Some(u64::MAX),
+                text: "第二行".to_owned(),
+            },
+            PlainRequest::SetVersioned {
+                selection: Selection::Clipboard,
+                expected: None,
+                text: String::new(),
+            },
+            PlainRequest::GetVersioned {
+                selection: Selection::Primary,
+            },
+            PlainRequest::SetExpiring {
+                selection: Selection::Primary,
+                seconds: MAX_EXPIRY_SECONDS,
+                text: "第二行".to_owned(),
+            },
+            PlainRequest::SetExpiring {
+                selection: Selection::Clipboard,
+                seconds: 1,
+                text: String::new(),
+            },
+            PlainRequest::SetSensitive {
+                selection: Selection::Primary,
+                seconds: Some(MAX_EXPIRY_SECONDS),
+                text: "hunter2".to_owned(),
+            },
+            PlainRequest::SetSensitive {
+                selection: Selection::Clipboard,
+                seconds: None,
+                text: String::new(),
+            },
+            PlainRequest::OutcomeQuery {
+                nonce: [6; NONCE_BYTES],
+            },
+            PlainRequest::SetVerified {
+                selection: Selection::Primary,
+                text: "第三行".to_owned(),
+            },
+        ] {
+            let wire = WireRequest::Plain(request);
+            let frame = encode_request_frame(&wire).unwrap();
+            let (_, payload) = split_frame(&frame);
+            assert_eq!(decode_request_payload(payload).unwrap(), wire);
+        }
+    }
+
+    #[test]
+    fn authenticated_request_hides_secrets_and_rejects_wrong_key_or_tampering() {
+        let token = "token-that-must-never-be-on-the-wire";
+        let text = "clipboard text that must be encrypted";
+        let keys = derive_auth_keys(token);
+        let request = PlainRequest::Set {
+            selection: Selection::Clipboard,
+            text: text.to_owned(),
+        };
+        let challenge = [5_u8; CHALLENGE_BYTES];
+        let fixed_nonce = [7_u8; NONCE_BYTES];
+        let (wire, binding) =
+            seal_request_with_nonce(&keys, &challenge, &request, fixed_nonce).unwrap();
+        let frame = encode_request_frame(&wire).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(decode_request_payload(payload).unwrap(), wire);
+
+        assert!(
+            !frame
+                .windows(token.len())
+                .any(|window| window == token.as_bytes())
+        );
+        assert!(
+            !frame
+                .windows(text.len())
+                .any(|window| window == text.as_bytes())
+        );
+
+        let WireRequest::Authenticated {
+            nonce,
+            mut ciphertext,
+            ..
+        } = wire
+        else {
+            panic!("expected authenticated request");
+        };
+        assert_eq!(binding, nonce);
+        assert_eq!(
+            open_request(&keys, &challenge, &nonce, &ciphertext).unwrap(),
+            request
+        );
+
+        let wrong_keys = derive_auth_keys("wrong token");
+        assert_eq!(
+            open_request(&wrong_keys, &challenge, &nonce, &ciphertext),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+        assert_eq!(
+            open_request(&keys, &[6_u8; CHALLENGE_BYTES], &nonce, &ciphertext),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+        ciphertext[0] ^= 0x80;
+        assert_eq!(
+            open_request(&keys, &challenge, &nonce, &ciphertext),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn authenticated_ack_is_tamper_proof_and_bound_to_request() {
+        let keys = derive_auth_keys("secret");
+        let challenge = [2_u8; CHALLENGE_BYTES];
+        let request_nonce = [3_u8; NONCE_BYTES];
+        let ack = Ack::status(true, Some("clipboard_set_ok".to_owned()));
+        let mut response =
+            seal_ack_with_nonce(&keys, &challenge, request_nonce, &ack, [9_u8; NONCE_BYTES])
+                .unwrap();
+        let encoded = encode_ack_frame(&response).unwrap();
+        let (_, payload) = split_frame(&encoded);
+        assert_eq!(
+            decode_ack_payload(payload, MAX_ACK_BYTES).unwrap(),
+            response
+        );
+        assert_eq!(
+            open_ack(&keys, &challenge, &request_nonce, &response, MAX_ACK_BYTES).unwrap(),
+            ack
+        );
+        assert_eq!(
+            open_ack(
+                &keys,
+                &challenge,
+                &[4_u8; NONCE_BYTES],
+                &response,
+                MAX_ACK_BYTES
+            ),
+            Err(ProtocolError::ResponseBinding)
+        );
+        assert_eq!(
+            open_ack(
+                &keys,
+                &[8_u8; CHALLENGE_BYTES],
+                &request_nonce,
+                &response,
+                MAX_ACK_BYTES
+            ),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+
+        let WireAck::Authenticated { ciphertext, .. } = &mut response else {
+            panic!("expected authenticated ack");
+        };
+        ciphertext[0] ^= 1;
+        assert_eq!(
+            open_ack(&keys, &challenge, &request_nonce, &response, MAX_ACK_BYTES),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn server_hello_round_trip_is_bounded_and_tagged() {
+        let hello = ServerHello {
+            challenge: [11_u8; CHALLENGE_BYTES],
+            info: Some(ServerInfo::current(true)),
+            host: None,
+        };
+        let encoded = encode_hello_frame(&hello).unwrap();
+        let (header, payload) = split_frame(&encoded);
+        assert_eq!(parse_header(header).unwrap(), payload.len());
+        assert_eq!(decode_hello_payload(payload).unwrap(), hello);
+        assert_eq!(payload[0], TAG_SERVER_HELLO);
+        assert_eq!(payload.len(), HELLO_BYTES + HELLO_INFO_BYTES);
+        assert!(payload.len() <= MAX_ACK_BYTES);
+    }
+
+    // The hello every connection opens with, decoded the way a client built
+    // before the description existed decodes it: the tag, the challenge and
+    // nothing after.
+    #[test]
+    fn the_first_hello_is_what_a_revision_1_client_reads() {
+        let hello = new_server_hello(ServerInfo::current(true)).unwrap();
+        let frame = encode_hello_frame(&hello.bare()).unwrap();
+        let (_, payload) = split_frame(&frame);
+        let mut decoder = Decoder::new(payload);
+        assert_eq!(decoder.read_u8().unwrap(), TAG_SERVER_HELLO);
+        assert_eq!(
+            decoder.read_array::<CHALLENGE_BYTES>().unwrap(),
+            hello.challenge
+        );
+        assert_eq!(decoder.finish(), Ok(()));
+
+        let describe = encode_describe_frame().unwrap();
+        let (_, payload) = split_frame(&describe);
+        assert!(is_describe(payload));
+        assert!(!is_client_hello(payload));
+        assert_eq!(decode_describe_payload(payload), Ok(()));
+        assert_eq!(
+            decode_describe_payload(&[TAG_DESCRIBE, 0]),
+            Err(ProtocolError::TrailingBytes)
+        );
+        // Not a request either: a revision-1 daemon refuses it and closes.
+        assert!(decode_request_payload(payload).is_err());
+    }
+
+    // A revision-1 daemon sends the bare challenge.  A client must still talk
+    // to it, assuming exactly what that daemon understood and nothing more.
+    #[test]
+    fn a_bare_challenge_hello_is_a_revision_1_daemon() {
+        let mut payload = vec![TAG_SERVER_HELLO];
+        payload.extend_from_slice(&[12_u8; CHALLENGE_BYTES]);
+        let hello = decode_hello_payload(&payload).unwrap();
+        assert_eq!(hello.info, None);
+        let info = hello.info();
+        assert_eq!(info.version, 1);
+        assert_eq!(info.token_configured, None);
+        assert!(info.capabilities.contains(Capabilities::GET));
+        assert!(!info.capabilities.contains(Capabilities::SET_SELECTION));
+        assert_eq!(
+            info.refusal(
+                &PlainRequest::Set {
+                    selection: Selection::Primary,
+                    text: String::new(),
+                },
+                true
+            ),
+            Some("request_unsupported")
+        );
+        assert_eq!(
+            info.refusal(
+                &PlainRequest::Set {
+                    selection: Selection::Clipboard,
+                    text: String::new(),
+                },
+                false
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn a_newer_hello_may_grow_but_this_revision_is_strict() {
+        let hello = ServerHello {
+            challenge: [13_u8; CHALLENGE_BYTES],
+            info: Some(ServerInfo::current(false)),
+            host: None,
+        };
+        let frame = encode_hello_frame(&hello).unwrap();
+        let (_, payload) = split_frame(&frame);
+
+        let mut trailing = payload.to_vec();
+        trailing.push(0);
+        assert_eq!(
+            decode_hello_payload(&trailing),
+            Err(ProtocolError::TrailingBytes)
+        );
+
+        // The flags byte, just before the stream limit.
+        let mut unknown_flag = payload.to_vec();
+        let flags = unknown_flag.len() - 9;
+        unknown_flag[flags] = 0x80;
+        assert_eq!(
+            decode_hello_payload(&unknown_flag),
+            Err(ProtocolError::UnknownTag(0x80))
+        );
+
+        // The same tail from a later revision, with a field this build has
+        // never heard of appended.
+        let mut newer = payload.to_vec();
+        newer[HELLO_BYTES..HELLO_BYTES + 2].copy_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+        newer.extend_from_slice(&[0xaa; 9]);
+        let decoded = decode_hello_payload(&newer).unwrap().info.unwrap();
+        assert_eq!(decoded.version, PROTOCOL_VERSION + 1);
+        assert_eq!(decoded.token_configured, Some(false));
+
+        let mut older = payload.to_vec();
+        older[HELLO_BYTES..HELLO_BYTES + 2].copy_from_slice(&1_u16.to_be_bytes());
+        assert_eq!(
+            decode_hello_payload(&older),
+            Err(ProtocolError::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn the_hello_names_the_key_derivation_and_each_scheme_yields_its_own_keys() {
+        let salt = [7_u8; KDF_SALT_BYTES];
+        for derivation in [
+            KeyDerivation::Sha256,
+            KeyDerivation::Hkdf(salt),
+            KeyDerivation::Argon2id(salt),
+        ] {
+            let hello = ServerHello {
+                challenge: [3_u8; CHALLENGE_BYTES],
+                info: Some(ServerInfo {
+                    key_derivation: derivation,
+                    ..ServerInfo::current(true)
+                }),
+                host: None,
+            };
+            let frame = encode_hello_frame(&hello).unwrap();
+            let (_, payload) = split_frame(&frame);
+            assert_eq!(decode_hello_payload(payload).unwrap(), hello);
+            assert_eq!(
+                KeyDerivation::parse(derivation.name(), salt),
+                Some(derivation)
+            );
+        }
+        assert_eq!(KeyDerivation::parse("scrypt", salt), None);
+
+        // A revision-1 daemon and one that names no scheme both mean SHA-256.
+        let bare = decode_hello_payload(&[TAG_SERVER_HELLO; HELLO_BYTES]).unwrap();
+        assert_eq!(bare.info(), ServerInfo::revision_1());
+        assert_eq!(bare.info().key_derivation, KeyDerivation::Sha256);
+
+        let hkdf = derive_keys("secret", &KeyDerivation::Hkdf(salt)).unwrap();
+        let argon2id = derive_keys("secret", &KeyDerivation::Argon2id(salt)).unwrap();
+        let legacy = derive_keys("secret", &KeyDerivation::Sha256).unwrap();
+        assert!(legacy.same_token(&derive_auth_keys("secret")));
+        assert!(hkdf.same_token(&derive_keys("secret", &KeyDerivation::Hkdf(salt)).unwrap()));
+        assert!(!hkdf.same_token(&legacy));
+        assert!(!hkdf.same_token(&argon2id));
+        assert!(!argon2id.same_token(&legacy));
+        let resalted = derive_keys("secret", &KeyDerivation::Hkdf([8; KDF_SALT_BYTES])).unwrap();
+        assert!(!hkdf.same_token(&resalted));
+
+        // A scheme this build does not know is refused, not read as SHA-256.
+        let hello = ServerHello {
+            challenge: [3_u8; CHALLENGE_BYTES],
+            info: Some(ServerInfo {
+                key_derivation: KeyDerivation::Hkdf(salt),
+                ..ServerInfo::current(true)
+            }),
+            host: None,
+        };
+        let frame = encode_hello_frame(&hello).unwrap();
+        let (_, payload) = split_frame(&frame);
+        let mut unknown = payload.to_vec();
+        unknown[payload.len() - HELLO_KDF_BYTES] = 0x7f;
+        assert_eq!(
+            decode_hello_payload(&unknown),
+            Err(ProtocolError::UnknownTag(0x7f))
+        );
+    }
+
+    // Each end proves its key over this connection's challenge, and the two
+    // arrive at the same keys without either key crossing the wire.
+    #[test]
+    fn a_key_exchange_agrees_on_keys_only_between_the_pinned_host_and_a_signing_client() {
+        let host = KeyPair::generate().unwrap();
+        let client = KeyPair::generate().unwrap();
+        let reread = KeyPair::parse(&client.to_file_contents()).unwrap();
+        assert_eq!(reread.public_key(), client.public_key());
+        let public = client.public_key();
+        assert_eq!(PublicKey::parse(&public.to_string()), Some(public));
+        assert_eq!(PublicKey::parse("00"), None);
+        assert_eq!(
+            KeyPair::parse(&"zz".repeat(32)).map(|pair| pair.public_key()),
+            None
+        );
+
+        let challenge = [5_u8; CHALLENGE_BYTES];
+        let exchange = HostExchange::new(&host, &challenge).unwrap();
+        let hello = ServerHello {
+            challenge,
+            info: Some(ServerInfo::current(true)),
+            host: Some(exchange.offer()),
+        };
+        let frame = encode_hello_frame(&hello).unwrap();
+        let (_, payload) = split_frame(&frame);
+        let hello = decode_hello_payload(payload).unwrap();
+        let offered = hello.host.unwrap();
+        assert_eq!(offered.host_key, host.public_key());
+
+        let (client_keys, offer) =
+            answer_host_offer(&client, &host.public_key(), &challenge, &offered).unwrap();
+        let client_hello = ClientHello {
+            offer: Some(offer),
+            ..ClientHello::session()
+        };
+        let frame = encode_client_hello_frame(&client_hello).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(decode_client_hello_payload(payload).unwrap(), client_hello);
+        let mut repeated = payload.to_vec();
+        repeated.extend_from_slice(&payload[CLIENT_HELLO_BYTES + CLIENT_EXTENSION_HEADER_BYTES..]);
+        assert_eq!(
+            decode_client_hello_payload(&repeated),
+            Err(ProtocolError::UnknownTag(CLIENT_EXTENSION_KEY_EXCHANGE))
+        );
+        let host_keys = exchange.accept(&offer).unwrap();
+        assert!(host_keys.same_token(&client_keys));
 
-/// The largest ack the given request may legitimately be answered with.
-pub fn ack_limit(request: &PlainRequest) -> usize {
-    match request {
-        PlainRequest::Get { .. } => MAX_DATA_ACK_BYTES,
-        _ => MAX_ACK_BYTES,
+        // A daemon that is not the pinned one, or signs some other challenge,
+        // is refused before the client says anything.
+        let impostor = KeyPair::generate().unwrap();
+        let stranger = HostExchange::new(&impostor, &challenge).unwrap().offer();
+        assert_eq!(
+            answer_host_offer(&client, &host.public_key(), &challenge, &stranger).err(),
+            Some(ProtocolError::HostKey)
+        );
+        assert!(answer_host_offer(&client, &impostor.public_key(), &challenge, &stranger).is_ok());
+        assert_eq!(
+            answer_host_offer(&client, &host.public_key(), &[6; CHALLENGE_BYTES], &offered).err(),
+            Some(ProtocolError::HostKey)
+        );
+
+        // An offer made on another connection, or signed by another key than
+        // the one it names, opens nothing here.
+        let elsewhere = HostExchange::new(&host, &[6; CHALLENGE_BYTES]).unwrap();
+        let (_, replayed) = answer_host_offer(
+            &client,
+            &host.public_key(),
+            &[6; CHALLENGE_BYTES],
+            &elsewhere.offer(),
+        )
+        .unwrap();
+        assert_eq!(
+            exchange.accept(&replayed).err(),
+            Some(ProtocolError::AuthenticationFailed)
+        );
+        let forged = ClientOffer {
+            identity: impostor.public_key(),
+            ..offer
+        };
+        assert_eq!(
+            exchange.accept(&forged).err(),
+            Some(ProtocolError::AuthenticationFailed)
+        );
     }
-}
 
-pub fn validate_ack_length(length: usize, limit: usize) -> Result<(), ProtocolError> {
-    if length == 0 || length > limit {
-        Err(ProtocolError::InvalidLength(length))
-    } else {
-        Ok(())
+    // Only a holder of the token can agree on the connection's keys, and no
+    // two connections agree on the same ones, so recording a connection and
+    // learning the token later opens nothing.
+    #[test]
+    fn an_ephemeral_exchange_mixes_the_token_into_keys_of_one_connection() {
+        let token = derive_auth_keys("secret");
+        let exchange = EphemeralExchange::new().unwrap();
+        let (client_key, client) = answer_ephemeral(&exchange.challenge()).unwrap();
+        let daemon = exchange.accept(&client_key).unwrap();
+        let keys = client.keys(&token).unwrap();
+        assert!(keys.same_token(&daemon.keys(&token).unwrap()));
+        assert!(!keys.same_token(&token));
+        assert!(!keys.same_token(&daemon.keys(&derive_auth_keys("other")).unwrap()));
+        let (_, elsewhere) =
+            answer_ephemeral(&EphemeralExchange::new().unwrap().challenge()).unwrap();
+        assert!(!keys.same_token(&elsewhere.keys(&token).unwrap()));
+        assert_eq!(
+            exchange.accept(&[0; EXCHANGE_KEY_BYTES]).err(),
+            Some(ProtocolError::AuthenticationFailed)
+        );
+
+        let client_hello = ClientHello {
+            ephemeral: Some(client_key),
+            ..ClientHello::session()
+        };
+        let frame = encode_client_hello_frame(&client_hello).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(decode_client_hello_payload(payload).unwrap(), client_hello);
+        let identity = KeyPair::generate().unwrap();
+        let host = KeyPair::generate().unwrap();
+        let offered = HostExchange::new(&host, &exchange.challenge())
+            .unwrap()
+            .offer();
+        let (_, offer) = answer_host_offer(
+            &identity,
+            &host.public_key(),
+            &exchange.challenge(),
+            &offered,
+        )
+        .unwrap();
+        let frame = encode_client_hello_frame(&ClientHello {
+            offer: Some(offer),
+            ..client_hello
+        })
+        .unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(
+            decode_client_hello_payload(payload),
+            Err(ProtocolError::UnknownTag(CLIENT_EXTENSION_EPHEMERAL))
+        );
     }
-}
 
-pub fn parse_header(header: &[u8; FRAME_HEADER_BYTES]) -> Result<usize, ProtocolError> {
-    if header[..FRAME_MAGIC.len()] != FRAME_MAGIC {
-        return Err(ProtocolError::InvalidMagic);
+    #[test]
+    fn server_info_rules_out_what_the_daemon_cannot_carry() {
+        let info = ServerInfo {
+            max_text_bytes: 4,
+            ..ServerInfo::current(true)
+        };
+        let set = |text: &str| PlainRequest::Set {
+            selection: Selection::Clipboard,
+            text: text.to_owned(),
+        };
+        assert_eq!(info.refusal(&set("four"), true), None);
+        assert_eq!(info.refusal(&set("five!"), true), Some("text_too_large"));
+        assert_eq!(
+            info.refusal(&PlainRequest::Ping, false),
+            Some("authentication_required")
+        );
+        assert_eq!(
+            ServerInfo::current(false).refusal(&PlainRequest::Ping, true),
+            Some("authentication_not_configured")
+        );
+        assert!(Capabilities::ALL.contains(Capabilities::REVISION_1));
     }
-    let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
-    validate_length(length)?;
-    Ok(length)
-}
 
-pub fn validate_length(length: usize) -> Result<(), ProtocolError> {
-    if length == 0 || length > MAX_FRAME_BYTES {
-        Err(ProtocolError::InvalidLength(length))
-    } else {
-        Ok(())
+    // The request tags that share values with the frame tags are never taken
+    // for them: a plain request frame leads with TAG_REQUEST_PLAIN.
+    #[test]
+    fn request_tags_that_match_frame_tags_stay_requests() {
+        let append = PlainRequest::Append {
+            selection: Selection::Clipboard,
+            separator: String::new(),
+            text: "tail".to_owned(),
+        };
+        for request in [PlainRequest::SlotList, PlainRequest::Status, append] {
+            let frame = encode_request_frame(&WireRequest::Plain(request.clone())).unwrap();
+            let (_, payload) = split_frame(&frame);
+            assert!(!is_describe(payload) && !is_client_hello(payload));
+            assert!(decode_server_hello(payload).is_err());
+            assert_eq!(
+                decode_request_payload(payload).unwrap(),
+                WireRequest::Plain(request)
+            );
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn client_hello_round_trip_is_strict() {
+        let hello = ClientHello::session();
+        let frame = encode_client_hello_frame(&hello).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(
+            payload,
+            [TAG_CLIENT_HELLO, 0, 2, CLIENT_EXTENSION_SESSION, 0, 0]
+        );
+        assert!(is_client_hello(payload));
+        assert!(!is_client_hello(&[TAG_REQUEST_PLAIN, TAG_PING]));
+        assert_eq!(decode_client_hello_payload(payload).unwrap(), hello);
+        assert_eq!(
+            decode_client_hello_payload(&[TAG_CLIENT_HELLO, 0, 2]).unwrap(),
+            ClientHello {
+                version: 2,
+                session: false,
+                offer: None,
+                ephemeral: None,
+                ack_codes: false,
+            }
+        );
+        let coded = ClientHello {
+            ack_codes: true,
+            ..ClientHello::session()
+        };
+        let frame = encode_client_hello_frame(&coded).unwrap();
+        let (_, coded_payload) = split_frame(&frame);
+        assert_eq!(
+            coded_payload,
+            [
+                TAG_CLIENT_HELLO,
+                0,
+                2,
+                CLIENT_EXTENSION_SESSION,
+                0,
+                0,
+                CLIENT_EXTENSION_ACK_CODES,
+                0,
+                0
+            ]
+        );
+        assert_eq!(decode_client_hello_payload(coded_payload).unwrap(), coded);
 
-    fn split_frame(frame: &[u8]) -> (&[u8; FRAME_HEADER_BYTES], &[u8]) {
-        let (header, payload) = frame.split_at(FRAME_HEADER_BYTES);
-        (header.try_into().unwrap(), payload)
+        let mut repeated = payload.to_vec();
+        repeated.extend_from_slice(&[CLIENT_EXTENSION_SESSION, 0, 0]);
+        for (bytes, error) in [
+            (
+                repeated,
+                ProtocolError::UnknownTag(CLIENT_EXTENSION_SESSION),
+            ),
+            (
+                vec![TAG_CLIENT_HELLO, 0, 2, 0x7e, 0, 0],
+                ProtocolError::UnknownTag(0x7e),
+            ),
+            (
+                vec![TAG_CLIENT_HELLO, 0, 2, CLIENT_EXTENSION_SESSION, 0, 1, 0],
+                ProtocolError::InvalidLength(1),
+            ),
+            (
+                vec![TAG_CLIENT_HELLO, 0, 2, CLIENT_EXTENSION_ACK_CODES, 0, 1, 0],
+                ProtocolError::InvalidLength(1),
+            ),
+            (
+                vec![TAG_CLIENT_HELLO, 0, 2, CLIENT_EXTENSION_SESSION, 0],
+                ProtocolError::UnexpectedEof,
+            ),
+            (
+                vec![TAG_CLIENT_HELLO, 0, 1],
+                ProtocolError::UnsupportedVersion(1),
+            ),
+        ] {
+            assert_eq!(decode_client_hello_payload(&bytes), Err(error));
+        }
     }
 
+    // Within a session every request has one place.  The same ciphertext one
+    // step later is a replay, and under another session or a one-request
+    // connection it was never valid at all.
     #[test]
-    fn plaintext_request_round_trip_preserves_unicode_and_delimiters() {
-        let request = WireRequest::Plain(PlainRequest::Set {
-            text: "第一行\ncontrol:\u{1}:✅".to_owned(),
-        });
-        let encoded = encode_request_frame(&request).unwrap();
-        let (header, payload) = split_frame(&encoded);
+    fn a_session_request_opens_only_at_its_own_place_in_the_session() {
+        let keys = derive_auth_keys("secret");
+        let client_hello = encode_client_hello(&ClientHello::session());
+        let mut session = Session::new(&[TAG_SERVER_HELLO; 8], &client_hello);
+        let (wire, nonce) = Binding::Session(&session)
+            .seal_request(&keys, &PlainRequest::Ping, Compression::Off)
+            .unwrap();
+        let WireRequest::Authenticated { ciphertext, .. } = wire else {
+            panic!("expected an authenticated request");
+        };
+        assert_eq!(
+            Binding::Session(&session)
+                .open_request(&keys, &nonce, &ciphertext)
+                .unwrap(),
+            (PlainRequest::Ping, Compression::Off)
+        );
 
-        assert_eq!(parse_header(header).unwrap(), payload.len());
-        assert_eq!(decode_request_payload(payload).unwrap(), request);
-        assert_eq!(payload[0], TAG_REQUEST_PLAIN);
-        assert_eq!(payload[1], TAG_SET);
+        let mut later = session.clone();
+        later.advance();
+        let other = Session::new(&[TAG_SERVER_HELLO; 9], &client_hello);
+        for binding in [
+            Binding::Session(&later),
+            Binding::Session(&other),
+            Binding::Connection(&[TAG_SERVER_HELLO; CHALLENGE_BYTES]),
+        ] {
+            assert_eq!(
+                binding.open_request(&keys, &nonce, &ciphertext),
+                Err(ProtocolError::AuthenticationFailed)
+            );
+        }
+
+        let ack = Ack::status(true, Some("ping_ok".to_owned()));
+        let sealed = Binding::Session(&session)
+            .seal_ack(&keys, nonce, &ack, Compression::Off)
+            .unwrap();
+        assert_eq!(
+            Binding::Session(&session)
+                .open_ack(&keys, &nonce, &sealed, MAX_ACK_BYTES)
+                .unwrap(),
+            ack
+        );
+        assert_eq!(
+            Binding::Session(&later).open_ack(&keys, &nonce, &sealed, MAX_ACK_BYTES),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+
+        session.advance();
+        assert_eq!(session, later);
+        assert_eq!(session.sequence(), 1);
     }
 
+    // A stream is only as trustworthy as its weakest chunk: each must open
+    // only for its own request, at its own index, in its own direction, and
+    // only as last if it was sealed as last.
     #[test]
-    fn every_request_variant_round_trips() {
-        for request in [
-            PlainRequest::Ping,
-            PlainRequest::Set {
-                text: String::new(),
-            },
-            PlainRequest::Legacy {
-                text: "legacy".to_owned(),
+    fn a_sealed_event_opens_only_in_its_own_place() {
+        let keys = derive_auth_keys("secret");
+        let challenge = [24_u8; CHALLENGE_BYTES];
+        let binding = Binding::Connection(&challenge);
+        let request_nonce = [25_u8; NONCE_BYTES];
+        let text = "剪贴板✅";
+        let events = [
+            Event {
+                index: 0,
+                change: Some(Change {
+                    selection: Selection::Primary,
+                    size: text.len() as u64,
+                    hash: content_hash(text),
+                    text: Some(text.to_owned()),
+                }),
             },
-            PlainRequest::Get {
-                selection: Selection::Clipboard,
+            Event {
+                index: 1,
+                change: None,
             },
-            PlainRequest::Get {
-                selection: Selection::Primary,
+            Event {
+                index: 2,
+                change: Some(Change {
+                    selection: Selection::Clipboard,
+                    size: MAX_EVENT_TEXT_BYTES as u64 + 1,
+                    hash: [7_u8; HASH_BYTES],
+                    text: None,
+                }),
             },
-        ] {
-            let wire = WireRequest::Plain(request);
-            let frame = encode_request_frame(&wire).unwrap();
-            let (_, payload) = split_frame(&frame);
-            assert_eq!(decode_request_payload(payload).unwrap(), wire);
+        ];
+        for event in &events {
+            let sealed = binding.seal_event(&keys, &request_nonce, event).unwrap();
+            let frame = encode_event_frame(&sealed).unwrap();
+            let (header, payload) = split_frame(&frame);
+            assert_eq!(parse_header(header).unwrap(), payload.len());
+            let decoded = decode_event_payload(payload).unwrap();
+            assert_eq!(decoded, sealed);
+            let opened = binding
+                .open_event(&keys, &request_nonce, event.index, &decoded)
+                .unwrap();
+            assert_eq!(&opened, event);
         }
-    }
-
-    #[test]
-    fn authenticated_request_hides_secrets_and_rejects_wrong_key_or_tampering() {
-        let token = "token-that-must-never-be-on-the-wire";
-        let text = "clipboard text that must be encrypted";
-        let keys = derive_auth_keys(token);
-        let request = PlainRequest::Set {
-            text: text.to_owned(),
-        };
-        let challenge = [5_u8; CHALLENGE_BYTES];
-        let fixed_nonce = [7_u8; NONCE_BYTES];
-        let (wire, binding) =
-            seal_request_with_nonce(&keys, &challenge, &request, fixed_nonce).unwrap();
-        let frame = encode_request_frame(&wire).unwrap();
-        let (_, payload) = split_frame(&frame);
-        assert_eq!(decode_request_payload(payload).unwrap(), wire);
 
-        assert!(
-            !frame
-                .windows(token.len())
-                .any(|window| window == token.as_bytes())
+        // Reordered, or spliced from another subscription.
+        let sealed = binding
+            .seal_event(&keys, &request_nonce, &events[1])
+            .unwrap();
+        assert_eq!(
+            binding.open_event(&keys, &request_nonce, 0, &sealed),
+            Err(ProtocolError::EventOrder(1))
         );
-        assert!(
-            !frame
-                .windows(text.len())
-                .any(|window| window == text.as_bytes())
+        assert_eq!(
+            binding.open_event(&keys, &[26_u8; NONCE_BYTES], 1, &sealed),
+            Err(ProtocolError::AuthenticationFailed)
         );
 
-        let WireRequest::Authenticated {
-            nonce,
-            mut ciphertext,
-        } = wire
-        else {
-            panic!("expected authenticated request");
+        // The text a change carries is the text its size describes.
+        let mismatched = Event {
+            index: 0,
+            change: Some(Change {
+                selection: Selection::Clipboard,
+                size: 1,
+                hash: content_hash("ab"),
+                text: Some("ab".to_owned()),
+            }),
         };
-        assert_eq!(binding, nonce);
         assert_eq!(
-            open_request(&keys, &challenge, &nonce, &ciphertext).unwrap(),
-            request
+            binding.seal_event(&keys, &request_nonce, &mismatched),
+            Err(ProtocolError::InvalidLength(2))
         );
+    }
 
-        let wrong_keys = derive_auth_keys("wrong token");
+    #[test]
+    fn a_sealed_chunk_opens_only_in_its_own_place() {
+        let keys = derive_auth_keys("secret");
+        let challenge = [21_u8; CHALLENGE_BYTES];
+        let binding = Binding::Connection(&challenge);
+        let request_nonce = [22_u8; NONCE_BYTES];
+        let text = "块".repeat(CHUNK_BYTES / 3 + 1);
+        let pieces = text_chunks(&text);
+        assert_eq!(pieces.len(), 2);
+        let sealed: Vec<WireChunk> = pieces
+            .iter()
+            .enumerate()
+            .map(|(index, piece)| {
+                let chunk = Chunk {
+                    index: index as u32,
+                    last: index + 1 == pieces.len(),
+                    text: (*piece).to_owned(),
+                };
+                binding
+                    .seal_request_chunk(&keys, &request_nonce, &chunk)
+                    .unwrap()
+            })
+            .collect();
+
+        let mut assembled = String::new();
+        for (index, chunk) in sealed.iter().enumerate() {
+            let frame = encode_chunk_frame(chunk).unwrap();
+            let (header, payload) = split_frame(&frame);
+            assert_eq!(parse_header(header).unwrap(), payload.len());
+            let decoded = decode_chunk_payload(payload).unwrap();
+            assert_eq!(&decoded, chunk);
+            let opened = binding
+                .open_request_chunk(&keys, &request_nonce, index as u32, &decoded)
+                .unwrap();
+            assert_eq!(opened.last, index == 1);
+            assembled.push_str(&opened.text);
+        }
+        assert_eq!(assembled, text);
+
+        // Reordered, spliced from another request, replayed as a reply.
         assert_eq!(
-            open_request(&wrong_keys, &challenge, &nonce, &ciphertext),
+            binding.open_request_chunk(&keys, &request_nonce, 0, &sealed[1]),
+            Err(ProtocolError::ChunkOrder(1))
+        );
+        assert_eq!(
+            binding.open_request_chunk(&keys, &[23_u8; NONCE_BYTES], 0, &sealed[0]),
             Err(ProtocolError::AuthenticationFailed)
         );
         assert_eq!(
-            open_request(&keys, &[6_u8; CHALLENGE_BYTES], &nonce, &ciphertext),
+            binding.open_reply_chunk(&keys, &request_nonce, 0, &sealed[0]),
             Err(ProtocolError::AuthenticationFailed)
         );
-        ciphertext[0] ^= 0x80;
+
+        // Truncation: the first chunk relabelled as the last one.
+        let WireChunk::Authenticated {
+            index,
+            nonce,
+            ciphertext,
+            ..
+        } = sealed[0].clone()
+        else {
+            panic!("expected a sealed chunk");
+        };
+        let forged = WireChunk::Authenticated {
+            index,
+            last: true,
+            nonce,
+            ciphertext,
+        };
         assert_eq!(
-            open_request(&keys, &challenge, &nonce, &ciphertext),
+            binding.open_request_chunk(&keys, &request_nonce, 0, &forged),
             Err(ProtocolError::AuthenticationFailed)
         );
+
+        assert_eq!(
+            open_plain_chunk(0, sealed[0].clone()),
+            Err(ProtocolError::UnexpectedProtection)
+        );
     }
 
+    // Every chunk but the last is full, so the chunk count, and with it the
+    // work a stream costs, is bounded by the bytes it carries.
     #[test]
-    fn authenticated_ack_is_tamper_proof_and_bound_to_request() {
-        let keys = derive_auth_keys("secret");
-        let challenge = [2_u8; CHALLENGE_BYTES];
-        let request_nonce = [3_u8; NONCE_BYTES];
-        let ack = Ack::status(true, Some("clipboard_set_ok".to_owned()));
-        let mut response =
-            seal_ack_with_nonce(&keys, &challenge, request_nonce, &ack, [9_u8; NONCE_BYTES])
-                .unwrap();
-        let encoded = encode_ack_frame(&response).unwrap();
-        let (_, payload) = split_frame(&encoded);
+    fn only_the_last_chunk_may_be_short() {
+        let short = WireChunk::Plain(Chunk {
+            index: 0,
+            last: false,
+            text: "short".to_owned(),
+        });
         assert_eq!(
-            decode_ack_payload(payload, MAX_ACK_BYTES).unwrap(),
-            response
+            encode_chunk_frame(&short),
+            Err(ProtocolError::InvalidLength(5))
         );
+        let mut payload = vec![TAG_CHUNK_PLAIN, 0, 0, 0, 0, 0, 0, 0, 0, 5];
+        payload.extend_from_slice(b"short");
+        assert!(matches!(
+            decode_chunk_payload(&payload),
+            Err(ProtocolError::InvalidLength(5))
+        ));
+        payload[5] = CHUNK_FLAG_LAST;
         assert_eq!(
-            open_ack(&keys, &challenge, &request_nonce, &response, MAX_ACK_BYTES).unwrap(),
-            ack
+            decode_chunk_payload(&payload),
+            Ok(WireChunk::Plain(Chunk {
+                index: 0,
+                last: true,
+                text: "short".to_owned(),
+            }))
         );
+        let last = decode_chunk_payload(&payload).unwrap();
+        assert_eq!(open_plain_chunk(1, last), Err(ProtocolError::ChunkOrder(0)));
+        payload[5] = 0x02;
         assert_eq!(
-            open_ack(
-                &keys,
-                &challenge,
-                &[4_u8; NONCE_BYTES],
-                &response,
-                MAX_ACK_BYTES
-            ),
-            Err(ProtocolError::ResponseBinding)
+            decode_chunk_payload(&payload),
+            Err(ProtocolError::UnknownTag(0x02))
+        );
+    }
+
+    #[test]
+    fn text_splits_on_character_boundaries() {
+        assert_eq!(split_text("", 4), vec![""]);
+        assert_eq!(split_text("abcd", 4), vec!["abcd"]);
+        assert_eq!(split_text("abcde", 4), vec!["abcd", "e"]);
+        assert_eq!(split_text("ab✅c", 4), vec!["ab", "✅c"]);
+        assert_eq!(text_chunks(&"x".repeat(CHUNK_BYTES)).len(), 1);
+    }
+
+    // The offer travels with the request whatever its size, and only a body
+    // that shrinks is actually deflated.
+    #[test]
+    fn a_request_offering_compression_round_trips_and_says_so() {
+        let keys = derive_auth_keys("secret");
+        let challenge = [24_u8; CHALLENGE_BYTES];
+        let binding = Binding::Connection(&challenge);
+        let text = "fn main() { println!(\"hello\"); }\n".repeat(4096);
+        let large = PlainRequest::Set {
+            selection: Selection::Primary,
+            text: text.clone(),
+        };
+        let get = PlainRequest::Get {
+            selection: Selection::Clipboard,
+        };
+        for (request, compression, deflated) in [
+            (&large, Compression::Deflate, true),
+            (&large, Compression::Off, false),
+            (&get, Compression::Deflate, false),
+            (&get, Compression::Off, false),
+        ] {
+            let (wire, nonce) = binding.seal_request(&keys, request, compression).unwrap();
+            let WireRequest::Authenticated { ciphertext, .. } = &wire else {
+                panic!("expected an authenticated request");
+            };
+            if request == &large {
+                assert_eq!(ciphertext.len() < text.len() / 4, deflated);
+            }
+            assert_eq!(
+                binding.open_request(&keys, &nonce, ciphertext).unwrap(),
+                (request.clone(), compression)
+            );
+        }
+    }
+
+    // A few kilobytes of deflate can describe gigabytes.  Whatever the frame
+    // looked like, what comes out is held to the same bound as a body that was
+    // never compressed.
+    #[test]
+    fn a_compressed_body_is_bounded_by_what_it_inflates_to() {
+        let keys = derive_auth_keys("secret");
+        let challenge = [25_u8; CHALLENGE_BYTES];
+        let nonce = [26_u8; NONCE_BYTES];
+        let aad = request_aad(&challenge);
+        let seal = |plaintext: &[u8]| encrypt(&keys.request, &nonce, plaintext, &aad).unwrap();
+
+        let mut bomb = vec![TAG_SET];
+        bomb.extend_from_slice(&(MAX_FRAME_BYTES as u32).to_be_bytes());
+        bomb.resize(MAX_FRAME_BYTES + 5, b'a');
+        let deflated = miniz_oxide::deflate::compress_to_vec(&bomb, DEFLATE_LEVEL);
+        let sealed = seal(&[&[TAG_COMPRESSED, COMPRESSED_FLAG_DEFLATE][..], &deflated].concat());
+        assert!(sealed.len() < MAX_ACK_BYTES * 16);
+        assert_eq!(
+            open_request_with_aad(&keys, &aad, &nonce, &sealed),
+            Err(ProtocolError::Decompression)
         );
+        let corrupt = seal(&[TAG_COMPRESSED, COMPRESSED_FLAG_DEFLATE, 0xff, 0xff]);
         assert_eq!(
-            open_ack(
-                &keys,
-                &[8_u8; CHALLENGE_BYTES],
-                &request_nonce,
-                &response,
-                MAX_ACK_BYTES
-            ),
-            Err(ProtocolError::AuthenticationFailed)
+            open_request_with_aad(&keys, &aad, &nonce, &corrupt),
+            Err(ProtocolError::Decompression)
         );
-
-        let WireAck::Authenticated { ciphertext, .. } = &mut response else {
-            panic!("expected authenticated ack");
-        };
-        ciphertext[0] ^= 1;
+        let unknown = seal(&[TAG_COMPRESSED, 0x80, TAG_PING]);
         assert_eq!(
-            open_ack(&keys, &challenge, &request_nonce, &response, MAX_ACK_BYTES),
-            Err(ProtocolError::AuthenticationFailed)
+            open_request_with_aad(&keys, &aad, &nonce, &unknown),
+            Err(ProtocolError::UnknownTag(0x80))
         );
-    }
 
-    #[test]
-    fn server_hello_round_trip_is_bounded_and_tagged() {
-        let hello = ServerHello {
-            challenge: [11_u8; CHALLENGE_BYTES],
-        };
-        let encoded = encode_hello_frame(&hello).unwrap();
-        let (header, payload) = split_frame(&encoded);
-        assert_eq!(parse_header(header).unwrap(), payload.len());
-        assert_eq!(decode_hello_payload(payload).unwrap(), hello);
-        assert_eq!(payload[0], TAG_SERVER_HELLO);
-        assert_eq!(payload.len(), 1 + CHALLENGE_BYTES);
-        assert!(payload.len() <= MAX_ACK_BYTES);
+        // A Get reply deflates to a status-sized frame, but a client that asked
+        // for a status still refuses to inflate it into a clipboard.
+        let ack = Ack::data("a".repeat(512 * 1024), Some("clipboard_get_ok".to_owned()));
+        let request_nonce = [27_u8; NONCE_BYTES];
+        let binding = Binding::Connection(&challenge);
+        let sealed = binding
+            .seal_ack(&keys, request_nonce, &ack, Compression::Deflate)
+            .unwrap();
+        let frame = encode_ack_frame(&sealed).unwrap();
+        assert!(frame.len() < MAX_ACK_BYTES, "{}", frame.len());
+        assert_eq!(
+            binding
+                .open_ack(&keys, &request_nonce, &sealed, MAX_DATA_ACK_BYTES)
+                .unwrap(),
+            ack
+        );
+        assert_eq!(
+            binding.open_ack(&keys, &request_nonce, &sealed, MAX_ACK_BYTES),
+            Err(ProtocolError::Decompression)
+        );
     }
 
     #[test]
@@ -1026,6 +5044,19 @@ mod tests {
                 "pasted\n第二行".to_owned(),
                 Some("clipboard_get_ok".to_owned()),
             ),
+            Ack::history(Vec::new(), None),
+            Ack::history(
+                vec![HistoryEntry {
+                    id: 7,
+                    time: 1_786_000_000,
+                    selection: Selection::Primary,
+                    origin: Origin::Observed,
+                    size: 12,
+                }],
+                Some("history_list_ok".to_owned()),
+            ),
+            Ack::status(false, Some("generation_conflict".to_owned())).with_generation(0),
+            Ack::data("pasted".to_owned(), None).with_generation(u64::MAX),
         ] {
             let limit = ack_body_limit(&ack);
             let wire = WireAck::Plain(ack);
@@ -1051,12 +5082,35 @@ mod tests {
         assert_eq!(ping_payload, [TAG_REQUEST_PLAIN, TAG_PING]);
 
         let set = encode_request_frame(&WireRequest::Plain(PlainRequest::Set {
+            selection: Selection::Clipboard,
             text: "A".to_owned(),
         }))
         .unwrap();
         let (_, set_payload) = split_frame(&set);
         assert_eq!(set_payload, [TAG_REQUEST_PLAIN, TAG_SET, 0, 0, 0, 1, b'A']);
 
+        // A PRIMARY write must not look like a CLIPBOARD write to a daemon that
+        // predates the selection byte, so it never reuses TAG_SET.
+        let primary = encode_request_frame(&WireRequest::Plain(PlainRequest::Set {
+            selection: Selection::Primary,
+            text: "A".to_owned(),
+        }))
+        .unwrap();
+        let (_, primary_payload) = split_frame(&primary);
+        assert_eq!(
+            primary_payload,
+            [
+                TAG_REQUEST_PLAIN,
+                TAG_SET_SELECTION,
+                SELECTION_PRIMARY,
+                0,
+                0,
+                0,
+                1,
+                b'A'
+            ]
+        );
+
         let ack =
             encode_ack_frame(&WireAck::Plain(Ack::status(false, Some("x".to_owned())))).unwrap();
         let (_, ack_payload) = split_frame(&ack);
@@ -1098,6 +5152,125 @@ mod tests {
         );
     }
 
+    // A coded ack is the same body with the flag on its tag and the code after
+    // the ok byte, and it reads back the same from the code alone: the detail
+    // is only what a daemon that sends no code is read by.
+    #[test]
+    fn an_ack_code_rides_next_to_the_detail() {
+        let ack = Ack::status(false, Some("clipboard_busy".to_owned())).coded();
+        assert_eq!(ack.code, Some(AckCode::Busy));
+        let frame = encode_ack_frame(&WireAck::Plain(ack.clone())).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(
+            &payload[..5],
+            [TAG_ACK_PLAIN, TAG_ACK_BODY | ACK_FLAG_CODE, 0, 0, 1]
+        );
+        assert_eq!(
+            decode_ack_payload(payload, MAX_ACK_BYTES).unwrap(),
+            WireAck::Plain(ack)
+        );
+
+        let data = Ack::data("hi".to_owned(), None).with_generation(3).coded();
+        let frame = encode_ack_frame(&WireAck::Plain(data.clone())).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(
+            decode_ack_payload(payload, MAX_DATA_ACK_BYTES).unwrap(),
+            WireAck::Plain(data)
+        );
+
+        let keys = derive_auth_keys("secret");
+        let challenge = [4_u8; CHALLENGE_BYTES];
+        let request_nonce = [5_u8; NONCE_BYTES];
+        let refusal = Ack::status(false, Some("token_not_permitted".to_owned())).coded();
+        let sealed = seal_ack(&keys, &challenge, request_nonce, &refusal).unwrap();
+        let opened = open_ack(&keys, &challenge, &request_nonce, &sealed, MAX_ACK_BYTES).unwrap();
+        assert_eq!(opened.code(), AckCode::NotPermitted);
+
+        let uncoded = Ack::status(false, Some("generation_conflict".to_owned()));
+        assert_eq!(uncoded.code, None);
+        assert_eq!(uncoded.code(), AckCode::GenerationConflict);
+        assert_eq!(Ack::status(true, None).code(), AckCode::Ok);
+        assert_eq!(
+            Ack::status(false, Some("from_the_future".to_owned())).code(),
+            AckCode::Unknown
+        );
+    }
+
+    // An outcome has a body of its own, and nothing else may share it.
+    #[test]
+    fn a_write_outcome_round_trips_and_stands_alone() {
+        for outcome in [
+            WriteOutcome::Unknown,
+            WriteOutcome::Running,
+            WriteOutcome::Applied,
+            WriteOutcome::Failed(AckCode::GenerationConflict),
+        ] {
+            let ack = Ack::outcome(outcome, Some("outcome_ok".to_owned())).coded();
+            let frame = encode_ack_frame(&WireAck::Plain(ack.clone())).unwrap();
+            let (_, payload) = split_frame(&frame);
+            assert_eq!(payload[1], TAG_ACK_OUTCOME_BODY | ACK_FLAG_CODE);
+            assert_eq!(
+                decode_ack_payload(payload, MAX_ACK_BYTES).unwrap(),
+                WireAck::Plain(ack)
+            );
+        }
+
+        let versioned = Ack::outcome(WriteOutcome::Applied, None).with_generation(1);
+        assert!(encode_ack_frame(&WireAck::Plain(versioned)).is_err());
+        let unknown = decode_ack_payload(
+            &[TAG_ACK_PLAIN, TAG_ACK_OUTCOME_BODY, 1, TAG_NONE, 0x09],
+            MAX_ACK_BYTES,
+        );
+        assert!(matches!(unknown, Err(ProtocolError::UnknownTag(0x09))));
+    }
+
+    #[test]
+    fn an_unknown_ack_code_reads_as_the_safe_default() {
+        let coded = |ok: u8, number: u16| {
+            let [high, low] = number.to_be_bytes();
+            decode_ack_payload(
+                &[
+                    TAG_ACK_PLAIN,
+                    TAG_ACK_BODY | ACK_FLAG_CODE,
+                    ok,
+                    high,
+                    low,
+                    TAG_NONE,
+                ],
+                MAX_ACK_BYTES,
+            )
+            .map(|ack| match ack {
+                WireAck::Plain(ack) => ack.code,
+                WireAck::Authenticated { .. } => None,
+            })
+        };
+        for number in 0..=AckCode::Overwritten.number() {
+            let code = AckCode::from_number(false, number);
+            assert_eq!(code.number(), number);
+            assert_eq!(coded(0, number), Ok(Some(code)));
+        }
+        assert_eq!(coded(0, 0x1234), Ok(Some(AckCode::Unknown)));
+        assert_eq!(coded(1, 0x1234), Ok(Some(AckCode::Ok)));
+        assert_eq!(
+            AckCode::from_number(false, AckCode::Unknown.number()),
+            AckCode::Unknown
+        );
+        assert_eq!(
+            decode_ack_payload(
+                &[TAG_ACK_PLAIN, TAG_ACK_BODY | ACK_FLAG_CODE, 0, 0],
+                MAX_ACK_BYTES
+            ),
+            Err(ProtocolError::UnexpectedEof)
+        );
+        assert_eq!(
+            decode_ack_payload(
+                &[TAG_ACK_PLAIN, 0x7f | ACK_FLAG_CODE, 0, 0, 0],
+                MAX_ACK_BYTES
+            ),
+            Err(ProtocolError::UnknownTag(0xff))
+        );
+    }
+
     #[test]
     fn unknown_tags_and_noncanonical_values_are_rejected() {
         assert_eq!(
@@ -1106,7 +5279,11 @@ mod tests {
         );
         assert_eq!(
             decode_request_payload(&[TAG_REQUEST_PLAIN, 0xfe]),
-            Err(ProtocolError::UnknownTag(0xfe))
+            Err(ProtocolError::UnsupportedRequest(0xfe))
+        );
+        assert_eq!(
+            decode_request_payload(&[TAG_REQUEST_PLAIN, TAG_SET_SELECTION, 0x7f, 0, 0, 0, 0]),
+            Err(ProtocolError::UnknownTag(0x7f))
         );
         assert_eq!(
             decode_hello_payload(&[0xfd]),
@@ -1133,6 +5310,7 @@ mod tests {
     #[test]
     fn truncated_payloads_and_declared_lengths_are_rejected() {
         let request = WireRequest::Authenticated {
+            key: None,
             nonce: [1_u8; NONCE_BYTES],
             ciphertext: vec![2_u8; MIN_REQUEST_CIPHERTEXT_BYTES],
         };
@@ -1188,6 +5366,7 @@ mod tests {
         );
 
         let oversized = WireRequest::Plain(PlainRequest::Set {
+            selection: Selection::Clipboard,
             text: "x".repeat(MAX_FRAME_BYTES),
         });
         assert!(matches!(
@@ -1199,6 +5378,7 @@ mod tests {
     #[test]
     fn advertised_set_text_limit_fits_plain_and_authenticated_frames() {
         let request = PlainRequest::Set {
+            selection: Selection::Clipboard,
             text: "x".repeat(MAX_SET_TEXT_BYTES),
         };
         assert!(encode_request_frame(&WireRequest::Plain(request.clone())).is_ok());
@@ -1209,10 +5389,432 @@ mod tests {
         let (wire, _) = seal_request_with_nonce(&keys, &challenge, &request, nonce).unwrap();
         assert!(encode_request_frame(&wire).is_ok());
 
-        let over = PlainRequest::Set {
-            text: "x".repeat(MAX_SET_TEXT_BYTES + 1),
+        // The advertised limit is the one that fits every selection, so the
+        // selection byte a PRIMARY write carries is already paid for.
+        let primary = PlainRequest::Set {
+            selection: Selection::Primary,
+            text: "x".repeat(MAX_SET_TEXT_BYTES),
+        };
+        let (wire, _) = seal_request_with_nonce(&keys, &challenge, &primary, nonce).unwrap();
+        assert!(encode_request_frame(&wire.identified(keys.id())).is_ok());
+
+        // One byte more no longer fits once the request names its key, and
+        // past the key id it cannot even be sealed.
+        let over = |extra| PlainRequest::Set {
+            selection: Selection::Primary,
+            text: "x".repeat(MAX_SET_TEXT_BYTES + extra),
+        };
+        let (wire, _) = seal_request_with_nonce(&keys, &challenge, &over(1), nonce).unwrap();
+        assert!(encode_request_frame(&wire).is_ok());
+        assert!(encode_request_frame(&wire.identified(keys.id())).is_err());
+        assert!(
+            seal_request_with_nonce(&keys, &challenge, &over(KEY_ID_BYTES + 1), nonce).is_err()
+        );
+    }
+
+    #[test]
+    fn an_append_fits_a_keyed_frame_up_to_its_advertised_limit() {
+        let keys = derive_auth_keys("token");
+        let challenge = [7; CHALLENGE_BYTES];
+        let nonce = [9; NONCE_BYTES];
+        let separator = "\n".repeat(MAX_SEPARATOR_BYTES);
+        let append = |extra| PlainRequest::Append {
+            selection: Selection::Primary,
+            separator: separator.clone(),
+            text: "x".repeat(max_append_text_bytes(&separator) + extra),
+        };
+        let (wire, _) = seal_request_with_nonce(&keys, &challenge, &append(0), nonce).unwrap();
+        assert!(encode_request_frame(&wire.identified(keys.id())).is_ok());
+        let (wire, _) = seal_request_with_nonce(&keys, &challenge, &append(1), nonce).unwrap();
+        assert!(encode_request_frame(&wire.identified(keys.id())).is_err());
+
+        let long = PlainRequest::Append {
+            selection: Selection::Clipboard,
+            separator: format!("{separator}x"),
+            text: String::new(),
+        };
+        assert_eq!(
+            encode_request_frame(&WireRequest::Plain(long)),
+            Err(ProtocolError::InvalidLength(MAX_SEPARATOR_BYTES + 1))
+        );
+    }
+
+    // The limit leaves room for an expected generation, so a versioned Set
+    // without one fits too; and only a status or data ack carries one.
+    #[test]
+    fn a_versioned_set_fits_a_keyed_frame_up_to_its_advertised_limit() {
+        let keys = derive_auth_keys("token");
+        let challenge = [7; CHALLENGE_BYTES];
+        let nonce = [9; NONCE_BYTES];
+        for expected in [None, Some(3)] {
+            let set = |extra| PlainRequest::SetVersioned {
+                selection: Selection::Primary,
+                expected,
+                text: "x".repeat(MAX_VERSIONED_SET_TEXT_BYTES + extra),
+            };
+            let (wire, _) = seal_request_with_nonce(&keys, &challenge, &set(0), nonce).unwrap();
+            assert!(encode_request_frame(&wire.identified(keys.id())).is_ok());
+            if expected.is_some() {
+                let (wire, _) = seal_request_with_nonce(&keys, &challenge, &set(1), nonce).unwrap();
+                assert!(encode_request_frame(&wire.identified(keys.id())).is_err());
+            }
+        }
+
+        let slots = Ack::slots(Vec::new(), None).with_generation(1);
+        assert_eq!(
+            encode_ack_frame(&WireAck::Plain(slots)),
+            Err(ProtocolError::InvalidLength(GENERATION_BYTES))
+        );
+        let plain = encode_ack_frame(&WireAck::Plain(Ack::status(true, None))).unwrap();
+        let (_, payload) = split_frame(&plain);
+        assert_eq!(payload, [TAG_ACK_PLAIN, TAG_ACK_BODY, 1, TAG_NONE]);
+        let counted = encode_ack_frame(&WireAck::Plain(
+            Ack::status(true, None).with_generation(258),
+        ))
+        .unwrap();
+        let (_, payload) = split_frame(&counted);
+        assert_eq!(
+            payload,
+            [
+                TAG_ACK_PLAIN,
+                TAG_ACK_GENERATION_BODY,
+                1,
+                TAG_NONE,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                1,
+                2
+            ]
+        );
+    }
+
+    // The expiry takes its room from the text, as the generation does, and a
+    // daemon never reads an expiry of none or of more than a day.
+    #[test]
+    fn an_expiring_set_fits_a_keyed_frame_and_carries_a_bounded_expiry() {
+        let keys = derive_auth_keys("token");
+        let challenge = [7; CHALLENGE_BYTES];
+        let nonce = [9; NONCE_BYTES];
+        let set = |seconds, extra| PlainRequest::SetExpiring {
+            selection: Selection::Primary,
+            seconds,
+            text: "x".repeat(MAX_EXPIRING_SET_TEXT_BYTES + extra),
+        };
+        let (wire, _) = seal_request_with_nonce(&keys, &challenge, &set(30, 0), nonce).unwrap();
+        assert!(encode_request_frame(&wire.identified(keys.id())).is_ok());
+        let (wire, _) = seal_request_with_nonce(&keys, &challenge, &set(30, 1), nonce).unwrap();
+        assert!(encode_request_frame(&wire.identified(keys.id())).is_err());
+
+        for seconds in [0, MAX_EXPIRY_SECONDS + 1] {
+            let short = PlainRequest::SetExpiring {
+                selection: Selection::Clipboard,
+                seconds,
+                text: String::new(),
+            };
+            assert_eq!(
+                encode_request_frame(&WireRequest::Plain(short)),
+                Err(ProtocolError::InvalidExpiry(seconds))
+            );
+            let mut payload = vec![TAG_REQUEST_PLAIN, TAG_SET_EXPIRING, 0];
+            payload.extend_from_slice(&seconds.to_be_bytes());
+            payload.extend_from_slice(&0_u32.to_be_bytes());
+            assert_eq!(
+                decode_request_payload(&payload),
+                Err(ProtocolError::InvalidExpiry(seconds))
+            );
+        }
+    }
+
+    // A sensitive Set may expire as well, within the same bounds, and its
+    // limit leaves room for the expiry either way.
+    #[test]
+    fn a_sensitive_set_fits_a_keyed_frame_with_or_without_an_expiry() {
+        let keys = derive_auth_keys("token");
+        let challenge = [7; CHALLENGE_BYTES];
+        let nonce = [9; NONCE_BYTES];
+        for seconds in [None, Some(30)] {
+            let set = |extra| PlainRequest::SetSensitive {
+                selection: Selection::Primary,
+                seconds,
+                text: "x".repeat(MAX_SENSITIVE_SET_TEXT_BYTES + extra),
+            };
+            let (wire, _) = seal_request_with_nonce(&keys, &challenge, &set(0), nonce).unwrap();
+            assert!(encode_request_frame(&wire.identified(keys.id())).is_ok());
+            if seconds.is_some() {
+                let (wire, _) = seal_request_with_nonce(&keys, &challenge, &set(1), nonce).unwrap();
+                assert!(encode_request_frame(&wire.identified(keys.id())).is_err());
+            }
+        }
+
+        let mut payload = vec![TAG_REQUEST_PLAIN, TAG_SET_SENSITIVE, 0, TAG_SOME];
+        payload.extend_from_slice(&0_u32.to_be_bytes());
+        payload.extend_from_slice(&0_u32.to_be_bytes());
+        assert_eq!(
+            decode_request_payload(&payload),
+            Err(ProtocolError::InvalidExpiry(0))
+        );
+        let payload = [TAG_REQUEST_PLAIN, TAG_SET_SENSITIVE, 0, 2, 0, 0, 0, 0];
+        assert_eq!(
+            decode_request_payload(&payload),
+            Err(ProtocolError::UnknownTag(2))
+        );
+    }
+
+    // A list is answered in one status-sized ack however full the history is,
+    // so the client reads it with the same bound as a ping.
+    #[test]
+    fn a_full_history_fits_a_status_ack_and_no_more_does() {
+        let entry = HistoryEntry {
+            id: u64::MAX,
+            time: u64::MAX,
+            selection: Selection::Clipboard,
+            origin: Origin::Set,
+            size: u64::MAX,
+        };
+        let full = Ack::history(
+            vec![entry; MAX_HISTORY_ENTRIES],
+            Some("history_search_ok".to_owned()),
+        );
+        let keys = derive_auth_keys("history");
+        let challenge = [5_u8; CHALLENGE_BYTES];
+        let sealed = seal_ack(&keys, &challenge, [6_u8; NONCE_BYTES], &full).unwrap();
+        let frame = encode_ack_frame(&sealed).unwrap();
+        assert!(frame.len() <= FRAME_HEADER_BYTES + MAX_ACK_BYTES);
+        let (_, payload) = split_frame(&frame);
+        let response = decode_ack_payload(payload, MAX_ACK_BYTES).unwrap();
+        let opened = open_ack(
+            &keys,
+            &challenge,
+            &[6_u8; NONCE_BYTES],
+            &response,
+            MAX_ACK_BYTES,
+        );
+        assert_eq!(opened.unwrap(), full);
+
+        let over = Ack::history(vec![entry; MAX_HISTORY_ENTRIES + 1], None);
+        assert_eq!(
+            encode_ack_frame(&WireAck::Plain(over)),
+            Err(ProtocolError::InvalidLength(MAX_HISTORY_ENTRIES + 1))
+        );
+    }
+
+    #[test]
+    fn store_record_hides_the_entry_and_rejects_wrong_key_or_tampering() {
+        let text = "copied password that must not reach the disk";
+        let entry = HistoryEntry {
+            id: 42,
+            time: 1_700_000_000,
+            selection: Selection::Primary,
+            origin: Origin::Observed,
+            size: text.len() as u64,
+        };
+        let key = derive_store_key(b"secret");
+        let mut record = seal_store_record(&key, &entry, text).unwrap();
+        assert!(
+            !record
+                .windows(text.len())
+                .any(|window| window == text.as_bytes())
+        );
+        assert_eq!(
+            open_store_record(&key, &record).unwrap(),
+            (entry, text.to_owned())
+        );
+        // Two seals of the same entry never share a nonce.
+        assert_ne!(seal_store_record(&key, &entry, text).unwrap(), record);
+
+        // The token's SCB1 keys are not the store key, and neither is the
+        // store key of another secret.
+        let auth_keys = derive_auth_keys("secret");
+        assert_ne!(auth_keys.request, key.0);
+        assert_ne!(auth_keys.ack, key.0);
+        assert_eq!(
+            open_store_record(&derive_store_key(b"other"), &record),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+        let last = record.len() - 1;
+        record[last] ^= 0x01;
+        assert_eq!(
+            open_store_record(&key, &record),
+            Err(ProtocolError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn a_slot_name_is_short_and_plain() {
+        for name in [
+            "a",
+            "Z9",
+            "snippet.rs-2_b",
+            &"n".repeat(MAX_SLOT_NAME_BYTES),
+        ] {
+            assert_eq!(SlotName::parse(name).unwrap().as_str(), name);
+        }
+        for name in ["", "a b", "a/b", "é", &"n".repeat(MAX_SLOT_NAME_BYTES + 1)] {
+            assert_eq!(SlotName::parse(name), None, "{name:?}");
+        }
+        // Whatever the sender's encoder allowed, the decoder checks again.
+        let frame = encode_request_frame(&WireRequest::Plain(PlainRequest::SlotGet {
+            name: SlotName::parse("ab").unwrap(),
+        }))
+        .unwrap();
+        let (_, payload) = split_frame(&frame);
+        let mut forged = payload.to_vec();
+        forged[3] = b'/';
+        assert_eq!(
+            decode_request_payload(&forged),
+            Err(ProtocolError::InvalidSlotName)
+        );
+    }
+
+    #[test]
+    fn a_full_slot_list_fits_a_status_ack_and_no_more_does() {
+        let slot = SlotEntry {
+            name: SlotName::parse(&"n".repeat(MAX_SLOT_NAME_BYTES)).unwrap(),
+            time: u64::MAX,
+            size: u64::MAX,
+        };
+        let full = Ack::slots(
+            vec![slot.clone(); MAX_SLOTS],
+            Some("slot_list_ok".to_owned()),
+        );
+        let frame = encode_ack_frame(&WireAck::Plain(full.clone())).unwrap();
+        assert!(frame.len() <= FRAME_HEADER_BYTES + MAX_ACK_BYTES);
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(
+            decode_ack_payload(payload, ack_limit(&PlainRequest::SlotList)).unwrap(),
+            WireAck::Plain(full)
+        );
+
+        let over = Ack::slots(vec![slot; MAX_SLOTS + 1], None);
+        assert_eq!(
+            encode_ack_frame(&WireAck::Plain(over)),
+            Err(ProtocolError::InvalidLength(MAX_SLOTS + 1))
+        );
+    }
+
+    #[test]
+    fn a_full_status_report_fits_a_status_ack_and_a_longer_name_does_not() {
+        let name = "n".repeat(MAX_STATUS_NAME_BYTES);
+        let report = StatusReport {
+            version: name.clone(),
+            uptime: u64::MAX,
+            address: name.clone(),
+            auth: AuthMode::TokensAndKeys,
+            backend: name.clone(),
+            last_operation: Some(false),
+            queue_depth: u32::MAX,
+            connections: u32::MAX,
+            details: vec![(name.clone(), u64::MAX); MAX_STATUS_DETAILS],
+            expiries: vec![
+                (Selection::Clipboard, u32::MAX),
+                (Selection::Primary, u32::MAX),
+            ],
+            restarts: Some(u32::MAX),
+        };
+        let full = Ack::report(report.clone(), Some("status_ok".to_owned()));
+        let frame = encode_ack_frame(&WireAck::Plain(full.clone())).unwrap();
+        assert!(frame.len() <= FRAME_HEADER_BYTES + MAX_ACK_BYTES);
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(
+            decode_ack_payload(payload, ack_limit(&PlainRequest::Status)).unwrap(),
+            WireAck::Plain(full)
+        );
+
+        let quiet = StatusReport {
+            last_operation: None,
+            details: Vec::new(),
+            expiries: Vec::new(),
+            auth: AuthMode::Tokens,
+            ..report.clone()
+        };
+        let quiet = WireAck::Plain(Ack::report(quiet, None));
+        let frame = encode_ack_frame(&quiet).unwrap();
+        let (_, payload) = split_frame(&frame);
+        assert_eq!(decode_ack_payload(payload, MAX_ACK_BYTES).unwrap(), quiet);
+
+        let long = StatusReport {
+            backend: format!("{name}x"),
+            ..report.clone()
+        };
+        assert_eq!(
+            encode_ack_frame(&WireAck::Plain(Ack::report(long, None))),
+            Err(ProtocolError::InvalidLength(MAX_STATUS_NAME_BYTES + 1))
+        );
+        let crowded = StatusReport {
+            details: vec![(name, 0); MAX_STATUS_DETAILS + 1],
+            ..report.clone()
         };
-        assert!(seal_request_with_nonce(&keys, &challenge, &over, nonce).is_err());
+        assert_eq!(
+            encode_ack_frame(&WireAck::Plain(Ack::report(crowded, None))),
+            Err(ProtocolError::InvalidLength(MAX_STATUS_DETAILS + 1))
+        );
+        let pending = StatusReport {
+            expiries: vec![(Selection::Clipboard, 1); MAX_STATUS_EXPIRIES + 1],
+            ..report
+        };
+        assert_eq!(
+            encode_ack_frame(&WireAck::Plain(Ack::report(pending, None))),
+            Err(ProtocolError::InvalidLength(MAX_STATUS_EXPIRIES + 1))
+        );
+    }
+
+    // The report as daemons sent it before the worker counted its restarts:
+    // the expiries end it.
+    #[test]
+    fn a_status_report_without_restarts_decodes_as_before() {
+        let mut bytes = vec![3];
+        bytes.extend_from_slice(b"0.2");
+        bytes.extend_from_slice(&7u64.to_be_bytes());
+        bytes.push(14);
+        bytes.extend_from_slice(b"127.0.0.1:3000");
+        bytes.push(AuthMode::Tokens.tag());
+        bytes.push(3);
+        bytes.extend_from_slice(b"x11");
+        bytes.extend_from_slice(&[TAG_SOME, 1]);
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.push(16);
+        bytes.extend_from_slice(b"clipboard_set_ok");
+        bytes.extend_from_slice(&2u64.to_be_bytes());
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.push(Selection::Clipboard.tag());
+        bytes.extend_from_slice(&30u32.to_be_bytes());
+
+        let mut decoder = Decoder::new(&bytes);
+        let report = StatusReport::decode(&mut decoder).unwrap();
+        assert_eq!(decoder.finish(), Ok(()));
+        assert_eq!(
+            report,
+            StatusReport {
+                version: "0.2".to_owned(),
+                uptime: 7,
+                address: "127.0.0.1:3000".to_owned(),
+                auth: AuthMode::Tokens,
+                backend: "x11".to_owned(),
+                last_operation: Some(true),
+                queue_depth: 0,
+                connections: 1,
+                details: vec![("clipboard_set_ok".to_owned(), 2)],
+                expiries: vec![(Selection::Clipboard, 30)],
+                restarts: None,
+            }
+        );
+
+        // The count follows everything an older client reads.
+        let mut encoded = Vec::new();
+        StatusReport {
+            restarts: Some(4),
+            ..report
+        }
+        .encode(&mut encoded)
+        .unwrap();
+        assert_eq!(encoded[..bytes.len()], bytes[..]);
+        assert_eq!(encoded[bytes.len()..], 4u32.to_be_bytes());
     }
 
     // A Get reply carries a clipboard, so it needs a frame-sized bound; a ping
@@ -1238,6 +5840,7 @@ mod tests {
         assert_eq!(ack_limit(&PlainRequest::Ping), MAX_ACK_BYTES);
         assert_eq!(
             ack_limit(&PlainRequest::Set {
+                selection: Selection::Clipboard,
                 text: String::new()
             }),
             MAX_ACK_BYTES
@@ -17,60 +17,265 @@
 //!
 //! Everything about the request itself — framing, AEAD sealing, the challenge
 //! and the response binding — is the library's `send_request`, verbatim, so the
-//! two transports cannot drift apart.
+//! two transports cannot drift apart.  A `set` or `get` goes through the
+//! library's `send_stream` and `receive_stream` instead, which send text that
+//! fits one chunk as the same single request and stream anything longer, and a
+//! `watch` holds one subscription open through the library's `watch`.  The
+//! `history-*` actions, `slot-list`, `status`, `append`, `generation`, a `set`
+//! naming `--if-generation`, `--ttl`, `--sensitive` or `--verify`, and a `set`
+//! or `get` naming a `--slot`,
+//! are single requests like `ping`.
 //!
-//! The token is read from the environment and the clipboard payload from stdin.
-//! Neither is ever an argument: `/proc/*/cmdline` is world-readable, so an
+//! The token is read from the environment, and the clipboard payload, a slot's
+//! text and a history search query from stdin.  None of them is ever an argument: `/proc/*/cmdline` is world-readable, so an
 //! argv-carried token or clipboard would be visible to every process on the
-//! machine for as long as this one runs.
+//! machine for as long as this one runs.  A key pair in place of the token is
+//! read from a file only this user can read, named by the environment too;
+//! `--generate-key` writes one.
 
-use simpleclipboard::protocol::{MAX_SET_TEXT_BYTES, PlainRequest, Selection};
-use simpleclipboard::{ClientError, ClientRequest, ack_result, send_request};
+use simpleclipboard::protocol::{
+    Ack, CHUNK_BYTES, Change, HistoryEntry, KeyPair, MAX_EVENT_TEXT_BYTES,
+    MAX_EXPIRING_SET_TEXT_BYTES, MAX_EXPIRY_SECONDS, MAX_SENSITIVE_SET_TEXT_BYTES,
+    MAX_SEPARATOR_BYTES, MAX_SET_TEXT_BYTES, MAX_SLOT_NAME_BYTES, MAX_SLOT_TEXT_BYTES,
+    MAX_VERSIONED_SET_TEXT_BYTES, PlainRequest, PublicKey, Selection, SlotEntry, SlotName,
+    StatusReport, max_append_text_bytes,
+};
+use simpleclipboard::{
+    ClientError, ClientRequest, Identity, ack_result, receive_stream, send_request, send_stream,
+    watch,
+};
 use std::env;
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::Arc;
 
 // The same vocabulary the FFI returns, so the Vim side reads one set of
 // outcomes whichever transport carried the request.
 const EXIT_OK: u8 = 0;
 const EXIT_FAILED: u8 = 1;
 const EXIT_OUTCOME_UNKNOWN: u8 = 2;
+const EXIT_CONFLICT: u8 = 3;
+const EXIT_OVERWRITTEN: u8 = 4;
 const EXIT_USAGE: u8 = 64;
 
 const TOKEN_VARIABLE: &str = "SIMPLECLIPBOARD_TOKEN";
+const IDENTITY_VARIABLE: &str = "SIMPLECLIPBOARD_IDENTITY_FILE";
+const HOST_KEY_VARIABLE: &str = "SIMPLECLIPBOARD_HOST_KEY";
 
 struct Options {
     address: String,
     action: String,
     selection: Selection,
+    with_text: bool,
+    id: Option<u64>,
+    slot: Option<SlotName>,
+    separator: Option<String>,
+    if_generation: Option<u64>,
+    ttl: Option<u32>,
+    sensitive: bool,
+    verify: bool,
+    json: bool,
 }
 
 fn usage() -> String {
     format!(
         "simpleclipboard-client {}\n\n\
-         Usage: simpleclipboard-client --address HOST:PORT --action ping|set|get\n\
-         \x20                          [--selection clipboard|primary]\n\n\
-         --selection applies to `get` only; SCB1 has no room for a selection in a\n\
-         `set`, so every write goes to CLIPBOARD and naming a selection there is a\n\
-         usage error rather than a silent write to the wrong place.\n\n\
+         Usage: simpleclipboard-client --address HOST:PORT --action ACTION\n\
+         \x20                          [--selection clipboard|primary] [--with-text] [--id ID]\n\
+         \x20                          [--slot NAME] [--separator TEXT] [--if-generation N]\n\
+         \x20                          [--ttl SECONDS] [--sensitive] [--verify] [--json]\n\
+         \x20      simpleclipboard-client --generate-key PATH\n\n\
+         ACTION is ping, set, get, clear, append, watch, generation,\n\
+         history-list, history-get, history-search, slot-list or status.\n\n\
+         --selection applies to `set`, `get`, `clear`, `append`, `watch` and `generation`\n\
+         (default clipboard); a `ping` addresses no selection, and naming one\n\
+         there is a usage error.  A daemon too old to write PRIMARY refuses such\n\
+         a `set` rather than writing the clipboard instead.  A `clear` reads\n\
+         nothing from standard input.\n\n\
          The text of a `set` is read from standard input; the text of a `get` is\n\
-         written to standard output.  The pre-shared key is read from\n\
-         {TOKEN_VARIABLE}; it is deliberately not a command-line argument.\n\n\
+         written to standard output.  Text longer than {CHUNK_BYTES} bytes is\n\
+         streamed in chunks, up to the limit the daemon advertises; a `get` that\n\
+         fails partway may have written part of the text, and only exit status\n\
+         {EXIT_OK} means the output is whole.\n\n\
+         An `append` adds the text read from standard input to the end of the\n\
+         selection, after --separator (default none) unless the selection is\n\
+         empty.  The daemon reads and writes the selection in one step, and\n\
+         refuses with `append_too_large` a result over {MAX_SET_TEXT_BYTES} bytes.\n\n\
+         `generation` needs the token and prints the selection's generation, a\n\
+         number the daemon raises each time it sees the selection change.\n\
+         `--if-generation N` makes a `set` write only while the selection is\n\
+         still at generation N, and exit {EXIT_CONFLICT} without writing otherwise; its\n\
+         text, at most {MAX_VERSIONED_SET_TEXT_BYTES} bytes, is read from standard input.  Read the\n\
+         generation before the text it guards, and a change in between is a\n\
+         conflict rather than a lost copy.\n\n\
+         `--ttl SECONDS` makes a `set` expire: after 1 to {MAX_EXPIRY_SECONDS} seconds the daemon\n\
+         clears the selection, unless it holds other text by then.  Its text, at\n\
+         most {MAX_EXPIRING_SET_TEXT_BYTES} bytes, is read from standard input, and the daemon\n\
+         keeps it out of its history.\n\n\
+         `--sensitive` marks a `set` as a secret: the daemon offers it with the\n\
+         hint clipboard managers read as \"do not record this\" and keeps it out\n\
+         of its history.  It may join `--ttl`, and its text, at most\n\
+         {MAX_SENSITIVE_SET_TEXT_BYTES} bytes, is read from standard input.\n\n\
+         `--verify` has the daemon read a `set` back a moment after writing it,\n\
+         and exit {EXIT_OVERWRITTEN} with `clipboard_overwritten` if another program has\n\
+         already replaced the text.  It stands alone, and its text, at most\n\
+         {MAX_SET_TEXT_BYTES} bytes, is read from standard input.\n\n\
+         A `watch` needs the token and runs until interrupted, printing one line\n\
+         per change: the selection, the new text's size in bytes and its SHA-256.\n\
+         The first line describes the selection as it stood.  With --with-text a\n\
+         change of at most {MAX_EVENT_TEXT_BYTES} bytes adds a fourth field,\n\
+         `text`, and is followed by exactly that many bytes of text and a\n\
+         newline.  The daemon ending the subscription is exit status {EXIT_FAILED}.\n\n\
+         The `history-*` actions need the token.  `history-list` prints one line\n\
+         per value the daemon remembers, newest first: its id, the time in seconds\n\
+         since the Unix epoch, the selection, `set` or `observed`, and the size in\n\
+         bytes.  `history-search` prints the lines of the values containing the\n\
+         query read from standard input, less one trailing newline.\n\
+         `history-get --id ID` writes that value to standard output, verbatim.\n\n\
+         `--slot NAME` points a `set` or `get` at one of the daemon's named slots\n\
+         instead of a selection: up to {MAX_SLOT_TEXT_BYTES} bytes kept in the daemon's memory,\n\
+         never on the system clipboard.  A name is 1 to {MAX_SLOT_NAME_BYTES} ASCII letters, digits,\n\
+         `-`, `_` or `.`, and setting a slot to empty text removes it.\n\
+         `slot-list` prints one line per slot, in name order: its name, the time\n\
+         it was set in seconds since the Unix epoch, and its size in bytes.  Slots\n\
+         need the token.\n\n\
+         `status` needs the token and prints what the daemon says about itself,\n\
+         one `name value` line each: its version, uptime in seconds, listen\n\
+         address, authentication (`token`, `key` or `token+key`), clipboard\n\
+         backend, whether its last clipboard operation succeeded (`ok`, `failed`\n\
+         or `none`), the operations waiting for the clipboard, the connections\n\
+         open and the times its clipboard worker was replaced, then one `detail NAME COUNT` line per ack detail it has sent and\n\
+         one `expiry SELECTION SECONDS` line per selection a `--ttl` will clear.\n\
+         With --json it prints one JSON object instead.\n\n\
+         The pre-shared key is read from\n\
+         {TOKEN_VARIABLE}; it is deliberately not a command-line argument.\n\
+         Instead of a token, {IDENTITY_VARIABLE} may name a key pair file,\n\
+         mode 0600, whose public key is in the daemon's authorized keys;\n\
+         {HOST_KEY_VARIABLE} must then hold the public key the daemon logs at\n\
+         start-up, and a daemon that cannot prove it holds that key is refused.\n\
+         `--generate-key PATH` writes a new key pair to PATH, which must not\n\
+         exist, and prints its public key.\n\n\
          Exit status: {EXIT_OK} success, {EXIT_FAILED} failure,\n\
          {EXIT_OUTCOME_UNKNOWN} the clipboard write started but its outcome is\n\
-         unknown, {EXIT_USAGE} usage error.",
+         unknown, {EXIT_CONFLICT} the selection had moved on from --if-generation,\n\
+         {EXIT_OVERWRITTEN} another program replaced a --verify set,\n\
+         {EXIT_USAGE} usage error.",
         env!("CARGO_PKG_VERSION")
     )
 }
 
 fn parse_options() -> Result<Option<Options>, String> {
-    parse_arguments(env::args().skip(1))
+    let arguments = env::args().skip(1).collect::<Vec<_>>();
+    if let Some(path) = parse_generate_key(&arguments)? {
+        let public_key = generate_key(&path)
+            .map_err(|error| format!("cannot write {}: {error}", path.display()))?;
+        println!("{public_key}");
+        return Ok(None);
+    }
+    parse_arguments(arguments.into_iter())
+}
+
+// `--generate-key PATH` is a command of its own, not an option to a request.
+fn parse_generate_key(arguments: &[String]) -> Result<Option<PathBuf>, String> {
+    if !arguments
+        .iter()
+        .any(|argument| argument == "--generate-key")
+    {
+        return Ok(None);
+    }
+    match arguments {
+        [option, path] if option == "--generate-key" => Ok(Some(PathBuf::from(path))),
+        _ => Err("--generate-key PATH takes no other options".to_owned()),
+    }
+}
+
+// Never over an existing file: that may be the key pair a daemon already
+// authorizes, and overwriting it would lock this client out.
+fn generate_key(path: &Path) -> std::io::Result<PublicKey> {
+    let keys = KeyPair::generate().map_err(std::io::Error::other)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(keys.to_file_contents().as_bytes())?;
+    file.sync_all()?;
+    Ok(keys.public_key())
+}
+
+// The token, or the key pair and pinned host key, that `request` is sealed
+// with.  Both at once would leave it unclear which the daemon is meant to
+// check, so that is refused rather than one of them quietly ignored.
+fn authenticate(
+    request: PlainRequest,
+    token: &str,
+    identity: Option<&Path>,
+    host_key: Option<&str>,
+) -> Result<ClientRequest, String> {
+    let Some(identity) = identity else {
+        return Ok(ClientRequest::new(request, token));
+    };
+    if !token.is_empty() {
+        return Err(format!(
+            "set {TOKEN_VARIABLE} or {IDENTITY_VARIABLE}, not both"
+        ));
+    }
+    let host_key = host_key
+        .filter(|host_key| !host_key.is_empty())
+        .ok_or_else(|| format!("{IDENTITY_VARIABLE} needs {HOST_KEY_VARIABLE}"))?;
+    let host_key = PublicKey::parse(host_key)
+        .ok_or_else(|| format!("{HOST_KEY_VARIABLE} must be 64 hex digits"))?;
+    let keys = read_identity(identity)
+        .map_err(|error| format!("cannot read {IDENTITY_VARIABLE}: {error}"))?;
+    Ok(ClientRequest::with_identity(
+        request,
+        Arc::new(Identity::new(keys, host_key)),
+    ))
+}
+
+// A key pair anyone else could read is a key pair anyone else could use.
+fn read_identity(path: &Path) -> std::io::Result<KeyPair> {
+    let file = File::open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = file.metadata()?;
+        // SAFETY: getuid has no arguments and cannot fail.
+        if metadata.uid() != unsafe { libc::getuid() } || metadata.mode() & 0o077 != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "the file must be owned by this user and mode 0600",
+            ));
+        }
+    }
+    let mut contents = Vec::new();
+    file.take(256).read_to_end(&mut contents)?;
+    let keys = std::str::from_utf8(&contents).ok().and_then(KeyPair::parse);
+    contents.fill(0);
+    keys.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not a key from --generate-key",
+        )
+    })
 }
 
 fn parse_arguments(mut arguments: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
     let mut address = None;
     let mut action = None;
     let mut selection = None;
+    let mut with_text = false;
+    let mut id = None;
+    let mut slot = None;
+    let mut separator = None;
+    let mut if_generation = None;
+    let mut ttl = None;
+    let mut sensitive = false;
+    let mut verify = false;
+    let mut json = false;
 
     while let Some(argument) = arguments.next() {
         match argument.as_str() {
@@ -91,6 +296,45 @@ fn parse_arguments(mut arguments: impl Iterator<Item = String>) -> Result<Option
                         .ok_or_else(|| format!("unknown selection: {value}"))?,
                 );
             }
+            "--with-text" => with_text = true,
+            "--separator" => separator = Some(next_value(&mut arguments, "--separator")?),
+            "--json" => json = true,
+            "--sensitive" => sensitive = true,
+            "--verify" => verify = true,
+            "--if-generation" => {
+                let value = next_value(&mut arguments, "--if-generation")?;
+                if_generation = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--if-generation must be a generation: {value}"))?,
+                );
+            }
+            "--ttl" => {
+                let value = next_value(&mut arguments, "--ttl")?;
+                ttl = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|seconds| (1..=MAX_EXPIRY_SECONDS).contains(seconds))
+                        .ok_or_else(|| {
+                            format!("--ttl must be 1 to {MAX_EXPIRY_SECONDS} seconds: {value}")
+                        })?,
+                );
+            }
+            "--id" => {
+                let value = next_value(&mut arguments, "--id")?;
+                id = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--id must be a history entry id: {value}"))?,
+                );
+            }
+            "--slot" => {
+                let value = next_value(&mut arguments, "--slot")?;
+                slot = Some(
+                    SlotName::parse(&value).ok_or_else(|| format!("invalid slot name: {value}"))?,
+                );
+            }
             other => return Err(format!("unknown option: {other}")),
         }
     }
@@ -100,23 +344,95 @@ fn parse_arguments(mut arguments: impl Iterator<Item = String>) -> Result<Option
     if address.is_empty() {
         return Err("--address must not be empty".to_owned());
     }
-    // Only Get carries a selection on the wire: PlainRequest::Set is a bare
-    // length-prefixed string and the daemon's set path hardcodes CLIPBOARD.
-    // Accepting `--action set --selection primary` therefore wrote CLIPBOARD
-    // and exited 0, which is the worst possible answer for someone scripting a
-    // PRIMARY write - the one outcome they would never check for.  Refuse it
-    // instead, and keep the refusal a usage error so it cannot be mistaken for
-    // an unreachable daemon.
-    if selection.is_some() && action != "get" {
+    // A selection only means something to a request that carries one on the
+    // wire.  Accepting it anywhere else is how `--action set --selection
+    // primary` once wrote CLIPBOARD and exited 0 - the one outcome a caller
+    // scripting a PRIMARY write would never check for - so it stays a usage
+    // error rather than something to ignore.
+    if selection.is_some()
+        && !matches!(
+            action.as_str(),
+            "set" | "get" | "clear" | "append" | "watch" | "generation"
+        )
+    {
+        return Err(format!(
+            "--selection applies to --action set, get, clear, append, watch and generation; a \
+             `{action}` addresses no selection"
+        ));
+    }
+    if with_text && action != "watch" {
+        return Err(format!(
+            "--with-text applies to --action watch; a `{action}` reports no changes"
+        ));
+    }
+    if id.is_some() != (action == "history-get") {
+        return Err("--id is required by --action history-get, and only by it".to_owned());
+    }
+    // A slot replaces the selection rather than refining it, so naming both is
+    // as ambiguous as naming a slot for a request that carries none.
+    if slot.is_some() && (selection.is_some() || !matches!(action.as_str(), "set" | "get")) {
+        return Err("--slot applies to --action set and get, in place of --selection".to_owned());
+    }
+    if let Some(separator) = &separator {
+        if action != "append" {
+            return Err(format!(
+                "--separator applies to --action append; a `{action}` adds to nothing"
+            ));
+        }
+        if separator.len() > MAX_SEPARATOR_BYTES {
+            return Err(format!(
+                "--separator holds at most {MAX_SEPARATOR_BYTES} bytes"
+            ));
+        }
+    }
+    // Slots have no generations.
+    if if_generation.is_some() && (action != "set" || slot.is_some()) {
+        return Err(
+            "--if-generation applies to --action set on a selection, and only to it".to_owned(),
+        );
+    }
+    // One request cannot carry both an expected generation and an expiry.
+    if ttl.is_some() && (action != "set" || slot.is_some() || if_generation.is_some()) {
+        return Err(
+            "--ttl applies to --action set on a selection, without --if-generation".to_owned(),
+        );
+    }
+    if sensitive && (action != "set" || slot.is_some() || if_generation.is_some()) {
+        return Err(
+            "--sensitive applies to --action set on a selection, without --if-generation"
+                .to_owned(),
+        );
+    }
+    if verify
+        && (action != "set"
+            || slot.is_some()
+            || if_generation.is_some()
+            || ttl.is_some()
+            || sensitive)
+    {
+        return Err(
+            "--verify applies to --action set on a selection, without another set option"
+                .to_owned(),
+        );
+    }
+    if json && action != "status" {
         return Err(format!(
-            "--selection applies to --action get; a `{action}` always writes the \
-             clipboard selection"
+            "--json applies to --action status; a `{action}` prints no report"
         ));
     }
     Ok(Some(Options {
         address,
         action,
         selection: selection.unwrap_or_default(),
+        with_text,
+        id,
+        slot,
+        separator,
+        if_generation,
+        ttl,
+        sensitive,
+        verify,
+        json,
     }))
 }
 
@@ -129,34 +445,78 @@ fn next_value(
         .ok_or_else(|| format!("{option} needs a value"))
 }
 
-fn read_text(mut reader: impl Read) -> Result<String, String> {
-    let mut bytes = Vec::new();
-    reader
-        .by_ref()
-        .take((MAX_SET_TEXT_BYTES + 1) as u64)
-        .read_to_end(&mut bytes)
-        .map_err(|error| format!("could not read the clipboard text: {error}"))?;
-    if bytes.len() > MAX_SET_TEXT_BYTES {
-        return Err(format!(
-            "clipboard text exceeds the {MAX_SET_TEXT_BYTES}-byte request limit"
-        ));
-    }
-    String::from_utf8(bytes).map_err(|_| "the clipboard text is not valid UTF-8".to_owned())
-}
-
-fn read_stdin() -> Result<String, String> {
-    read_text(std::io::stdin())
-}
-
+// The text itself is not read here: a `set` streams standard input, and a
+// `get` standard output, only once the daemon is known to take them.  A search
+// query and a slot's text are filled in by `run`.
 fn build_request(options: &Options) -> Result<PlainRequest, String> {
+    if let Some(name) = options.slot.clone() {
+        return match options.action.as_str() {
+            "set" => Ok(PlainRequest::SlotSet {
+                name,
+                text: String::new(),
+            }),
+            _ => Ok(PlainRequest::SlotGet { name }),
+        };
+    }
+    if let Some(expected) = options.if_generation {
+        return Ok(PlainRequest::SetVersioned {
+            selection: options.selection,
+            expected: Some(expected),
+            text: String::new(),
+        });
+    }
+    if options.sensitive {
+        return Ok(PlainRequest::SetSensitive {
+            selection: options.selection,
+            seconds: options.ttl,
+            text: String::new(),
+        });
+    }
+    if options.verify {
+        return Ok(PlainRequest::SetVerified {
+            selection: options.selection,
+            text: String::new(),
+        });
+    }
+    if let Some(seconds) = options.ttl {
+        return Ok(PlainRequest::SetExpiring {
+            selection: options.selection,
+            seconds,
+            text: String::new(),
+        });
+    }
     match options.action.as_str() {
         "ping" => Ok(PlainRequest::Ping),
-        "get" => Ok(PlainRequest::Get {
+        "get" => Ok(PlainRequest::GetStream {
+            selection: options.selection,
+        }),
+        "set" => Ok(PlainRequest::SetStream {
+            selection: options.selection,
+        }),
+        "clear" => Ok(PlainRequest::Clear {
+            selection: options.selection,
+        }),
+        "append" => Ok(PlainRequest::Append {
+            selection: options.selection,
+            separator: options.separator.clone().unwrap_or_default(),
+            text: String::new(),
+        }),
+        "watch" => Ok(PlainRequest::Subscribe {
+            selection: options.selection,
+            text: options.with_text,
+        }),
+        "generation" => Ok(PlainRequest::GetVersioned {
             selection: options.selection,
         }),
-        "set" => Ok(PlainRequest::Set {
-            text: read_stdin()?,
+        "history-list" => Ok(PlainRequest::HistoryList),
+        "history-get" => Ok(PlainRequest::HistoryGet {
+            id: options.id.unwrap_or_default(),
+        }),
+        "history-search" => Ok(PlainRequest::HistorySearch {
+            query: String::new(),
         }),
+        "slot-list" => Ok(PlainRequest::SlotList),
+        "status" => Ok(PlainRequest::Status),
         other => Err(format!("unknown action: {other}")),
     }
 }
@@ -165,25 +525,107 @@ fn run() -> Result<u8, String> {
     let Some(options) = parse_options()? else {
         return Ok(EXIT_OK);
     };
-    let request = build_request(&options)?;
+    let mut request = build_request(&options)?;
+    match &mut request {
+        PlainRequest::HistorySearch { query } => {
+            *query = read_query().map_err(|error| error.to_string())?;
+        }
+        PlainRequest::SlotSet { text, .. } => {
+            *text = read_slot_text().map_err(|error| error.to_string())?;
+        }
+        PlainRequest::Append {
+            separator, text, ..
+        } => {
+            let limit = max_append_text_bytes(separator);
+            *text =
+                read_bounded_text(limit, "an append carries").map_err(|error| error.to_string())?;
+        }
+        PlainRequest::SetVersioned { text, .. } => {
+            *text = read_bounded_text(MAX_VERSIONED_SET_TEXT_BYTES, "a conditional set carries")
+                .map_err(|error| error.to_string())?;
+        }
+        PlainRequest::SetExpiring { text, .. } => {
+            *text = read_bounded_text(MAX_EXPIRING_SET_TEXT_BYTES, "an expiring set carries")
+                .map_err(|error| error.to_string())?;
+        }
+        PlainRequest::SetSensitive { text, .. } => {
+            *text = read_bounded_text(MAX_SENSITIVE_SET_TEXT_BYTES, "a sensitive set carries")
+                .map_err(|error| error.to_string())?;
+        }
+        PlainRequest::SetVerified { text, .. } => {
+            *text = read_bounded_text(MAX_SET_TEXT_BYTES, "a verified set carries")
+                .map_err(|error| error.to_string())?;
+        }
+        _ => {}
+    }
     let token = env::var(TOKEN_VARIABLE).unwrap_or_default();
-    let client = ClientRequest::new(request, &token);
+    let identity = env::var_os(IDENTITY_VARIABLE)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    let host_key = env::var(HOST_KEY_VARIABLE).ok();
+    let client = authenticate(request, &token, identity.as_deref(), host_key.as_deref())?;
     drop(token);
 
-    match send_request(&options.address, &client) {
-        Ok(ack) => {
-            if let Some(text) = ack.text.as_deref()
-                && ack.ok
-            {
-                // Written verbatim, without a trailing newline of our own: the
-                // clipboard's own bytes are the whole answer, and a newline
-                // invented here would be pasted into the user's buffer.
-                let mut stdout = std::io::stdout().lock();
-                stdout
-                    .write_all(text.as_bytes())
-                    .and_then(|()| stdout.flush())
-                    .map_err(|error| format!("could not write the clipboard text: {error}"))?;
+    // The text of a `get` is written verbatim, without a trailing newline of
+    // our own: the clipboard's own bytes are the whole answer, and a newline
+    // invented here would be pasted into the user's buffer.
+    let result = match options.action.as_str() {
+        "set"
+            if options.slot.is_some()
+                || options.if_generation.is_some()
+                || options.ttl.is_some()
+                || options.sensitive
+                || options.verify =>
+        {
+            send_request(&options.address, &client)
+        }
+        "get" if options.slot.is_some() => {
+            send_request(&options.address, &client).and_then(write_text)
+        }
+        "set" => send_stream(&options.address, &client, std::io::stdin().lock()).map(|r| r.ack),
+        "get" => receive_stream(&options.address, &client, std::io::stdout().lock()).map(|r| r.ack),
+        "watch" => {
+            let mut output = std::io::stdout().lock();
+            let print = |change: Change| print_change(&mut output, &change);
+            match watch(&options.address, &client, print) {
+                // A subscription has no successful end: the daemon closing it
+                // is the watcher losing sight of the clipboard.
+                Ok(reply) if reply.ack.ok => {
+                    eprintln!("simpleclipboard-client: the daemon ended the subscription");
+                    return Ok(EXIT_FAILED);
+                }
+                result => result.map(|reply| reply.ack),
+            }
+        }
+        "history-list" | "history-search" => {
+            send_request(&options.address, &client).and_then(|ack| {
+                let entries = ack.entries.as_deref().unwrap_or_default();
+                print_entries(&mut std::io::stdout().lock(), entries)?;
+                Ok(ack)
+            })
+        }
+        "history-get" => send_request(&options.address, &client).and_then(write_text),
+        "generation" => send_request(&options.address, &client).and_then(|ack| {
+            if let Some(generation) = ack.generation {
+                print_generation(&mut std::io::stdout().lock(), generation)?;
+            }
+            Ok(ack)
+        }),
+        "slot-list" => send_request(&options.address, &client).and_then(|ack| {
+            let slots = ack.slots.as_deref().unwrap_or_default();
+            print_slots(&mut std::io::stdout().lock(), slots)?;
+            Ok(ack)
+        }),
+        "status" => send_request(&options.address, &client).and_then(|ack| {
+            if let Some(report) = &ack.report {
+                print_status(&mut std::io::stdout().lock(), report, options.json)?;
             }
+            Ok(ack)
+        }),
+        _ => send_request(&options.address, &client),
+    };
+    match result {
+        Ok(ack) => {
             if !ack.ok {
                 eprintln!(
                     "simpleclipboard-client: daemon refused the request: {}",
@@ -193,9 +635,12 @@ fn run() -> Result<u8, String> {
             Ok(match ack_result(&ack) {
                 1 => EXIT_OK,
                 2 => EXIT_OUTCOME_UNKNOWN,
+                3 => EXIT_CONFLICT,
+                4 => EXIT_OVERWRITTEN,
                 _ => EXIT_FAILED,
             })
         }
+        Err(error @ (ClientError::Input(_) | ClientError::Output(_))) => Err(error.to_string()),
         Err(ClientError::OutcomeUnknown) => {
             eprintln!("simpleclipboard-client: clipboard outcome is unknown");
             Ok(EXIT_OUTCOME_UNKNOWN)
@@ -207,6 +652,211 @@ fn run() -> Result<u8, String> {
     }
 }
 
+// One line per change, flushed at once so a reader on the other end of a pipe
+// sees it as it happens.  The text, when there is any, follows the line with
+// its length already given, so it needs no escaping.
+fn print_change(output: &mut impl Write, change: &Change) -> Result<(), ClientError> {
+    let hash: String = change
+        .hash
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let text = change.text.as_deref();
+    let marker = if text.is_some() { " text" } else { "" };
+    writeln!(
+        output,
+        "{} {} {hash}{marker}",
+        change.selection.name(),
+        change.size
+    )
+    .and_then(|()| match text {
+        Some(text) => writeln!(output, "{text}"),
+        None => Ok(()),
+    })
+    .and_then(|()| output.flush())
+    .map_err(ClientError::Output)
+}
+
+// The text of a reply, verbatim, as for a `get`.
+fn write_text(ack: Ack) -> Result<Ack, ClientError> {
+    let mut output = std::io::stdout().lock();
+    if let Some(text) = ack.text.as_deref() {
+        output
+            .write_all(text.as_bytes())
+            .and_then(|()| output.flush())
+            .map_err(ClientError::Output)?;
+    }
+    Ok(ack)
+}
+
+// A slot's text is sent in one request, so it is read up front, and text the
+// slot could never hold is refused before anything is sent.
+fn read_slot_text() -> Result<String, ClientError> {
+    read_bounded_text(MAX_SLOT_TEXT_BYTES, "a slot holds")
+}
+
+// So is an append's.  `what` says whose limit `limit` is.
+fn read_bounded_text(limit: usize, what: &str) -> Result<String, ClientError> {
+    let mut text = Vec::new();
+    std::io::stdin()
+        .lock()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut text)
+        .map_err(ClientError::Input)?;
+    if text.len() > limit {
+        return Err(ClientError::Input(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{what} at most {limit} bytes"),
+        )));
+    }
+    String::from_utf8(text).map_err(|error| {
+        ClientError::Input(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    })
+}
+
+// A query ends where standard input does.  `echo` adds a newline that is
+// almost never part of what the caller is looking for, so one is dropped.
+fn read_query() -> Result<String, ClientError> {
+    let mut query = String::new();
+    std::io::stdin()
+        .lock()
+        .take(MAX_SET_TEXT_BYTES as u64)
+        .read_to_string(&mut query)
+        .map_err(ClientError::Input)?;
+    let trimmed = query.strip_suffix('\n').unwrap_or(&query);
+    Ok(trimmed.strip_suffix('\r').unwrap_or(trimmed).to_owned())
+}
+
+fn print_entries(output: &mut impl Write, entries: &[HistoryEntry]) -> Result<(), ClientError> {
+    entries
+        .iter()
+        .try_for_each(|entry| {
+            writeln!(
+                output,
+                "{} {} {} {} {}",
+                entry.id,
+                entry.time,
+                entry.selection.name(),
+                entry.origin.name(),
+                entry.size
+            )
+        })
+        .and_then(|()| output.flush())
+        .map_err(ClientError::Output)
+}
+
+fn print_generation(output: &mut impl Write, generation: u64) -> Result<(), ClientError> {
+    writeln!(output, "{generation}")
+        .and_then(|()| output.flush())
+        .map_err(ClientError::Output)
+}
+
+fn print_slots(output: &mut impl Write, slots: &[SlotEntry]) -> Result<(), ClientError> {
+    slots
+        .iter()
+        .try_for_each(|slot| writeln!(output, "{} {} {}", slot.name.as_str(), slot.time, slot.size))
+        .and_then(|()| output.flush())
+        .map_err(ClientError::Output)
+}
+
+fn print_status(
+    output: &mut impl Write,
+    report: &StatusReport,
+    json: bool,
+) -> Result<(), ClientError> {
+    let last_operation = match report.last_operation {
+        Some(true) => "ok",
+        Some(false) => "failed",
+        None => "none",
+    };
+    if json {
+        let details = report
+            .details
+            .iter()
+            .map(|(detail, count)| format!("{}:{count}", json_string(detail)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let expiries = report
+            .expiries
+            .iter()
+            .map(|(selection, seconds)| format!("{}:{seconds}", json_string(selection.name())))
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(
+            output,
+            "{{\"version\":{},\"uptime\":{},\"address\":{},\"auth\":{},\"backend\":{},\
+             \"last_operation\":{},\"queue_depth\":{},\"connections\":{},\"restarts\":{},\
+             \"details\":{{{details}}},\"expiries\":{{{expiries}}}}}",
+            json_string(&report.version),
+            report.uptime,
+            json_string(&report.address),
+            json_string(report.auth.name()),
+            json_string(&report.backend),
+            report
+                .last_operation
+                .map_or("null", |ok| if ok { "true" } else { "false" }),
+            report.queue_depth,
+            report.connections,
+            report
+                .restarts
+                .map_or("null".to_owned(), |restarts| restarts.to_string()),
+        )
+    } else {
+        writeln!(
+            output,
+            "version {}\nuptime {}\naddress {}\nauth {}\nbackend {}\nlast_operation {}\n\
+             queue_depth {}\nconnections {}",
+            report.version,
+            report.uptime,
+            report.address,
+            report.auth.name(),
+            report.backend,
+            last_operation,
+            report.queue_depth,
+            report.connections,
+        )
+        // A daemon that predates the count leaves its line out.
+        .and_then(|()| {
+            report
+                .restarts
+                .map_or(Ok(()), |restarts| writeln!(output, "restarts {restarts}"))
+        })
+        .and_then(|()| {
+            report
+                .details
+                .iter()
+                .try_for_each(|(detail, count)| writeln!(output, "detail {detail} {count}"))
+        })
+        .and_then(|()| {
+            report.expiries.iter().try_for_each(|(selection, seconds)| {
+                writeln!(output, "expiry {} {seconds}", selection.name())
+            })
+        })
+    }
+    .and_then(|()| output.flush())
+    .map_err(ClientError::Output)
+}
+
+// Enough of JSON's string escaping for what a daemon reports: names and
+// addresses, which a daemon of another version could still fill with anything.
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            control if control < ' ' => escaped.push_str(&format!("\\u{:04x}", control as u32)),
+            other => escaped.push(other),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
 fn main() -> ExitCode {
     match run() {
         Ok(code) => ExitCode::from(code),
@@ -224,6 +874,7 @@ fn main() -> ExitCode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use simpleclipboard::protocol::{AuthMode, Origin};
 
     const ADDRESS: [&str; 2] = ["--address", "127.0.0.1:12343"];
 
@@ -235,27 +886,32 @@ mod tests {
         parse_arguments(arguments)
     }
 
-    // The selection only reaches the wire for Get: PlainRequest::Set is a bare
-    // length-prefixed string, and the daemon's set path hardcodes CLIPBOARD.
-    // Parsing --selection for a set therefore used to write the clipboard and
-    // exit 0, which is the one answer a caller scripting a PRIMARY write would
-    // never think to check.
+    // Parsing --selection where it never reaches the wire is how a PRIMARY
+    // write once went to CLIPBOARD and exited 0.  A ping carries no selection.
     #[test]
     fn a_selection_is_refused_where_it_cannot_reach_the_wire() {
-        for action in ["set", "ping"] {
-            for selection in ["primary", "clipboard"] {
-                let arguments = ["--action", action, "--selection", selection];
-                let Err(error) = parse(&arguments) else {
-                    panic!("--selection was accepted for --action {action}");
-                };
-                assert!(
-                    error.contains("--selection applies to --action get"),
-                    "{action}/{selection}: {error}"
-                );
-            }
+        for selection in ["primary", "clipboard"] {
+            let arguments = ["--action", "ping", "--selection", selection];
+            let Err(error) = parse(&arguments) else {
+                panic!("--selection was accepted for --action ping");
+            };
+            assert!(
+                error.contains(
+                    "--selection applies to --action set, get, clear, append, watch and generation"
+                ),
+                "{selection}: {error}"
+            );
         }
     }
 
+    #[test]
+    fn a_set_carries_its_selection_into_the_options() {
+        let options = parse(&["--action", "set", "--selection", "primary"])
+            .expect("a set with a selection must parse")
+            .expect("a set is not --help");
+        assert_eq!(options.selection, Selection::Primary);
+    }
+
     #[test]
     fn a_set_without_a_selection_is_still_accepted() {
         let options = parse(&["--action", "set"])
@@ -272,20 +928,419 @@ mod tests {
             .expect("a get is not --help");
         assert_eq!(
             build_request(&options),
-            Ok(PlainRequest::Get {
+            Ok(PlainRequest::GetStream {
                 selection: Selection::Primary
             })
         );
     }
 
+    // Text past one frame is streamed rather than refused, so a set must not
+    // read standard input before the daemon is known to take it.
     #[test]
-    fn stdin_is_bounded_before_the_request_is_materialized() {
-        let oversized = std::io::repeat(b'x').take((MAX_SET_TEXT_BYTES + 1) as u64);
-        let error = read_text(oversized).unwrap_err();
-        assert!(error.contains("request limit"), "{error}");
+    fn a_set_streams_its_text_instead_of_reading_it_up_front() {
+        let options = parse(&["--action", "set", "--selection", "primary"])
+            .expect("a set must parse")
+            .expect("a set is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SetStream {
+                selection: Selection::Primary
+            })
+        );
+    }
+
+    #[test]
+    fn a_clear_names_its_selection_and_reads_no_text() {
+        let options = parse(&["--action", "clear", "--selection", "primary"])
+            .expect("a clear with a selection must parse")
+            .expect("a clear is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::Clear {
+                selection: Selection::Primary
+            })
+        );
+    }
+
+    #[test]
+    fn a_watch_subscribes_to_its_selection_with_text_only_when_asked() {
+        for (arguments, text) in [
+            (&["--action", "watch", "--selection", "primary"][..], false),
+            (
+                &["--action", "watch", "--selection", "primary", "--with-text"][..],
+                true,
+            ),
+        ] {
+            let options = parse(arguments)
+                .expect("a watch must parse")
+                .expect("a watch is not --help");
+            assert_eq!(
+                build_request(&options),
+                Ok(PlainRequest::Subscribe {
+                    selection: Selection::Primary,
+                    text
+                })
+            );
+        }
+        let Err(error) = parse(&["--action", "get", "--with-text"]) else {
+            panic!("--with-text was accepted for --action get");
+        };
+        assert!(
+            error.contains("--with-text applies to --action watch"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn a_change_prints_as_one_line_and_then_its_text() {
+        let mut change = Change {
+            selection: Selection::Clipboard,
+            size: 5,
+            hash: [0xab; 32],
+            text: None,
+        };
+        let mut output = Vec::new();
+        print_change(&mut output, &change).unwrap();
+        change.text = Some("a\nb\nc".to_owned());
+        print_change(&mut output, &change).unwrap();
+        let hash = "ab".repeat(32);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("clipboard 5 {hash}\nclipboard 5 {hash} text\na\nb\nc\n")
+        );
+    }
+
+    #[test]
+    fn a_history_get_needs_an_id_and_nothing_else_takes_one() {
+        let options = parse(&["--action", "history-get", "--id", "42"])
+            .expect("a history-get with an id must parse")
+            .expect("a history-get is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::HistoryGet { id: 42 })
+        );
+        for arguments in [
+            &["--action", "history-get"][..],
+            &["--action", "history-list", "--id", "42"][..],
+        ] {
+            let Err(error) = parse(arguments) else {
+                panic!("{arguments:?} was accepted");
+            };
+            assert!(error.contains("--id is required by"), "{error}");
+        }
+        let Err(error) = parse(&["--action", "history-list", "--selection", "primary"]) else {
+            panic!("--selection was accepted for --action history-list");
+        };
+        assert!(error.contains("--selection applies to"), "{error}");
+    }
+
+    #[test]
+    fn history_entries_print_one_line_each() {
+        let entry = HistoryEntry {
+            id: 3,
+            time: 1_786_000_000,
+            selection: Selection::Primary,
+            origin: Origin::Observed,
+            size: 12,
+        };
+        let mut output = Vec::new();
+        print_entries(&mut output, &[entry, HistoryEntry { id: 2, ..entry }]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "3 1786000000 primary observed 12\n2 1786000000 primary observed 12\n"
+        );
+    }
+
+    #[test]
+    fn a_slot_takes_the_place_of_a_selection_on_set_and_get() {
+        let name = SlotName::parse("a").unwrap();
+        let options = parse(&["--action", "get", "--slot", "a"])
+            .expect("a get from a slot must parse")
+            .expect("a get is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SlotGet { name: name.clone() })
+        );
+        let options = parse(&["--action", "set", "--slot", "a"])
+            .expect("a set to a slot must parse")
+            .expect("a set is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SlotSet {
+                name,
+                text: String::new()
+            })
+        );
+        for arguments in [
+            &["--action", "set", "--slot", "a", "--selection", "primary"][..],
+            &["--action", "clear", "--slot", "a"][..],
+            &["--action", "slot-list", "--slot", "a"][..],
+        ] {
+            let Err(error) = parse(arguments) else {
+                panic!("{arguments:?} was accepted");
+            };
+            assert!(error.contains("--slot applies to"), "{error}");
+        }
+        let Err(error) = parse(&["--action", "get", "--slot", "a/b"]) else {
+            panic!("an invalid slot name was accepted");
+        };
+        assert!(error.contains("invalid slot name"), "{error}");
+    }
+
+    #[test]
+    fn slots_print_one_line_each() {
+        let slot = SlotEntry {
+            name: SlotName::parse("a").unwrap(),
+            time: 1_786_000_000,
+            size: 7,
+        };
+        let mut output = Vec::new();
+        print_slots(&mut output, &[slot]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "a 1786000000 7\n");
+    }
+
+    #[test]
+    fn an_append_takes_a_selection_and_a_separator_that_nothing_else_takes() {
+        let options = parse(&[
+            "--action",
+            "append",
+            "--selection",
+            "primary",
+            "--separator",
+            "\n",
+        ])
+        .expect("an append may name a selection and a separator")
+        .expect("an append is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::Append {
+                selection: Selection::Primary,
+                separator: "\n".to_owned(),
+                text: String::new(),
+            })
+        );
+        let Err(error) = parse(&["--action", "set", "--separator", ","]) else {
+            panic!("--separator was accepted for --action set");
+        };
+        assert!(
+            error.contains("--separator applies to --action append"),
+            "{error}"
+        );
+        let long = "-".repeat(MAX_SEPARATOR_BYTES + 1);
+        let Err(error) = parse(&["--action", "append", "--separator", &long]) else {
+            panic!("an overlong separator was accepted");
+        };
+        assert!(error.contains("--separator holds at most"), "{error}");
+    }
+
+    #[test]
+    fn a_conditional_set_names_the_generation_it_expects() {
+        let options = parse(&[
+            "--action",
+            "set",
+            "--selection",
+            "primary",
+            "--if-generation",
+            "42",
+        ])
+        .expect("a set may name the generation it expects")
+        .expect("a set is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SetVersioned {
+                selection: Selection::Primary,
+                expected: Some(42),
+                text: String::new(),
+            })
+        );
+        for arguments in [
+            &["--action", "get", "--if-generation", "1"][..],
+            &["--action", "set", "--slot", "a", "--if-generation", "1"],
+        ] {
+            let Err(error) = parse(arguments) else {
+                panic!("--if-generation was accepted for {arguments:?}");
+            };
+            assert!(error.contains("--if-generation applies to"), "{error}");
+        }
+        let Err(error) = parse(&["--action", "set", "--if-generation", "next"]) else {
+            panic!("a generation that is not a number was accepted");
+        };
+        assert!(error.contains("must be a generation"), "{error}");
+    }
+
+    #[test]
+    fn an_expiring_set_names_its_ttl_and_nothing_else_takes_one() {
+        let options = parse(&["--action", "set", "--selection", "primary", "--ttl", "30"])
+            .expect("a set may expire")
+            .expect("a set is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SetExpiring {
+                selection: Selection::Primary,
+                seconds: 30,
+                text: String::new(),
+            })
+        );
+        for arguments in [
+            &["--action", "append", "--ttl", "30"][..],
+            &["--action", "set", "--slot", "a", "--ttl", "30"],
+            &["--action", "set", "--if-generation", "1", "--ttl", "30"],
+        ] {
+            let Err(error) = parse(arguments) else {
+                panic!("--ttl was accepted for {arguments:?}");
+            };
+            assert!(error.contains("--ttl applies to"), "{error}");
+        }
+        for seconds in ["0", "86401", "soon"] {
+            let Err(error) = parse(&["--action", "set", "--ttl", seconds]) else {
+                panic!("--ttl {seconds} was accepted");
+            };
+            assert!(
+                error.contains("--ttl must be 1 to 86400 seconds"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_sensitive_set_may_expire_but_not_name_a_generation() {
+        let options = parse(&["--action", "set", "--sensitive", "--ttl", "30"])
+            .expect("a sensitive set may expire")
+            .expect("a set is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SetSensitive {
+                selection: Selection::Clipboard,
+                seconds: Some(30),
+                text: String::new(),
+            })
+        );
+        for arguments in [
+            &["--action", "get", "--sensitive"][..],
+            &["--action", "set", "--slot", "a", "--sensitive"],
+            &["--action", "set", "--if-generation", "1", "--sensitive"],
+        ] {
+            let Err(error) = parse(arguments) else {
+                panic!("--sensitive was accepted for {arguments:?}");
+            };
+            assert!(error.contains("--sensitive applies to"), "{error}");
+        }
+    }
+
+    #[test]
+    fn a_verified_set_stands_alone() {
+        let options = parse(&["--action", "set", "--verify", "--selection", "primary"])
+            .expect("a set may be verified")
+            .expect("a set is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::SetVerified {
+                selection: Selection::Primary,
+                text: String::new(),
+            })
+        );
+        for arguments in [
+            &["--action", "append", "--verify"][..],
+            &["--action", "set", "--slot", "a", "--verify"],
+            &["--action", "set", "--ttl", "30", "--verify"],
+            &["--action", "set", "--sensitive", "--verify"],
+        ] {
+            let Err(error) = parse(arguments) else {
+                panic!("--verify was accepted for {arguments:?}");
+            };
+            assert!(error.contains("--verify applies to"), "{error}");
+        }
+    }
+
+    #[test]
+    fn a_generation_is_read_and_printed_as_one_number() {
+        let options = parse(&["--action", "generation", "--selection", "primary"])
+            .expect("a generation may name a selection")
+            .expect("a generation is not --help");
+        assert_eq!(
+            build_request(&options),
+            Ok(PlainRequest::GetVersioned {
+                selection: Selection::Primary
+            })
+        );
+        let mut output = Vec::new();
+        print_generation(&mut output, u64::MAX).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "18446744073709551615\n");
+    }
+
+    #[test]
+    fn a_status_prints_as_lines_or_as_one_json_object() {
+        let options = parse(&["--action", "status", "--json"])
+            .expect("a status may ask for JSON")
+            .expect("a status is not --help");
+        assert!(options.json);
+        assert_eq!(build_request(&options), Ok(PlainRequest::Status));
+        let Err(error) = parse(&["--action", "ping", "--json"]) else {
+            panic!("--json was accepted for --action ping");
+        };
+        assert!(
+            error.contains("--json applies to --action status"),
+            "{error}"
+        );
 
-        let exact = std::io::repeat(b'x').take(MAX_SET_TEXT_BYTES as u64);
-        assert_eq!(read_text(exact).unwrap().len(), MAX_SET_TEXT_BYTES);
+        let report = StatusReport {
+            version: "0.1.0".to_owned(),
+            uptime: 42,
+            address: "127.0.0.1:12343".to_owned(),
+            auth: AuthMode::Tokens,
+            backend: "x11".to_owned(),
+            last_operation: Some(true),
+            queue_depth: 0,
+            connections: 1,
+            details: vec![
+                ("clipboard_set_ok".to_owned(), 3),
+                ("say \"hi\"".to_owned(), 1),
+            ],
+            expiries: vec![(Selection::Primary, 25)],
+            restarts: Some(2),
+        };
+        let mut output = Vec::new();
+        print_status(&mut output, &report, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "version 0.1.0\nuptime 42\naddress 127.0.0.1:12343\nauth token\nbackend x11\n\
+             last_operation ok\nqueue_depth 0\nconnections 1\nrestarts 2\ndetail clipboard_set_ok 3\n\
+             detail say \"hi\" 1\nexpiry primary 25\n"
+        );
+        let mut output = Vec::new();
+        let quiet = StatusReport {
+            last_operation: None,
+            details: Vec::new(),
+            expiries: Vec::new(),
+            restarts: None,
+            ..report.clone()
+        };
+        print_status(&mut output, &quiet, true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"version\":\"0.1.0\",\"uptime\":42,\"address\":\"127.0.0.1:12343\",\
+             \"auth\":\"token\",\"backend\":\"x11\",\"last_operation\":null,\"queue_depth\":0,\
+             \"connections\":1,\"restarts\":null,\"details\":{},\"expiries\":{}}\n"
+        );
+        let mut output = Vec::new();
+        print_status(&mut output, &quiet, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "version 0.1.0\nuptime 42\naddress 127.0.0.1:12343\nauth token\nbackend x11\n\
+             last_operation none\nqueue_depth 0\nconnections 1\n"
+        );
+        let mut output = Vec::new();
+        print_status(&mut output, &report, true).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains(
+                "\"last_operation\":true,\"queue_depth\":0,\"connections\":1,\
+                 \"restarts\":2,\"details\":{\"clipboard_set_ok\":3,\"say \\\"hi\\\"\":1},\
+                 \"expiries\":{\"primary\":25}}"
+            ),
+            "{output}"
+        );
+        assert_eq!(json_string("a\\b\u{1}"), "\"a\\\\b\\u0001\"");
     }
 
     #[test]
@@ -295,20 +1350,61 @@ mod tests {
             .expect("a get is not --help");
         assert_eq!(
             build_request(&options),
-            Ok(PlainRequest::Get {
+            Ok(PlainRequest::GetStream {
                 selection: Selection::Clipboard
             })
         );
     }
 
-    // The usage text advertised --selection as a general option, which is how a
-    // caller learned to pass it to a set in the first place.
     #[test]
-    fn usage_says_the_selection_is_for_get_only() {
+    fn usage_says_which_actions_take_a_selection() {
         let usage = usage();
         assert!(
-            usage.contains("--selection applies to `get` only"),
+            usage.contains(
+                "--selection applies to `set`, `get`, `clear`, `append`, `watch` and `generation`"
+            ),
             "{usage}"
         );
     }
+
+    // A generated key pair is usable as is, never replaces a file, and is
+    // refused once anyone else could read it.
+    #[test]
+    fn a_generated_key_authenticates_requests_in_place_of_a_token() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("identity");
+        let public_key = generate_key(&path).unwrap();
+        assert!(generate_key(&path).is_err());
+        let host_key = public_key.to_string();
+
+        let identity = Some(path.as_path());
+        let authenticated = authenticate(PlainRequest::Ping, "", identity, Some(&host_key));
+        assert!(authenticated.is_ok());
+        let both = authenticate(PlainRequest::Ping, "secret", identity, Some(&host_key));
+        assert!(both.err().unwrap().contains("not both"));
+        let unpinned = authenticate(PlainRequest::Ping, "", identity, None);
+        assert!(unpinned.err().unwrap().contains(HOST_KEY_VARIABLE));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            let shared = authenticate(PlainRequest::Ping, "", identity, Some(&host_key));
+            assert!(shared.err().unwrap().contains("mode 0600"));
+        }
+
+        let arguments = |list: &[&str]| list.iter().map(|a| (*a).to_owned()).collect::<Vec<_>>();
+        assert_eq!(
+            parse_generate_key(&arguments(&["--generate-key", "key"])),
+            Ok(Some(PathBuf::from("key")))
+        );
+        assert!(
+            parse_generate_key(&arguments(&["--generate-key", "key", "--action", "ping"])).is_err()
+        );
+        assert_eq!(
+            parse_generate_key(&arguments(&["--action", "ping"])),
+            Ok(None)
+        );
+    }
 }
@@ -2,25 +2,42 @@ pub mod protocol;
 
 use libc::c_char;
 use protocol::{
-    Ack, AuthKeys, FRAME_HEADER_BYTES, MAX_ACK_BYTES, PlainRequest, ServerHello, WireAck,
-    WireRequest, ack_limit, decode_ack_payload, decode_hello_payload, derive_auth_keys,
-    encode_request_frame, open_ack, parse_header, seal_request, validate_ack_length,
+    Ack, AckCode, AuthKeys, Binding, CHUNK_BYTES, Capabilities, Change, Chunk, ClientHello,
+    Compression, EVENT_HEARTBEAT, FRAME_HEADER_BYTES, KeyDerivation, KeyPair, MAX_ACK_BYTES,
+    MAX_CHUNK_PAYLOAD_BYTES, MAX_EVENT_PAYLOAD_BYTES, MAX_EXPIRY_SECONDS,
+    MAX_VERSIONED_SET_TEXT_BYTES, PlainRequest, PublicKey, SESSION_IDLE_TIMEOUT, Selection,
+    ServerHello, ServerInfo, Session, WireAck, WireChunk, WireRequest, WriteOutcome, ack_limit,
+    answer_ephemeral, answer_host_offer, decode_ack_payload, decode_chunk_payload,
+    decode_event_payload, decode_hello_payload, derive_keys, encode_chunk_frame,
+    encode_client_hello_frame, encode_describe_frame, encode_request_frame, open_plain_chunk,
+    parse_header, validate_ack_length,
 };
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::ffi::CStr;
 use std::fmt;
 use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
 use std::panic::{AssertUnwindSafe, catch_unwind};
-use std::sync::LazyLock;
 use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::{Duration, Instant};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
 const IO_TIMEOUT: Duration = Duration::from_millis(1200);
+// The daemon's own allowance for each chunk of a stream, and for the clipboard
+// after the last one.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(10);
 const FIELD_SEPARATOR: char = '\u{1}';
 const ABI_V2: &str = "SCB2";
 const RESOLVER_QUEUE: usize = 8;
-const AMBIGUOUS_CLIPBOARD_DETAIL: &str = "clipboard_outcome_unknown";
+// The daemon gives a write longer than IO_TIMEOUT, so one the client gave up
+// on may still be running when it asks what became of it.
+const OUTCOME_PATIENCE: Duration = Duration::from_millis(1500);
+const OUTCOME_POLL: Duration = Duration::from_millis(100);
+// Half the daemon's idle timeout: a session this old is closed and replaced
+// rather than raced against the daemon closing it.
+const SESSION_REUSE_WINDOW: Duration = Duration::from_secs(SESSION_IDLE_TIMEOUT.as_secs() / 2);
 
 type Resolution = std::io::Result<Vec<SocketAddr>>;
 
@@ -46,34 +63,234 @@ static RESOLVER: LazyLock<Option<SyncSender<ResolveJob>>> = LazyLock::new(|| {
         .map(|_| sender)
 });
 
-/// One request plus the keys derived from the caller's token, if any.
+/// One request plus the caller's token or identity, if any.
+///
+/// The keys depend on how the daemon derives them, which only its hello says,
+/// so they are derived after it and kept for the next request.  An identity's
+/// keys are agreed with the daemon afresh on every connection.
 pub struct ClientRequest {
     request: PlainRequest,
-    keys: Option<AuthKeys>,
+    secret: Option<Secret>,
 }
 
 impl ClientRequest {
     pub fn new(request: PlainRequest, token: &str) -> Self {
         Self {
             request,
-            keys: (!token.is_empty()).then(|| derive_auth_keys(token)),
+            secret: (!token.is_empty()).then(|| Secret::Token(Token(token.to_owned()))),
+        }
+    }
+
+    /// `request`, authenticated by `identity` instead of a token.
+    pub fn with_identity(request: PlainRequest, identity: Arc<Identity>) -> Self {
+        Self {
+            request,
+            secret: Some(Secret::Identity(identity)),
+        }
+    }
+
+    fn authenticated(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    // The same secret carrying another request, for a request sent in a form
+    // every daemon understands instead.
+    fn with_request(&self, request: PlainRequest) -> Self {
+        Self {
+            request,
+            secret: self.secret.clone(),
         }
     }
 
+    // The keys to seal with on the connection `hello` opened, and the client
+    // hello that lets the daemon agree on them too, when they are agreed on
+    // this connection rather than derived from the token alone.  A daemon
+    // that can is asked for ack codes in the same hello, or in one of its own.
+    fn keys_for(
+        &self,
+        hello: &ServerHello,
+    ) -> Result<(Option<AuthKeys>, Option<ClientHello>), ClientError> {
+        let (keys, opening) = self.agreed_keys(hello)?;
+        if !hello.info().capabilities.contains(Capabilities::ACK_CODE) {
+            return Ok((keys, opening));
+        }
+        let opening = opening.unwrap_or(ClientHello {
+            session: false,
+            ..ClientHello::session()
+        });
+        Ok((
+            keys,
+            Some(ClientHello {
+                ack_codes: true,
+                ..opening
+            }),
+        ))
+    }
+
+    fn agreed_keys(
+        &self,
+        hello: &ServerHello,
+    ) -> Result<(Option<AuthKeys>, Option<ClientHello>), ClientError> {
+        match &self.secret {
+            None => Ok((None, None)),
+            Some(Secret::Token(token)) => {
+                let server = hello.info();
+                let keys = derived_keys(token, &server.key_derivation)?;
+                if !server.capabilities.contains(Capabilities::FORWARD_SECRECY) {
+                    return Ok((Some(keys), None));
+                }
+                let (ephemeral, agreed) = answer_ephemeral(&hello.challenge)?;
+                let opening = ClientHello {
+                    session: false,
+                    ephemeral: Some(ephemeral),
+                    ..ClientHello::session()
+                };
+                Ok((Some(agreed.keys(&keys)?), Some(opening)))
+            }
+            Some(Secret::Identity(identity)) => {
+                let host = hello
+                    .host
+                    .as_ref()
+                    .ok_or(protocol::ProtocolError::HostKey)?;
+                let (keys, offer) =
+                    answer_host_offer(&identity.keys, &identity.host_key, &hello.challenge, host)?;
+                let opening = ClientHello {
+                    session: false,
+                    offer: Some(offer),
+                    ..ClientHello::session()
+                };
+                Ok((Some(keys), Some(opening)))
+            }
+        }
+    }
+
+    // A slot counts: once its Set is sent, a lost ack leaves it as unknown as
+    // a clipboard write.
     fn mutates_clipboard(&self) -> bool {
         matches!(
             &self.request,
-            PlainRequest::Set { .. } | PlainRequest::Legacy { .. }
+            PlainRequest::Set { .. }
+                | PlainRequest::Legacy { .. }
+                | PlainRequest::SetStream { .. }
+                | PlainRequest::Clear { .. }
+                | PlainRequest::SlotSet { .. }
+                | PlainRequest::Append { .. }
+                | PlainRequest::SetVersioned { .. }
+                | PlainRequest::SetExpiring { .. }
+                | PlainRequest::SetSensitive { .. }
+                | PlainRequest::SetVerified { .. }
+        )
+    }
+
+    fn streams(&self) -> bool {
+        matches!(
+            &self.request,
+            PlainRequest::SetStream { .. }
+                | PlainRequest::GetStream { .. }
+                | PlainRequest::Subscribe { .. }
         )
     }
 }
 
+#[derive(Clone, PartialEq)]
+enum Secret {
+    Token(Token),
+    Identity(Arc<Identity>),
+}
+
+/// A client's key pair, and the host key it expects the daemon to prove it
+/// holds.  Pinning the host key is what keeps one compromised client from
+/// posing as the daemon to the others.
+pub struct Identity {
+    keys: KeyPair,
+    host_key: PublicKey,
+}
+
+impl Identity {
+    pub fn new(keys: KeyPair, host_key: PublicKey) -> Self {
+        Self { keys, host_key }
+    }
+}
+
+impl PartialEq for Identity {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys.public_key() == other.keys.public_key() && self.host_key == other.host_key
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct Token(String);
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        std::mem::take(&mut self.0).into_bytes().fill(0);
+    }
+}
+
+struct DerivedKeys {
+    token: Token,
+    derivation: KeyDerivation,
+    keys: AuthKeys,
+}
+
+// Argon2id costs tens of milliseconds by design, which an editor would
+// otherwise pay on every yank.  Each daemon start brings a fresh salt, so a
+// few entries cover a restart, or a second daemon, without deriving again.
+const DERIVED_KEYS: usize = 4;
+
+static DERIVED: Mutex<VecDeque<DerivedKeys>> = Mutex::new(VecDeque::new());
+
+// The lock is not held while deriving: another call waits for nothing but
+// the lookup, and at worst two calls derive the same keys once each.
+fn derived_keys(token: &Token, derivation: &KeyDerivation) -> Result<AuthKeys, ClientError> {
+    let lock = || {
+        DERIVED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    };
+    let cached = lock()
+        .iter()
+        .find(|entry| entry.token == *token && entry.derivation == *derivation)
+        .map(|entry| entry.keys.clone());
+    if let Some(keys) = cached {
+        return Ok(keys);
+    }
+    let keys = derive_keys(&token.0, derivation)?;
+    let mut derived = lock();
+    if derived.len() == DERIVED_KEYS {
+        derived.pop_front();
+    }
+    derived.push_back(DerivedKeys {
+        token: token.clone(),
+        derivation: *derivation,
+        keys: keys.clone(),
+    });
+    Ok(keys)
+}
+
+/// The daemon's answer, together with what the daemon said about itself.
+#[derive(Debug)]
+pub struct Reply {
+    pub server: ServerInfo,
+    pub ack: Ack,
+}
+
 #[derive(Debug)]
 pub enum ClientError {
     InvalidPayload,
     Io(std::io::Error),
     Protocol(protocol::ProtocolError),
     OutcomeUnknown,
+    /// The daemon's hello ruled the request out, so nothing was sent.  The
+    /// detail is the refusal the daemon itself would have given.
+    Unsupported {
+        server: ServerInfo,
+        detail: &'static str,
+    },
+    /// The text to send could not be read, or is not UTF-8.
+    Input(std::io::Error),
+    /// The text received could not be written out.
+    Output(std::io::Error),
 }
 
 impl fmt::Display for ClientError {
@@ -83,6 +300,13 @@ impl fmt::Display for ClientError {
             Self::Io(error) => write!(f, "I/O failed: {error}"),
             Self::Protocol(error) => write!(f, "protocol failed: {error}"),
             Self::OutcomeUnknown => f.write_str("clipboard request outcome is unknown"),
+            Self::Unsupported { server, detail } => write!(
+                f,
+                "daemon (protocol {}) cannot carry this request: {detail}",
+                server.version
+            ),
+            Self::Input(error) => write!(f, "could not read the clipboard text: {error}"),
+            Self::Output(error) => write!(f, "could not write the clipboard text: {error}"),
         }
     }
 }
@@ -183,37 +407,641 @@ fn connect_with_timeout(address: &str) -> Result<TcpStream, ClientError> {
 /// `simpleclipboard-client` binary, so the two transports can never drift on
 /// framing, sealing or response binding.
 pub fn send_request(address: &str, request: &ClientRequest) -> Result<Ack, ClientError> {
-    if address.is_empty() {
+    exchange(address, request).map(|reply| reply.ack)
+}
+
+/// [`send_request`], also returning the daemon's description of itself.
+///
+/// A request the daemon's hello rules out — a request kind it does not
+/// advertise, text above its limit, or a token on one side only — fails with
+/// [`ClientError::Unsupported`] before any of it is written.  Discovering the
+/// same thing afterwards would cost a write its certainty: a connection that
+/// drops after a Set frame is an unknown outcome, not a refusal.
+///
+/// Against a daemon that supports sessions, the connection is left open after
+/// the answer and the next call to the same address with the same token
+/// reuses it, skipping the connect and the hello.
+///
+/// A streamed request is not carried here: its text travels through
+/// [`send_stream`] and [`receive_stream`].  Nor is a subscription, which
+/// [`watch`] holds open.
+pub fn exchange(address: &str, request: &ClientRequest) -> Result<Reply, ClientError> {
+    if address.is_empty() || request.streams() {
         return Err(ClientError::InvalidPayload);
     }
-
-    let mut stream = connect_with_timeout(address)?;
-    let deadline = Instant::now() + IO_TIMEOUT;
-    let hello = read_hello_from_stream(&mut stream, deadline)?;
-    let (wire_request, request_nonce) = match request.keys.as_ref() {
-        Some(keys) => {
-            let (wire, nonce) = seal_request(keys, &hello.challenge, &request.request)?;
-            (wire, Some(nonce))
+    if let Some(open) = take_session(address, request) {
+        let server = open.server;
+        if let Some(detail) = server.refusal(&request.request, request.authenticated()) {
+            store_session(open);
+            return Err(ClientError::Unsupported { server, detail });
         }
-        None => (WireRequest::Plain(request.request.clone()), None),
-    };
+        match exchange_in_session(open, request, &[], true, Instant::now() + IO_TIMEOUT) {
+            Ok(reply) => return Ok(reply),
+            Err(SessionFailure::Sent(error)) => return Err(error),
+            Err(SessionFailure::Unsent(_)) => {}
+        }
+    }
+    exchange_on_new_connection(address, request, can_keep_sessions())
+}
+
+fn exchange_on_new_connection(
+    address: &str,
+    request: &ClientRequest,
+    open_session: bool,
+) -> Result<Reply, ClientError> {
+    let (mut stream, deadline, hello, hello_payload) = connect_for_hello(address)?;
+    let server = hello.info();
+    if let Some(detail) = server.refusal(&request.request, request.authenticated()) {
+        return Err(ClientError::Unsupported { server, detail });
+    }
+    let (keys, opening) = request.keys_for(&hello)?;
+    // Keys agreed on this connection need a client hello, so they open a
+    // session whether or not one is kept.
+    let keep = open_session && server.capabilities.contains(Capabilities::SESSION);
+    if keep || opening.is_some() {
+        let client_hello = encode_client_hello_frame(&ClientHello {
+            session: keep,
+            ..opening.unwrap_or_else(ClientHello::session)
+        })?;
+        let open = OpenSession {
+            address: address.to_owned(),
+            secret: request.secret.clone(),
+            keys,
+            server,
+            session: Session::new(&hello_payload, &client_hello[FRAME_HEADER_BYTES..]),
+            stream,
+            last_used: Instant::now(),
+        };
+        return exchange_in_session(open, request, &client_hello, keep, deadline)
+            .map_err(|(SessionFailure::Unsent(error) | SessionFailure::Sent(error))| error);
+    }
+
+    let binding = Binding::Connection(&hello.challenge);
+    let (wire_request, request_nonce) = seal_for(request, keys.as_ref(), binding, &server)?;
     let frame = encode_request_frame(&wire_request)?;
     write_all_until(&mut stream, &frame, deadline)?;
-    after_frame_sent(request, || {
+    let result = after_frame_sent(request, || {
         stream.set_write_timeout(Some(deadline_remaining(deadline)?))?;
         stream.flush()?;
         stream.shutdown(Shutdown::Write)?;
 
         let limit = ack_limit(&request.request);
         let response = read_ack_from_stream(&mut stream, deadline, limit)?;
-        match (request.keys.as_ref(), request_nonce, response) {
-            (None, None, WireAck::Plain(ack)) => Ok(ack),
-            (Some(keys), Some(nonce), response) => {
-                Ok(open_ack(keys, &hello.challenge, &nonce, &response, limit)?)
+        let ack = open_response(keys.as_ref(), binding, request_nonce, response, limit)?;
+        Ok(Reply { server, ack })
+    });
+    resolved(address, request, &server, request_nonce, result)
+}
+
+/// Writes the text `reader` yields to the clipboard as a streamed Set, so that
+/// it need fit neither one request frame nor this process's memory.
+///
+/// `request` must be a `SetStream`.  Text that fits one chunk is sent as an
+/// ordinary Set instead, which every daemon understands; anything longer needs
+/// a daemon that advertises [`Capabilities::STREAM`].  The daemon acts only on
+/// a complete stream, so every failure before the last chunk is sent — text
+/// past the daemon's advertised cap, a reader that fails or yields something
+/// other than UTF-8 — leaves the clipboard untouched.
+pub fn send_stream(
+    address: &str,
+    request: &ClientRequest,
+    reader: impl Read,
+) -> Result<Reply, ClientError> {
+    let PlainRequest::SetStream { selection } = request.request else {
+        return Err(ClientError::InvalidPayload);
+    };
+    if address.is_empty() {
+        return Err(ClientError::InvalidPayload);
+    }
+    // Read before connecting: the daemon's deadline for the request frame
+    // should not be spent waiting on the caller's pipe.
+    let mut chunks = TextChunks::new(reader);
+    let mut chunk = chunks.next_chunk()?;
+    if chunk.last {
+        let set = request.with_request(PlainRequest::Set {
+            selection,
+            text: chunk.text,
+        });
+        return exchange(address, &set);
+    }
+
+    let (mut stream, deadline, hello, hello_payload) = connect_for_hello(address)?;
+    let server = hello.info();
+    if let Some(detail) = server.refusal(&request.request, request.authenticated()) {
+        return Err(ClientError::Unsupported { server, detail });
+    }
+    let (keys, opening) = request.keys_for(&hello)?;
+    let (mut frames, session) = single_opening(&hello_payload, opening)?;
+    let binding = session
+        .as_ref()
+        .map_or(Binding::Connection(&hello.challenge), Binding::Session);
+    let (wire_request, request_nonce) = seal_for(request, keys.as_ref(), binding, &server)?;
+    frames.extend(encode_request_frame(&wire_request)?);
+    write_all_until(&mut stream, &frames, deadline)?;
+
+    let mut sent = 0_u64;
+    loop {
+        sent += chunk.text.len() as u64;
+        if sent > server.max_stream_bytes {
+            return Err(ClientError::Unsupported {
+                server,
+                detail: "text_too_large",
+            });
+        }
+        let last = chunk.last;
+        let wire = match (keys.as_ref(), request_nonce.as_ref()) {
+            (Some(keys), Some(nonce)) => binding.seal_request_chunk(keys, nonce, &chunk)?,
+            _ => WireChunk::Plain(chunk),
+        };
+        let frame = encode_chunk_frame(&wire)?;
+        write_all_until(&mut stream, &frame, Instant::now() + CHUNK_TIMEOUT)?;
+        if last {
+            break;
+        }
+        chunk = chunks.next_chunk()?;
+    }
+    let result = after_frame_sent(request, || {
+        stream.shutdown(Shutdown::Write)?;
+        let deadline = Instant::now() + CHUNK_TIMEOUT;
+        let response = read_ack_from_stream(&mut stream, deadline, MAX_ACK_BYTES)?;
+        let ack = open_response(
+            keys.as_ref(),
+            binding,
+            request_nonce,
+            response,
+            MAX_ACK_BYTES,
+        )?;
+        Ok(Reply { server, ack })
+    });
+    resolved(address, request, &server, request_nonce, result)
+}
+
+/// Writes the clipboard to `output` as the daemon streams it, verbatim and a
+/// chunk at a time.
+///
+/// `request` must be a `GetStream`.  Against a daemon that does not advertise
+/// [`Capabilities::STREAM`] this is an ordinary Get, bounded by a single
+/// frame.  Text is written as each chunk opens, so a stream that fails partway
+/// leaves part of the text in `output`: only `Ok` with a successful ack means
+/// it is whole.  The ack itself never carries the text.
+pub fn receive_stream(
+    address: &str,
+    request: &ClientRequest,
+    mut output: impl Write,
+) -> Result<Reply, ClientError> {
+    let PlainRequest::GetStream { selection } = request.request else {
+        return Err(ClientError::InvalidPayload);
+    };
+    if address.is_empty() {
+        return Err(ClientError::InvalidPayload);
+    }
+    let (mut stream, deadline, hello, hello_payload) = connect_for_hello(address)?;
+    let server = hello.info();
+    if !server.capabilities.contains(Capabilities::STREAM) {
+        drop(stream);
+        let get = request.with_request(PlainRequest::Get { selection });
+        let mut reply = exchange(address, &get)?;
+        if let Some(text) = reply.ack.text.take() {
+            output
+                .write_all(text.as_bytes())
+                .and_then(|()| output.flush())
+                .map_err(ClientError::Output)?;
+        }
+        return Ok(reply);
+    }
+    if let Some(detail) = server.refusal(&request.request, request.authenticated()) {
+        return Err(ClientError::Unsupported { server, detail });
+    }
+    let (keys, opening) = request.keys_for(&hello)?;
+    let (mut frames, session) = single_opening(&hello_payload, opening)?;
+    let binding = session
+        .as_ref()
+        .map_or(Binding::Connection(&hello.challenge), Binding::Session);
+    let (wire_request, request_nonce) = seal_for(request, keys.as_ref(), binding, &server)?;
+    frames.extend(encode_request_frame(&wire_request)?);
+    write_all_until(&mut stream, &frames, deadline)?;
+    stream.shutdown(Shutdown::Write)?;
+    let response = read_ack_from_stream(&mut stream, deadline, MAX_ACK_BYTES)?;
+    let ack = open_response(
+        keys.as_ref(),
+        binding,
+        request_nonce,
+        response,
+        MAX_ACK_BYTES,
+    )?;
+    if !ack.ok {
+        return Ok(Reply { server, ack });
+    }
+    for index in 0..=u32::MAX {
+        let deadline = Instant::now() + CHUNK_TIMEOUT;
+        let payload = read_payload_from_stream(&mut stream, deadline, MAX_CHUNK_PAYLOAD_BYTES)?;
+        let chunk = decode_chunk_payload(&payload)?;
+        let chunk = match (keys.as_ref(), request_nonce.as_ref()) {
+            (Some(keys), Some(nonce)) => binding.open_reply_chunk(keys, nonce, index, &chunk)?,
+            _ => open_plain_chunk(index, chunk)?,
+        };
+        output
+            .write_all(chunk.text.as_bytes())
+            .map_err(ClientError::Output)?;
+        if chunk.last {
+            output.flush().map_err(ClientError::Output)?;
+            return Ok(Reply { server, ack });
+        }
+    }
+    Err(protocol::ProtocolError::ChunkOrder(u32::MAX).into())
+}
+
+/// Subscribes to one selection and hands each change to `on_change` as the
+/// daemon reports it, until the daemon ends the subscription.
+///
+/// `request` must be a `Subscribe` carrying a token: a daemon serves
+/// subscriptions only to authenticated clients.  The first change is the
+/// selection as it stood when the subscription began.  Heartbeats are consumed
+/// here, and a daemon silent for twice [`EVENT_HEARTBEAT`] is taken to be gone.
+/// `Ok` is either a refusal, which the ack explains, or a subscription the
+/// daemon closed; an error from `on_change` ends the subscription and is
+/// returned as it is.
+pub fn watch(
+    address: &str,
+    request: &ClientRequest,
+    mut on_change: impl FnMut(Change) -> Result<(), ClientError>,
+) -> Result<Reply, ClientError> {
+    if address.is_empty() || !matches!(request.request, PlainRequest::Subscribe { .. }) {
+        return Err(ClientError::InvalidPayload);
+    }
+    let (mut stream, deadline, hello, hello_payload) = connect_for_hello(address)?;
+    let server = hello.info();
+    if let Some(detail) = server.refusal(&request.request, request.authenticated()) {
+        return Err(ClientError::Unsupported { server, detail });
+    }
+    let (keys, opening) = request.keys_for(&hello)?;
+    let (mut frames, session) = single_opening(&hello_payload, opening)?;
+    let binding = session
+        .as_ref()
+        .map_or(Binding::Connection(&hello.challenge), Binding::Session);
+    let (wire_request, request_nonce) = seal_for(request, keys.as_ref(), binding, &server)?;
+    frames.extend(encode_request_frame(&wire_request)?);
+    // The write half stays open: closing it is how a subscriber says it is
+    // done.
+    write_all_until(&mut stream, &frames, deadline)?;
+    let response = read_ack_from_stream(&mut stream, deadline, MAX_ACK_BYTES)?;
+    let ack = open_response(
+        keys.as_ref(),
+        binding,
+        request_nonce,
+        response,
+        MAX_ACK_BYTES,
+    )?;
+    if !ack.ok {
+        return Ok(Reply { server, ack });
+    }
+    let (Some(keys), Some(nonce)) = (keys.as_ref(), request_nonce) else {
+        return Err(protocol::ProtocolError::UnexpectedProtection.into());
+    };
+    for index in 0..=u32::MAX {
+        let deadline = Instant::now() + EVENT_HEARTBEAT * 2;
+        let Some(payload) = read_event_payload(&mut stream, deadline)? else {
+            return Ok(Reply { server, ack });
+        };
+        let event = binding.open_event(keys, &nonce, index, &decode_event_payload(&payload)?)?;
+        if let Some(change) = event.change {
+            on_change(change)?;
+        }
+    }
+    Err(protocol::ProtocolError::EventOrder(u32::MAX).into())
+}
+
+// Compression is offered to every daemon that advertises it: a body it would
+// not shrink is sent as it is anyway, so the offer costs two bytes at most.
+fn seal_for(
+    request: &ClientRequest,
+    keys: Option<&AuthKeys>,
+    binding: Binding<'_>,
+    server: &ServerInfo,
+) -> Result<(WireRequest, Option<protocol::Nonce>), ClientError> {
+    let carried = carried(&request.request, server);
+    match keys {
+        Some(keys) => {
+            let compression = if server.capabilities.contains(Capabilities::COMPRESSION) {
+                Compression::Deflate
+            } else {
+                Compression::Off
+            };
+            let (wire, nonce) = binding.seal_request(keys, &carried, compression)?;
+            // A daemon holding several tokens finds this one by its id.  Keys
+            // agreed by an exchange belong to the connection, which already
+            // says whose they are.
+            let token = matches!(request.secret, Some(Secret::Token(_)));
+            let wire = if token && server.capabilities.contains(Capabilities::KEY_ID) {
+                wire.identified(keys.id())
+            } else {
+                wire
+            };
+            Ok((wire, Some(nonce)))
+        }
+        None => Ok((WireRequest::Plain(carried.into_owned()), None)),
+    }
+}
+
+// A Set or Get goes to a daemon that counts generations in the form whose ack
+// carries one.  A Set too long for that form keeps its own, and its ack says
+// nothing about generations.
+fn carried<'a>(request: &'a PlainRequest, server: &ServerInfo) -> Cow<'a, PlainRequest> {
+    if !server.capabilities.contains(Capabilities::GENERATION) {
+        return Cow::Borrowed(request);
+    }
+    match request {
+        PlainRequest::Set { selection, text } if text.len() <= MAX_VERSIONED_SET_TEXT_BYTES => {
+            Cow::Owned(PlainRequest::SetVersioned {
+                selection: *selection,
+                expected: None,
+                text: text.clone(),
+            })
+        }
+        PlainRequest::Get { selection } => Cow::Owned(PlainRequest::GetVersioned {
+            selection: *selection,
+        }),
+        _ => Cow::Borrowed(request),
+    }
+}
+
+// A request sealed with keys agreed on its connection carries the client's
+// half of the exchange in a client hello written ahead of it, which binds the
+// request to the session that hello opens rather than to the bare challenge.
+// The hello asks for no session to be kept, so the connection still carries
+// only this request.  Without one there is nothing to write ahead and no
+// session.
+fn single_opening(
+    hello_payload: &[u8],
+    opening: Option<ClientHello>,
+) -> Result<(Vec<u8>, Option<Session>), ClientError> {
+    let Some(opening) = opening else {
+        return Ok((Vec::new(), None));
+    };
+    let frame = encode_client_hello_frame(&opening)?;
+    let session = Session::new(hello_payload, &frame[FRAME_HEADER_BYTES..]);
+    Ok((frame, Some(session)))
+}
+
+// Reads text a chunk at a time, never splitting a character between chunks,
+// and one byte ahead, so that the last chunk is known to be last when it is
+// sent rather than one empty chunk later.
+struct TextChunks<R> {
+    reader: R,
+    size: usize,
+    pending: Vec<u8>,
+    index: u32,
+    finished: bool,
+}
+
+impl<R: Read> TextChunks<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            size: CHUNK_BYTES,
+            pending: Vec::new(),
+            index: 0,
+            finished: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<Chunk, ClientError> {
+        if !self.finished {
+            let wanted = (self.size + 1).saturating_sub(self.pending.len());
+            let read = (&mut self.reader)
+                .take(wanted as u64)
+                .read_to_end(&mut self.pending)
+                .map_err(ClientError::Input)?;
+            self.finished = read < wanted;
+        }
+        let last = self.pending.len() <= self.size;
+        let end = if last {
+            self.pending.len()
+        } else {
+            match std::str::from_utf8(&self.pending[..self.size]) {
+                Ok(_) => self.size,
+                // A character cut off by the chunk boundary goes in the next
+                // chunk; anything else is not text.
+                Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                Err(_) => return Err(not_text()),
             }
-            _ => Err(protocol::ProtocolError::UnexpectedProtection.into()),
+        };
+        let rest = self.pending.split_off(end);
+        let text = String::from_utf8(std::mem::replace(&mut self.pending, rest))
+            .map_err(|_| not_text())?;
+        let index = self.index;
+        self.index = index.checked_add(1).ok_or(ClientError::InvalidPayload)?;
+        Ok(Chunk { index, last, text })
+    }
+}
+
+fn not_text() -> ClientError {
+    ClientError::Input(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "the clipboard text is not valid UTF-8",
+    ))
+}
+
+fn open_response(
+    keys: Option<&AuthKeys>,
+    binding: Binding<'_>,
+    request_nonce: Option<protocol::Nonce>,
+    response: WireAck,
+    limit: usize,
+) -> Result<Ack, ClientError> {
+    match (keys, request_nonce, response) {
+        (None, None, WireAck::Plain(ack)) => Ok(ack),
+        (Some(keys), Some(nonce), response) => {
+            Ok(binding.open_ack(keys, &nonce, &response, limit)?)
         }
-    })
+        _ => Err(protocol::ProtocolError::UnexpectedProtection.into()),
+    }
+}
+
+/// A session left open after a successful exchange.
+struct OpenSession {
+    address: String,
+    secret: Option<Secret>,
+    keys: Option<AuthKeys>,
+    server: ServerInfo,
+    session: Session,
+    stream: TcpStream,
+    last_used: Instant,
+}
+
+// One is enough: an editor talks to one daemon.  A call for another address or
+// token closes it and opens its own.
+static OPEN_SESSION: Mutex<Option<OpenSession>> = Mutex::new(None);
+
+fn store_session(open: OpenSession) {
+    *OPEN_SESSION
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(open);
+}
+
+// A session is reused only by the token or identity that opened it.
+fn take_session(address: &str, request: &ClientRequest) -> Option<OpenSession> {
+    let open = OPEN_SESSION
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take()?;
+    let reusable = open.address == address
+        && open.secret == request.secret
+        && open.last_used.elapsed() < SESSION_REUSE_WINDOW
+        && still_open(&open.stream);
+    reusable.then_some(open)
+}
+
+// A daemon that ended the session — idle, shutting down, or out of session
+// slots — has left its close waiting on the socket.  Sending into that would
+// turn a write the daemon never saw into an unknown outcome, so it is checked
+// for before anything is written.  The daemon never speaks first, so waiting
+// data is as disqualifying as a close.
+fn still_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let idle = matches!(
+        stream.peek(&mut [0_u8; 1]),
+        Err(error) if error.kind() == std::io::ErrorKind::WouldBlock
+    );
+    idle && stream.set_nonblocking(false).is_ok()
+}
+
+enum SessionFailure {
+    /// None of the request reached the daemon, so it may still be sent on a
+    /// new connection.
+    Unsent(ClientError),
+    Sent(ClientError),
+}
+
+// `opening` is the client hello of a session that is only now being opened,
+// written together with its first request.  A session that did not ask to be
+// kept ends with this request, and is not stored.
+fn exchange_in_session(
+    mut open: OpenSession,
+    request: &ClientRequest,
+    opening: &[u8],
+    keep: bool,
+    deadline: Instant,
+) -> Result<Reply, SessionFailure> {
+    let server = open.server;
+    let binding = Binding::Session(&open.session);
+    let (wire_request, request_nonce) =
+        seal_for(request, open.keys.as_ref(), binding, &server).map_err(SessionFailure::Unsent)?;
+    let mut frames = opening.to_vec();
+    frames.extend(
+        encode_request_frame(&wire_request)
+            .map_err(|error| SessionFailure::Unsent(error.into()))?,
+    );
+    // A write that fails has not delivered the whole frame, and the daemon
+    // acts on nothing less.
+    write_all_until(&mut open.stream, &frames, deadline)
+        .map_err(|error| SessionFailure::Unsent(error.into()))?;
+    let result = after_frame_sent(request, || {
+        let limit = ack_limit(&request.request);
+        let response = read_ack_from_stream(&mut open.stream, deadline, limit)?;
+        open_response(open.keys.as_ref(), binding, request_nonce, response, limit)
+    });
+    let address = open.address.clone();
+    // Stored before any question about the write, which may then reuse it.
+    if keep && result.is_ok() {
+        open.session.advance();
+        open.last_used = Instant::now();
+        store_session(open);
+    }
+    let result = result.map(|ack| Reply { server, ack });
+    resolved(&address, request, &server, request_nonce, result).map_err(SessionFailure::Sent)
+}
+
+// A write whose ack was lost, or that the daemon stopped waiting for, is asked
+// about under the same secret while the daemon still remembers it.  One still
+// running is asked about again until OUTCOME_PATIENCE runs out; anything short
+// of a definite answer leaves the outcome unknown.
+fn resolved(
+    address: &str,
+    request: &ClientRequest,
+    server: &ServerInfo,
+    nonce: Option<protocol::Nonce>,
+    result: Result<Reply, ClientError>,
+) -> Result<Reply, ClientError> {
+    let ambiguous = match &result {
+        Ok(reply) => reply.ack.code() == AckCode::OutcomeUnknown,
+        Err(error) => matches!(error, ClientError::OutcomeUnknown),
+    };
+    let Some(nonce) = nonce.filter(|_| {
+        ambiguous
+            && request.mutates_clipboard()
+            && server.capabilities.contains(Capabilities::OUTCOME)
+    }) else {
+        return result;
+    };
+    let query = request.with_request(PlainRequest::OutcomeQuery { nonce });
+    let patience = Instant::now() + OUTCOME_PATIENCE;
+    loop {
+        let answer = exchange(address, &query).ok();
+        let ack = match answer.and_then(|reply| reply.ack.outcome) {
+            Some(WriteOutcome::Applied) => {
+                Ack::status(true, Some("clipboard_outcome_applied".to_owned()))
+            }
+            Some(WriteOutcome::Failed(code)) => Ack {
+                code: Some(code),
+                ..Ack::status(false, Some("clipboard_outcome_failed".to_owned()))
+            },
+            Some(WriteOutcome::Running) if Instant::now() + OUTCOME_POLL < patience => {
+                std::thread::sleep(OUTCOME_POLL);
+                continue;
+            }
+            _ => return result,
+        };
+        return Ok(Reply {
+            server: *server,
+            ack,
+        });
+    }
+}
+
+/// Whether a session can outlive the call that opened it.
+///
+/// Vim's `libcall()` unloads the library after every call, which would take a
+/// cached session with it: the socket would leak and the next call would open
+/// another.  The library therefore asks the loader to keep it resident the
+/// first time it would open a session; where that cannot be done, every
+/// request gets its own connection, exactly as before sessions existed.
+fn can_keep_sessions() -> bool {
+    static RESIDENT: LazyLock<bool> = LazyLock::new(stay_resident);
+    *RESIDENT
+}
+
+#[cfg(unix)]
+fn stay_resident() -> bool {
+    let mut info = std::mem::MaybeUninit::<libc::Dl_info>::zeroed();
+    // SAFETY: dladdr only writes into `info`, and the address is code in this
+    // very object.
+    let found = unsafe { libc::dladdr(stay_resident as *const libc::c_void, info.as_mut_ptr()) };
+    if found == 0 {
+        return false;
+    }
+    // SAFETY: dladdr succeeded, so it initialised the structure.
+    let info = unsafe { info.assume_init() };
+    if info.dli_fname.is_null() {
+        return false;
+    }
+    // SAFETY: dli_fname is the loader's own NUL-terminated path for this
+    // object.  RTLD_NOLOAD never loads anything; it only takes a reference to
+    // what is already mapped, and RTLD_NODELETE keeps it mapped after that.
+    let handle = unsafe {
+        libc::dlopen(
+            info.dli_fname,
+            libc::RTLD_LAZY | libc::RTLD_NOLOAD | libc::RTLD_NODELETE,
+        )
+    };
+    !handle.is_null()
+}
+
+#[cfg(not(unix))]
+fn stay_resident() -> bool {
+    false
 }
 
 fn after_frame_sent<T>(
@@ -237,33 +1065,100 @@ fn read_ack(reader: &mut impl Read) -> Result<WireAck, ClientError> {
     Ok(decode_ack_payload(&payload, MAX_ACK_BYTES)?)
 }
 
-fn read_ack_from_stream(
+// Bounded by what the daemon may legitimately send at this point, so a ping
+// cannot make this allocate a Get-sized buffer.
+fn read_payload_from_stream(
     stream: &mut TcpStream,
     deadline: Instant,
     limit: usize,
-) -> Result<WireAck, ClientError> {
+) -> Result<Vec<u8>, ClientError> {
     let mut header = [0_u8; FRAME_HEADER_BYTES];
     read_exact_until(stream, &mut header, deadline)?;
     let payload_length = parse_header(&header)?;
-    // Bounded by what the request that was actually sent may be answered with,
-    // so a ping cannot make this allocate a Get-sized buffer.
     validate_ack_length(payload_length, limit)?;
     let mut payload = vec![0_u8; payload_length];
     read_exact_until(stream, &mut payload, deadline)?;
-    Ok(decode_ack_payload(&payload, limit)?)
+    Ok(payload)
 }
 
-fn read_hello_from_stream(
+// The daemon may close between events, and that is how a subscription ends;
+// a close partway through a frame is still a truncated frame.
+fn read_event_payload(
     stream: &mut TcpStream,
     deadline: Instant,
-) -> Result<ServerHello, ClientError> {
+) -> Result<Option<Vec<u8>>, ClientError> {
     let mut header = [0_u8; FRAME_HEADER_BYTES];
-    read_exact_until(stream, &mut header, deadline)?;
+    stream.set_read_timeout(Some(deadline_remaining(deadline)?))?;
+    let read = loop {
+        match stream.read(&mut header[..1]) {
+            Ok(read) => break read,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    };
+    if read == 0 {
+        return Ok(None);
+    }
+    read_exact_until(stream, &mut header[1..], deadline)?;
     let payload_length = parse_header(&header)?;
-    validate_ack_length(payload_length, MAX_ACK_BYTES)?;
+    validate_ack_length(payload_length, MAX_EVENT_PAYLOAD_BYTES)?;
     let mut payload = vec![0_u8; payload_length];
     read_exact_until(stream, &mut payload, deadline)?;
-    Ok(decode_hello_payload(&payload)?)
+    Ok(Some(payload))
+}
+
+fn read_ack_from_stream(
+    stream: &mut TcpStream,
+    deadline: Instant,
+    limit: usize,
+) -> Result<WireAck, ClientError> {
+    let payload = read_payload_from_stream(stream, deadline, limit)?;
+    Ok(decode_ack_payload(&payload, limit)?)
+}
+
+// A connection, its request deadline and the daemon's hello.  Every daemon
+// opens with the bare challenge, so each is asked to describe itself; a
+// revision-1 daemon closes the connection instead of answering, and all there
+// is to know about it is the bare hello it sends on a fresh one.  The raw
+// payload comes back too: a session's transcript covers the hello exactly as
+// it arrived.
+fn connect_for_hello(
+    address: &str,
+) -> Result<(TcpStream, Instant, ServerHello, Vec<u8>), ClientError> {
+    let mut stream = connect_with_timeout(address)?;
+    let deadline = Instant::now() + IO_TIMEOUT;
+    let (hello, _) = read_hello_from_stream(&mut stream, deadline)?;
+    write_all_until(&mut stream, &encode_describe_frame()?, deadline)?;
+    match read_hello_from_stream(&mut stream, deadline) {
+        Ok((described, payload)) => {
+            if described.challenge != hello.challenge || described.info.is_none() {
+                return Err(protocol::ProtocolError::ResponseBinding.into());
+            }
+            Ok((stream, deadline, described, payload))
+        }
+        Err(ClientError::Io(error))
+            if matches!(
+                error.kind(),
+                std::io::ErrorKind::UnexpectedEof
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+            ) =>
+        {
+            let mut stream = connect_with_timeout(address)?;
+            let deadline = Instant::now() + IO_TIMEOUT;
+            let (hello, payload) = read_hello_from_stream(&mut stream, deadline)?;
+            Ok((stream, deadline, hello, payload))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn read_hello_from_stream(
+    stream: &mut TcpStream,
+    deadline: Instant,
+) -> Result<(ServerHello, Vec<u8>), ClientError> {
+    let payload = read_payload_from_stream(stream, deadline, MAX_ACK_BYTES)?;
+    Ok((decode_hello_payload(&payload)?, payload))
 }
 
 fn deadline_remaining(deadline: Instant) -> std::io::Result<Duration> {
@@ -322,6 +1217,87 @@ fn read_exact_until(
     Ok(())
 }
 
+/// The options an SCB2 action may carry after its verb.
+///
+/// The payload has no spare field, so options ride in the action itself as
+/// comma-separated `key=value` pairs: `set,selection=primary`.  A bare verb is
+/// exactly the action older plugins send.  An `append` may also name the
+/// `separator` it puts before its text, percent-encoded wherever it holds `%`
+/// or `,`: `append,separator=%2C%20` is ", ".  A `set` may name the
+/// `generation` the selection must still be at for it to be written:
+/// `set,generation=42`, or the seconds after which the daemon is to clear it
+/// again if it still holds this text: `set,ttl=30`, but not both.  A `set`
+/// with `sensitive=1` asks the daemon to tell clipboard managers not to record
+/// it and to keep it out of its own history; it may carry a `ttl` as well, but
+/// not a `generation`.  A `set` with `verify=1` has the daemon read it back
+/// and fail with `clipboard_overwritten` if another owner replaced it; it
+/// carries no other option but `selection`.  An option
+/// the verb does not take, a repeated one, or one this library does not know
+/// is a malformed payload, never something to ignore: silently dropping
+/// `selection=primary` would write CLIPBOARD instead, and dropping `ttl=30`
+/// would leave a password there for good.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ActionOptions {
+    selection: Option<Selection>,
+    separator: Option<String>,
+    generation: Option<u64>,
+    ttl: Option<u32>,
+    sensitive: bool,
+    verify: bool,
+}
+
+impl ActionOptions {
+    fn parse(action: &str) -> Result<(&str, Self), ClientError> {
+        let mut parts = action.split(',');
+        let verb = parts.next().unwrap_or_default();
+        let mut options = Self::default();
+        for part in parts {
+            let (key, value) = part.split_once('=').ok_or(ClientError::InvalidPayload)?;
+            match key {
+                "selection" if options.selection.is_none() => {
+                    options.selection =
+                        Some(Selection::parse(value).ok_or(ClientError::InvalidPayload)?);
+                }
+                "separator" if options.separator.is_none() => {
+                    options.separator = Some(percent_decode(value)?);
+                }
+                "generation" if options.generation.is_none() => {
+                    options.generation =
+                        Some(value.parse().map_err(|_| ClientError::InvalidPayload)?);
+                }
+                "ttl" if options.ttl.is_none() => {
+                    let seconds = value.parse().map_err(|_| ClientError::InvalidPayload)?;
+                    if !(1..=MAX_EXPIRY_SECONDS).contains(&seconds) {
+                        return Err(ClientError::InvalidPayload);
+                    }
+                    options.ttl = Some(seconds);
+                }
+                "sensitive" if value == "1" && !options.sensitive => options.sensitive = true,
+                "verify" if value == "1" && !options.verify => options.verify = true,
+                _ => return Err(ClientError::InvalidPayload),
+            }
+        }
+        Ok((verb, options))
+    }
+}
+
+fn percent_decode(value: &str) -> Result<String, ClientError> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let digits = tail.get(..2).ok_or(ClientError::InvalidPayload)?;
+            let digits = std::str::from_utf8(digits).map_err(|_| ClientError::InvalidPayload)?;
+            bytes.push(u8::from_str_radix(digits, 16).map_err(|_| ClientError::InvalidPayload)?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| ClientError::InvalidPayload)
+}
+
 fn parse_v2_payload(payload: &str) -> Result<(&str, ClientRequest), ClientError> {
     let mut fields = payload.splitn(5, FIELD_SEPARATOR);
     if fields.next() != Some(ABI_V2) {
@@ -334,11 +1310,74 @@ fn parse_v2_payload(payload: &str) -> Result<(&str, ClientRequest), ClientError>
     if address.is_empty() || token.contains(FIELD_SEPARATOR) {
         return Err(ClientError::InvalidPayload);
     }
-    let request = match action {
-        "ping" if text.is_empty() => PlainRequest::Ping,
-        "set" => PlainRequest::Set {
-            text: text.to_owned(),
+    let (verb, options) = ActionOptions::parse(action)?;
+    let request = match verb {
+        "ping" if text.is_empty() && options == ActionOptions::default() => PlainRequest::Ping,
+        "set" if options.verify => {
+            let alone = ActionOptions {
+                selection: options.selection,
+                verify: true,
+                ..ActionOptions::default()
+            };
+            if options != alone {
+                return Err(ClientError::InvalidPayload);
+            }
+            PlainRequest::SetVerified {
+                selection: options.selection.unwrap_or_default(),
+                text: text.to_owned(),
+            }
+        }
+        "set" if options.separator.is_none() && options.sensitive => {
+            if options.generation.is_some() {
+                return Err(ClientError::InvalidPayload);
+            }
+            PlainRequest::SetSensitive {
+                selection: options.selection.unwrap_or_default(),
+                seconds: options.ttl,
+                text: text.to_owned(),
+            }
+        }
+        "set" if options.separator.is_none() => match (options.generation, options.ttl) {
+            (Some(expected), None) => PlainRequest::SetVersioned {
+                selection: options.selection.unwrap_or_default(),
+                expected: Some(expected),
+                text: text.to_owned(),
+            },
+            (None, Some(seconds)) => PlainRequest::SetExpiring {
+                selection: options.selection.unwrap_or_default(),
+                seconds,
+                text: text.to_owned(),
+            },
+            (None, None) => PlainRequest::Set {
+                selection: options.selection.unwrap_or_default(),
+                text: text.to_owned(),
+            },
+            (Some(_), Some(_)) => return Err(ClientError::InvalidPayload),
         },
+        "clear"
+            if text.is_empty()
+                && options.separator.is_none()
+                && options.generation.is_none()
+                && options.ttl.is_none()
+                && !options.sensitive
+                && !options.verify =>
+        {
+            PlainRequest::Clear {
+                selection: options.selection.unwrap_or_default(),
+            }
+        }
+        "append"
+            if options.generation.is_none()
+                && options.ttl.is_none()
+                && !options.sensitive
+                && !options.verify =>
+        {
+            PlainRequest::Append {
+                selection: options.selection.unwrap_or_default(),
+                separator: options.separator.unwrap_or_default(),
+                text: text.to_owned(),
+            }
+        }
         _ => return Err(ClientError::InvalidPayload),
     };
     Ok((address, ClientRequest::new(request, token)))
@@ -361,6 +1400,7 @@ fn parse_legacy_payload(payload: &str) -> Result<(&str, ClientRequest), ClientEr
             let request = match action {
                 "ping" if text.is_empty() => PlainRequest::Ping,
                 "set" => PlainRequest::Set {
+                    selection: Selection::Clipboard,
                     text: text.to_owned(),
                 },
                 _ => return Err(ClientError::InvalidPayload),
@@ -380,19 +1420,25 @@ fn parse_legacy_payload(payload: &str) -> Result<(&str, ClientRequest), ClientEr
     ))
 }
 
-/// 1 success, 2 outcome unknown, 0 definitive failure.
+/// 1 success, 2 outcome unknown, 3 conflict, 4 overwritten, 0 definitive
+/// failure.
 ///
-/// The same three values the FFI returns and the client binary exits with, so
-/// the Vim side reads one vocabulary whichever transport it used.
+/// The same values the FFI returns and the client binary exits with, so the
+/// Vim side reads one vocabulary whichever transport it used.  A conflict is a
+/// conditional Set the daemon refused because the selection had moved on from
+/// the generation it expected: nothing was written, and reading the selection
+/// again is the way forward.  Overwritten is a verified Set that was written
+/// but had already been replaced by another owner when the daemon read it
+/// back, so writing it again may only start a tug of war.
 pub fn ack_result(ack: &Ack) -> i32 {
-    if ack.ok {
-        1
-    } else if ack.detail.as_deref() == Some(AMBIGUOUS_CLIPBOARD_DETAIL) {
+    match ack.code() {
+        _ if ack.ok => 1,
         // The worker already started an operation that cannot be cancelled.  A
         // fallback now could race and be overwritten by that late operation.
-        2
-    } else {
-        0
+        AckCode::OutcomeUnknown => 2,
+        AckCode::GenerationConflict => 3,
+        AckCode::Overwritten => 4,
+        _ => 0,
     }
 }
 
@@ -426,9 +1472,20 @@ unsafe fn call_from_c(
 
 /// Sends an SCB2 ABI payload (`SCB2\x01address\x01action\x01token\x01text`).
 ///
-/// The text field is last, so embedded U+0001 characters are preserved. Returns
-/// 1 for success, 2 when a clipboard operation is already in progress but its
-/// result is unknown, and 0 for a definitive failure.
+/// The text field is last, so embedded U+0001 characters are preserved. The
+/// action is `ping`, `set`, `clear` or `append`; all but `ping` take
+/// `,selection=primary` to address PRIMARY instead of CLIPBOARD, an `append`
+/// takes `,separator=` too, percent-encoded wherever it holds `%` or `,`, and
+/// a `set` takes `,generation=` to write only while the selection is still at
+/// that generation, or `,ttl=` for the seconds after which the daemon clears
+/// it again if it still holds this text; `,sensitive=1` asks clipboard managers
+/// not to record it and may join a `,ttl=`, and `,verify=1` has the daemon
+/// read it back a moment later. Returns 1 for success, 2 when a clipboard
+/// operation is already in progress but its result is unknown, or a verified
+/// Set could not be read back, 3 when a `set,generation=` found the selection
+/// at another generation and wrote nothing, 4 when a `set,verify=1` was
+/// written but another owner had already replaced it, and 0 for a definitive
+/// failure.
 ///
 /// # Safety
 ///
@@ -442,7 +1499,8 @@ pub unsafe extern "C" fn rust_set_clipboard_tcp_v2(payload: *const c_char) -> i3
 
 /// Compatibility entry point for the v0.1 Vim client payload.
 ///
-/// Returns the same 0/1/2 status values as [`rust_set_clipboard_tcp_v2`].
+/// Returns the same status values as [`rust_set_clipboard_tcp_v2`], of which a
+/// payload this old never earns 3 or 4.
 ///
 /// # Safety
 ///
@@ -457,7 +1515,7 @@ pub unsafe extern "C" fn rust_set_clipboard_tcp(payload: *const c_char) -> i32 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use protocol::encode_ack_frame;
+    use protocol::{derive_auth_keys, encode_ack_frame};
     use std::io::Cursor;
     use std::ptr;
 
@@ -469,10 +1527,198 @@ mod tests {
         assert_eq!(
             request.request,
             PlainRequest::Set {
+                selection: Selection::Clipboard,
                 text: "a\u{1}b".to_owned(),
             }
         );
-        assert!(request.keys.is_some());
+        assert!(request.authenticated());
+    }
+
+    #[test]
+    fn v2_set_action_carries_its_selection() {
+        let payload = "SCB2\u{1}127.0.0.1:1\u{1}set,selection=primary\u{1}\u{1}text";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::Set {
+                selection: Selection::Primary,
+                text: "text".to_owned(),
+            }
+        );
+    }
+
+    // An option that cannot be honoured must fail the call: dropping it would
+    // quietly write CLIPBOARD when the caller asked for something else.
+    #[test]
+    fn v2_clear_action_names_its_selection_and_carries_no_text() {
+        let payload = "SCB2\u{1}127.0.0.1:1\u{1}clear,selection=primary\u{1}token\u{1}";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::Clear {
+                selection: Selection::Primary
+            }
+        );
+        assert!(request.mutates_clipboard());
+
+        let payload = "SCB2\u{1}127.0.0.1:1\u{1}clear\u{1}\u{1}";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::Clear {
+                selection: Selection::Clipboard
+            }
+        );
+        assert!(matches!(
+            parse_v2_payload("SCB2\u{1}127.0.0.1:1\u{1}clear\u{1}\u{1}text"),
+            Err(ClientError::InvalidPayload)
+        ));
+    }
+
+    #[test]
+    fn v2_append_action_carries_its_selection_and_decoded_separator() {
+        let payload =
+            "SCB2\u{1}127.0.0.1:1\u{1}append,separator=%2C%20=,selection=primary\u{1}t\u{1}line";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::Append {
+                selection: Selection::Primary,
+                separator: ", =".to_owned(),
+                text: "line".to_owned(),
+            }
+        );
+        assert!(request.mutates_clipboard());
+
+        let (_, request) = parse_v2_payload("SCB2\u{1}127.0.0.1:1\u{1}append\u{1}\u{1}x").unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::Append {
+                selection: Selection::Clipboard,
+                separator: String::new(),
+                text: "x".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn v2_action_options_that_cannot_be_honoured_are_rejected() {
+        for action in [
+            "set,selection=secondary",
+            "set,selection=primary,selection=clipboard",
+            "set,colour=blue",
+            "set,selection",
+            "ping,selection=primary",
+            "set,separator=%0A",
+            "clear,separator=%0A",
+            "append,separator=%0",
+            "append,separator=%zz",
+            "append,separator=%FF",
+            "append,separator=a,separator=b",
+            "clear,generation=1",
+            "append,generation=1",
+            "ping,generation=1",
+            "set,generation=x",
+            "set,generation=-1",
+            "set,generation=1,generation=2",
+            "set,ttl=0",
+            "set,ttl=86401",
+            "set,ttl=soon",
+            "set,ttl=1,ttl=2",
+            "set,ttl=30,generation=1",
+            "clear,ttl=30",
+            "append,ttl=30",
+            "set,sensitive=0",
+            "set,sensitive=yes",
+            "set,sensitive=1,sensitive=1",
+            "set,sensitive=1,generation=1",
+            "clear,sensitive=1",
+            "append,sensitive=1",
+            "ping,sensitive=1",
+            "set,verify=0",
+            "set,verify=1,verify=1",
+            "set,verify=1,ttl=30",
+            "set,sensitive=1,verify=1",
+            "set,verify=1,generation=1",
+            "append,verify=1",
+            "clear,verify=1",
+        ] {
+            let payload = format!("SCB2\u{1}127.0.0.1:1\u{1}{action}\u{1}\u{1}");
+            assert!(
+                matches!(parse_v2_payload(&payload), Err(ClientError::InvalidPayload)),
+                "{action}"
+            );
+        }
+    }
+
+    #[test]
+    fn v2_set_with_a_generation_is_written_only_at_that_generation() {
+        let payload = "SCB2\u{1}127.0.0.1:1\u{1}set,generation=42,selection=primary\u{1}\u{1}x";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::SetVersioned {
+                selection: Selection::Primary,
+                expected: Some(42),
+                text: "x".to_owned(),
+            }
+        );
+        assert!(request.mutates_clipboard());
+    }
+
+    #[test]
+    fn v2_set_with_a_ttl_asks_the_daemon_to_clear_it_again() {
+        let payload = "SCB2\u{1}127.0.0.1:1\u{1}set,selection=primary,ttl=30\u{1}\u{1}secret";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::SetExpiring {
+                selection: Selection::Primary,
+                seconds: 30,
+                text: "secret".to_owned(),
+            }
+        );
+        assert!(request.mutates_clipboard());
+    }
+
+    #[test]
+    fn v2_set_with_verify_is_read_back_by_the_daemon() {
+        let payload = "SCB2\u{1}127.0.0.1:1\u{1}set,verify=1,selection=primary\u{1}\u{1}x";
+        let (_, request) = parse_v2_payload(payload).unwrap();
+        assert_eq!(
+            request.request,
+            PlainRequest::SetVerified {
+                selection: Selection::Primary,
+                text: "x".to_owned(),
+            }
+        );
+        assert!(request.mutates_clipboard());
+    }
+
+    #[test]
+    fn v2_sensitive_set_may_also_expire() {
+        for (action, seconds) in [
+            ("set,sensitive=1", None),
+            ("set,ttl=30,sensitive=1,selection=primary", Some(30)),
+        ] {
+            let payload = format!("SCB2\u{1}127.0.0.1:1\u{1}{action}\u{1}\u{1}secret");
+            let (_, request) = parse_v2_payload(&payload).unwrap();
+            let selection = if seconds.is_some() {
+                Selection::Primary
+            } else {
+                Selection::Clipboard
+            };
+            assert_eq!(
+                request.request,
+                PlainRequest::SetSensitive {
+                    selection,
+                    seconds,
+                    text: "secret".to_owned(),
+                },
+                "{action}"
+            );
+            assert!(request.mutates_clipboard());
+        }
     }
 
     #[test]
@@ -482,10 +1728,11 @@ mod tests {
         assert_eq!(
             request.request,
             PlainRequest::Set {
+                selection: Selection::Clipboard,
                 text: "a\u{1}b".to_owned(),
             }
         );
-        assert!(request.keys.is_some());
+        assert!(request.authenticated());
     }
 
     #[test]
@@ -526,14 +1773,62 @@ mod tests {
 
     #[test]
     fn ambiguous_clipboard_result_suppresses_immediate_fallback() {
-        let ack = Ack::status(false, Some(AMBIGUOUS_CLIPBOARD_DETAIL.to_owned()));
+        let ack = Ack::status(false, Some("clipboard_outcome_unknown".to_owned()));
         assert_eq!(ack_result(&ack), 2);
+        // A daemon that sends codes is read by the code, whatever its detail.
+        let coded = Ack {
+            detail: Some("outcome_not_known".to_owned()),
+            code: Some(AckCode::OutcomeUnknown),
+            ..Ack::status(false, None)
+        };
+        assert_eq!(ack_result(&coded), 2);
+    }
+
+    #[test]
+    fn a_generation_conflict_is_reported_apart_from_failure() {
+        let ack = Ack::status(false, Some("generation_conflict".to_owned())).with_generation(7);
+        assert_eq!(ack_result(&ack), 3);
+    }
+
+    #[test]
+    fn an_overwritten_set_is_reported_apart_from_failure() {
+        let ack = Ack::status(false, Some("clipboard_overwritten".to_owned()));
+        assert_eq!(ack_result(&ack), 4);
+        let ack = Ack::status(false, Some("clipboard_unavailable".to_owned()));
+        assert_eq!(ack_result(&ack), 0);
+    }
+
+    // Codes are asked for in a hello of their own where nothing else needs
+    // one, and never of a daemon that would refuse the hello for it.
+    #[test]
+    fn ack_codes_are_asked_for_only_where_advertised() {
+        use protocol::ServerInfo;
+
+        let hello = |capabilities| ServerHello {
+            challenge: [1_u8; protocol::CHALLENGE_BYTES],
+            info: Some(ServerInfo {
+                capabilities,
+                ..ServerInfo::current(false)
+            }),
+            host: None,
+        };
+        let ping = ClientRequest::new(PlainRequest::Ping, "");
+        let (keys, opening) = ping.keys_for(&hello(Capabilities::ALL)).unwrap();
+        assert!(keys.is_none());
+        let opening = opening.unwrap();
+        assert!(opening.ack_codes && !opening.session);
+
+        let older =
+            Capabilities::from_bits(Capabilities::ALL.bits() & !Capabilities::ACK_CODE.bits());
+        let (_, opening) = ping.keys_for(&hello(older)).unwrap();
+        assert_eq!(opening, None);
     }
 
     #[test]
     fn transport_failure_after_full_set_frame_is_ambiguous_but_ping_is_not() {
         let set = ClientRequest::new(
             PlainRequest::Set {
+                selection: Selection::Clipboard,
                 text: "already sent".to_owned(),
             },
             "secret",
@@ -556,6 +1851,530 @@ mod tests {
         assert!(matches!(ping_result, Err(ClientError::Io(_))));
     }
 
+    // The hello arrives before the request is written, so a request the daemon
+    // cannot carry fails without a byte of it leaving this process.
+    #[test]
+    fn a_request_the_hello_rules_out_is_never_sent() {
+        use protocol::{Capabilities, ServerInfo};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let daemon = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = ServerHello {
+                challenge: [1_u8; protocol::CHALLENGE_BYTES],
+                info: Some(ServerInfo {
+                    capabilities: Capabilities::REVISION_1,
+                    ..ServerInfo::current(true)
+                }),
+                host: None,
+            };
+            describe(&mut stream, &hello);
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let request = ClientRequest::new(
+            PlainRequest::Set {
+                selection: Selection::Primary,
+                text: "never sent".to_owned(),
+            },
+            "secret",
+        );
+        let result = exchange(&address, &request);
+        assert!(matches!(
+            result,
+            Err(ClientError::Unsupported {
+                detail: "request_unsupported",
+                ..
+            })
+        ));
+        assert!(daemon.join().unwrap().is_empty());
+    }
+
+    // Every daemon opens with the bare challenge.  One that knows the describe
+    // frame answers it; a revision-1 daemon closes on it, and the request goes
+    // out again on a fresh connection as that daemon always understood it.
+    #[test]
+    fn a_bare_hello_is_described_on_request_or_taken_as_revision_1() {
+        use protocol::{
+            Capabilities, ServerInfo, decode_request_payload, encode_ack_frame, encode_hello_frame,
+            is_describe,
+        };
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let daemon = std::thread::spawn(move || {
+            let hello = ServerHello {
+                challenge: [3_u8; protocol::CHALLENGE_BYTES],
+                info: Some(ServerInfo {
+                    capabilities: Capabilities::REVISION_1,
+                    ..ServerInfo::current(false)
+                }),
+                host: None,
+            };
+            let bare = encode_hello_frame(&hello.bare()).unwrap();
+            let pong = encode_ack_frame(&WireAck::Plain(Ack::status(true, None))).unwrap();
+            let mut requests = Vec::new();
+            for describes in [true, false, false] {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(&bare).unwrap();
+                let mut payload = read_frame(&mut stream).unwrap();
+                if is_describe(&payload) {
+                    if !describes {
+                        continue;
+                    }
+                    stream
+                        .write_all(&encode_hello_frame(&hello).unwrap())
+                        .unwrap();
+                    payload = read_frame(&mut stream).unwrap();
+                }
+                requests.push(decode_request_payload(&payload).unwrap());
+                stream.write_all(&pong).unwrap();
+            }
+            requests
+        });
+
+        let ping = ClientRequest::new(PlainRequest::Ping, "");
+        let described = exchange(&address, &ping).unwrap();
+        assert_eq!(described.server.version, protocol::PROTOCOL_VERSION);
+        let bare = exchange(&address, &ping).unwrap();
+        assert_eq!(bare.server.version, 1);
+        assert_eq!(
+            daemon.join().unwrap(),
+            vec![WireRequest::Plain(PlainRequest::Ping); 2]
+        );
+    }
+
+    // The cached session is process-wide, so the tests that touch it take
+    // turns.
+    static SESSION_TESTS: Mutex<()> = Mutex::new(());
+
+    fn read_frame(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut header = [0_u8; FRAME_HEADER_BYTES];
+        stream.read_exact(&mut header).ok()?;
+        let mut payload = vec![0_u8; parse_header(&header).unwrap()];
+        stream.read_exact(&mut payload).unwrap();
+        Some(payload)
+    }
+
+    // Opens a connection as every daemon does, with the bare challenge, and
+    // answers the client's describe frame with `hello`, whose frame it returns.
+    fn describe(stream: &mut TcpStream, hello: &ServerHello) -> Vec<u8> {
+        use protocol::{encode_hello_frame, is_describe};
+
+        stream
+            .write_all(&encode_hello_frame(&hello.bare()).unwrap())
+            .unwrap();
+        assert!(is_describe(&read_frame(stream).unwrap()));
+        let frame = encode_hello_frame(hello).unwrap();
+        stream.write_all(&frame).unwrap();
+        frame
+    }
+
+    // A daemon that serves one session on one connection, answers each request
+    // as `answer` says given the nonce it was sealed under, and reports every
+    // request it opened, in order.
+    fn session_daemon(
+        key_derivation: KeyDerivation,
+        mut answer: impl FnMut(&PlainRequest, &protocol::Nonce) -> Ack + Send + 'static,
+    ) -> (String, std::thread::JoinHandle<Vec<PlainRequest>>) {
+        use protocol::{decode_client_hello_payload, decode_request_payload};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let daemon = std::thread::spawn(move || {
+            let token_keys = derive_keys("secret", &key_derivation).unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let ephemeral = protocol::EphemeralExchange::new().unwrap();
+            let hello = ServerHello {
+                challenge: ephemeral.challenge(),
+                info: Some(ServerInfo {
+                    key_derivation,
+                    ..ServerInfo::current(true)
+                }),
+                host: None,
+            };
+            let hello_frame = describe(&mut stream, &hello);
+            let client_hello = read_frame(&mut stream).unwrap();
+            let opening = decode_client_hello_payload(&client_hello).unwrap();
+            assert!(opening.session);
+            // Sealed with keys of this connection alone, never the token's.
+            let agreed = ephemeral.accept(&opening.ephemeral.unwrap()).unwrap();
+            let keys = agreed.keys(&token_keys).unwrap();
+            assert!(!keys.same_token(&token_keys));
+            let mut session = Session::new(&hello_frame[FRAME_HEADER_BYTES..], &client_hello);
+            let mut received = Vec::new();
+            while let Some(payload) = read_frame(&mut stream) {
+                let WireRequest::Authenticated {
+                    key,
+                    nonce,
+                    ciphertext,
+                } = decode_request_payload(&payload).unwrap()
+                else {
+                    panic!("expected an authenticated request");
+                };
+                assert_eq!(key, Some(keys.id()));
+                let binding = Binding::Session(&session);
+                let (request, compression) =
+                    binding.open_request(&keys, &nonce, &ciphertext).unwrap();
+                let ack = binding
+                    .seal_ack(&keys, nonce, &answer(&request, &nonce), compression)
+                    .unwrap();
+                received.push(request);
+                stream.write_all(&encode_ack_frame(&ack).unwrap()).unwrap();
+                session.advance();
+            }
+            received
+        });
+        (address, daemon)
+    }
+
+    #[test]
+    fn consecutive_requests_share_one_session() {
+        let _turn = SESSION_TESTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let salt = [9_u8; protocol::KDF_SALT_BYTES];
+        for key_derivation in [KeyDerivation::Sha256, KeyDerivation::Argon2id(salt)] {
+            let (address, daemon) = session_daemon(key_derivation, |_, _| Ack::status(true, None));
+
+            let ping = ClientRequest::new(PlainRequest::Ping, "secret");
+            let set = ClientRequest::new(
+                PlainRequest::Set {
+                    selection: Selection::Primary,
+                    text: "second".to_owned(),
+                },
+                "secret",
+            );
+            assert!(
+                exchange_on_new_connection(&address, &ping, true)
+                    .unwrap()
+                    .ack
+                    .ok
+            );
+            // The daemon accepts once, so this only succeeds on the open session.
+            assert!(exchange(&address, &set).unwrap().ack.ok);
+            drop(take_session(&address, &set));
+
+            // A daemon that counts generations is sent the form whose ack
+            // says which one the Set left.
+            let versioned = PlainRequest::SetVersioned {
+                selection: Selection::Primary,
+                expected: None,
+                text: "second".to_owned(),
+            };
+            assert_eq!(daemon.join().unwrap(), [ping.request, versioned]);
+        }
+        // Kept for the next call rather than derived again.
+        assert!(
+            DERIVED
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|entry| entry.derivation == KeyDerivation::Argon2id(salt))
+        );
+    }
+
+    // The daemon gave up waiting on the Set, but remembers how it went: the
+    // client asks until the write is no longer running.
+    #[test]
+    fn an_ambiguous_write_is_resolved_by_asking_the_daemon() {
+        let _turn = SESSION_TESTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut written = None;
+        let mut asked = 0;
+        let (address, daemon) =
+            session_daemon(KeyDerivation::Sha256, move |request, nonce| match request {
+                PlainRequest::OutcomeQuery { nonce } => {
+                    assert_eq!(Some(*nonce), written);
+                    asked += 1;
+                    let outcome = if asked == 1 {
+                        WriteOutcome::Running
+                    } else {
+                        WriteOutcome::Applied
+                    };
+                    Ack::outcome(outcome, None)
+                }
+                _ => {
+                    written = Some(*nonce);
+                    Ack::status(false, Some("clipboard_outcome_unknown".to_owned()))
+                }
+            });
+
+        let set = ClientRequest::new(
+            PlainRequest::Set {
+                selection: Selection::Primary,
+                text: "slow".to_owned(),
+            },
+            "secret",
+        );
+        let reply = exchange_on_new_connection(&address, &set, true).unwrap();
+        assert_eq!(ack_result(&reply.ack), 1, "{:?}", reply.ack);
+        drop(take_session(&address, &set));
+        let received = daemon.join().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(matches!(
+            received[1..],
+            [
+                PlainRequest::OutcomeQuery { .. },
+                PlainRequest::OutcomeQuery { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn a_session_is_reused_only_for_its_own_daemon_and_token_while_open() {
+        use std::net::TcpListener;
+
+        let _turn = SESSION_TESTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let stream = TcpStream::connect(&address).unwrap();
+        let (daemon_end, _) = listener.accept().unwrap();
+        let keys = derive_auth_keys("secret");
+        store_session(OpenSession {
+            address: address.clone(),
+            secret: Some(Secret::Token(Token("secret".to_owned()))),
+            keys: Some(keys.clone()),
+            server: ServerInfo::current(true),
+            session: Session::new(b"hello", b"client hello"),
+            stream,
+            last_used: Instant::now(),
+        });
+        let reuse = |address: &str, token: &str| {
+            let open = take_session(address, &ClientRequest::new(PlainRequest::Ping, token));
+            let reused = open.is_some();
+            if let Some(open) = open {
+                store_session(open);
+            }
+            reused
+        };
+
+        assert!(reuse(&address, "secret"));
+        assert!(!reuse("127.0.0.1:1", "secret"));
+        // A miss closes the session rather than keep it for later.
+        assert!(!reuse(&address, "secret"));
+
+        let stream = TcpStream::connect(&address).unwrap();
+        let (daemon_end_2, _) = listener.accept().unwrap();
+        for (token, stale) in [("other", false), ("", false), ("secret", true)] {
+            store_session(OpenSession {
+                address: address.clone(),
+                secret: Some(Secret::Token(Token("secret".to_owned()))),
+                keys: Some(keys.clone()),
+                server: ServerInfo::current(true),
+                session: Session::new(b"hello", b"client hello"),
+                stream: stream.try_clone().unwrap(),
+                last_used: if stale {
+                    Instant::now() - SESSION_REUSE_WINDOW
+                } else {
+                    Instant::now()
+                },
+            });
+            assert!(!reuse(&address, token), "{token:?} stale={stale}");
+        }
+
+        // The daemon's close is seen before anything is written.
+        store_session(OpenSession {
+            address: address.clone(),
+            secret: Some(Secret::Token(Token("secret".to_owned()))),
+            keys: Some(keys),
+            server: ServerInfo::current(true),
+            session: Session::new(b"hello", b"client hello"),
+            stream,
+            last_used: Instant::now(),
+        });
+        assert!(reuse(&address, "secret"));
+
+        // Nor is a session opened by an identity reused by a token, or by
+        // another identity.
+        let identity = |host_key| {
+            let keys = KeyPair::parse(&"07".repeat(32)).unwrap();
+            Arc::new(Identity::new(keys, host_key))
+        };
+        let host_key = KeyPair::generate().unwrap().public_key();
+        let mut open = OPEN_SESSION.lock().unwrap().take().unwrap();
+        open.secret = Some(Secret::Identity(identity(host_key)));
+        store_session(open);
+        let by = |identity| {
+            let request = ClientRequest::with_identity(PlainRequest::Ping, identity);
+            let open = take_session(&address, &request);
+            let reused = open.is_some();
+            if let Some(open) = open {
+                store_session(open);
+            }
+            reused
+        };
+        assert!(by(identity(host_key)));
+        assert!(!by(identity(KeyPair::generate().unwrap().public_key())));
+        store_session(OpenSession {
+            address: address.clone(),
+            secret: Some(Secret::Identity(identity(host_key))),
+            keys: Some(derive_auth_keys("secret")),
+            server: ServerInfo::current(true),
+            session: Session::new(b"hello", b"client hello"),
+            stream: daemon_end_2.try_clone().unwrap(),
+            last_used: Instant::now(),
+        });
+        assert!(!reuse(&address, "secret"));
+        drop(daemon_end_2);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!reuse(&address, "secret"));
+        drop(daemon_end);
+    }
+
+    // A daemon that answers one Subscribe with the selection, a heartbeat and a
+    // change, then closes: the subscriber sees the two changes and a clean end.
+    #[test]
+    fn a_subscription_yields_its_changes_and_ends_when_the_daemon_closes() {
+        use protocol::open_request;
+        use protocol::{Event, content_hash, decode_request_payload, encode_event_frame};
+        use std::net::TcpListener;
+
+        let keys = derive_auth_keys("secret");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let daemon_keys = keys.clone();
+        let daemon = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let challenge = [5_u8; protocol::CHALLENGE_BYTES];
+            // A daemon from before forward secrecy and ack codes, whose token
+            // keys seal the subscription as they are.
+            let capabilities = Capabilities::from_bits(
+                Capabilities::ALL.bits()
+                    & !(Capabilities::FORWARD_SECRECY.bits() | Capabilities::ACK_CODE.bits()),
+            );
+            let hello = ServerHello {
+                challenge,
+                info: Some(ServerInfo {
+                    capabilities,
+                    ..ServerInfo::current(true)
+                }),
+                host: None,
+            };
+            describe(&mut stream, &hello);
+            let payload = read_frame(&mut stream).unwrap();
+            let WireRequest::Authenticated {
+                nonce, ciphertext, ..
+            } = decode_request_payload(&payload).unwrap()
+            else {
+                panic!("expected an authenticated request");
+            };
+            let request = open_request(&daemon_keys, &challenge, &nonce, &ciphertext).unwrap();
+            let binding = Binding::Connection(&challenge);
+            let ack = binding
+                .seal_ack(
+                    &daemon_keys,
+                    nonce,
+                    &Ack::status(true, Some("subscribe_ok".to_owned())),
+                    Compression::Off,
+                )
+                .unwrap();
+            stream.write_all(&encode_ack_frame(&ack).unwrap()).unwrap();
+            let change = |text: &str| Change {
+                selection: Selection::Primary,
+                size: text.len() as u64,
+                hash: content_hash(text),
+                text: None,
+            };
+            let changes = [Some(change("one")), None, Some(change("three"))];
+            for (index, change) in changes.into_iter().enumerate() {
+                let event = Event {
+                    index: index as u32,
+                    change,
+                };
+                let sealed = binding.seal_event(&daemon_keys, &nonce, &event).unwrap();
+                stream
+                    .write_all(&encode_event_frame(&sealed).unwrap())
+                    .unwrap();
+            }
+            request
+        });
+
+        let subscribe = ClientRequest::new(
+            PlainRequest::Subscribe {
+                selection: Selection::Primary,
+                text: false,
+            },
+            "secret",
+        );
+        let mut seen = Vec::new();
+        let reply = watch(&address, &subscribe, |change| {
+            seen.push(change);
+            Ok(())
+        })
+        .unwrap();
+        assert!(reply.ack.ok);
+        assert_eq!(
+            seen.iter().map(|change| change.size).collect::<Vec<_>>(),
+            [3, 5]
+        );
+        assert_eq!(seen[1].hash, content_hash("three"));
+        assert_eq!(daemon.join().unwrap(), subscribe.request);
+        assert!(matches!(
+            exchange(&address, &subscribe),
+            Err(ClientError::InvalidPayload)
+        ));
+    }
+
+    fn chunks_of(text: &[u8], size: usize) -> Result<Vec<Chunk>, ClientError> {
+        let mut chunks = TextChunks::new(text);
+        chunks.size = size;
+        let mut read = Vec::new();
+        loop {
+            let chunk = chunks.next_chunk()?;
+            let last = chunk.last;
+            read.push(chunk);
+            if last {
+                return Ok(read);
+            }
+        }
+    }
+
+    // The last chunk is known to be last when it is read, a character cut by
+    // the chunk size moves whole into the next chunk, and bytes that are not
+    // UTF-8 stop the stream before anything unfinished reaches the daemon.
+    #[test]
+    fn text_is_read_a_chunk_at_a_time_on_character_boundaries() {
+        let texts = |chunks: Vec<Chunk>| -> Vec<(u32, bool, String)> {
+            chunks
+                .into_iter()
+                .map(|chunk| (chunk.index, chunk.last, chunk.text))
+                .collect()
+        };
+        assert_eq!(
+            texts(chunks_of(b"", 4).unwrap()),
+            [(0, true, String::new())]
+        );
+        assert_eq!(
+            texts(chunks_of(b"abcd", 4).unwrap()),
+            [(0, true, "abcd".to_owned())]
+        );
+        assert_eq!(
+            texts(chunks_of("ab✅cd".as_bytes(), 4).unwrap()),
+            [
+                (0, false, "ab".to_owned()),
+                (1, false, "✅c".to_owned()),
+                (2, true, "d".to_owned()),
+            ]
+        );
+
+        for invalid in [&b"abc\xffdefgh"[..], b"abcdefg\xe2\x9c"] {
+            let Err(ClientError::Input(error)) = chunks_of(invalid, 4) else {
+                panic!("{invalid:?} was read as text");
+            };
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
     #[test]
     fn resolution_wait_obeys_its_deadline() {
         let (_sender, receiver) = mpsc::sync_channel::<Resolution>(1);